@@ -0,0 +1,206 @@
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use anyhow::{anyhow, Context};
+use wingmate_rs::client::{self, Client};
+use wingmate_rs::protocol::{Operation, Response, Target};
+
+const USAGE: &str = "usage: wingmatectl [--socket PATH] COMMAND
+
+commands:
+  status                     show services, cron entries and timers
+  health                     exit 0 only if wingmate is healthy
+  start|stop|restart SERVICE control a service
+  signal SERVICE SIGNAL      send a signal to a service
+  logs [-f] [-n LINES] SERVICE
+                             show the latest output of a service, kept
+                             with logs = true in [control]
+  cron list                  show cron entries
+  cron run ENTRY             run a cron entry now
+  cron enable|disable ENTRY  resume or pause the schedule of a cron entry
+  reload [SERVICE]           reload the configuration, or run the reload
+                             command of a service
+  shutdown [--exit-code N]   stop all services and exit wingmate";
+
+enum Output {
+    Done,
+    Status,
+    Cron,
+    Lines,
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let socket = match socket(&mut args) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("wingmatectl: {:#}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    if args == ["health"] {
+        return client::check_health(&socket);
+    }
+
+    match run(&socket, args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("wingmatectl: {:#}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn socket(args: &mut Vec<String>) -> Result<PathBuf, anyhow::Error> {
+    if args.first().map(|a| { a.as_str() }) != Some("--socket") {
+        return Ok(client::default_socket());
+    }
+    if args.len() < 2 {
+        return Err(anyhow!("--socket needs a path"));
+    }
+    let socket = PathBuf::from(args.remove(1));
+    args.remove(0);
+    Ok(socket)
+}
+
+fn run(socket: &Path, args: Vec<String>) -> Result<bool, anyhow::Error> {
+    let (operation, output) = parse(&args)?;
+    let follow = matches!(operation, Operation::Logs { follow: true, .. });
+
+    let mut client = Client::connect(socket)
+        .with_context(|| { format!("connecting to {}", socket.to_string_lossy()) })?;
+    client.send(operation)?;
+
+    let response = client.receive().context("invalid response")?
+        .ok_or_else(|| { anyhow!("connection closed by wingmate") })?;
+    if !response.ok {
+        print(&response, &output);
+        eprintln!("wingmatectl: {}", response.error.unwrap_or_default());
+        return Ok(false);
+    }
+
+    print(&response, &output);
+    if follow {
+        while let Some(r) = client.receive().context("invalid response")? {
+            print(&r, &output);
+        }
+    }
+
+    Ok(true)
+}
+
+fn parse(args: &[String]) -> Result<(Operation, Output), anyhow::Error> {
+    let words: Vec<&str> = args.iter().map(|a| { a.as_str() }).collect();
+    let service = |name: &str| { Target::Service(String::from(name)) };
+    let cron = |name: &str| { Target::Cron(String::from(name)) };
+
+    let parsed = match words.as_slice() {
+        ["status"] => (Operation::Status, Output::Status),
+        ["start", name] => (Operation::Start { target: service(name) }, Output::Done),
+        ["stop", name] => (Operation::Stop { target: service(name) }, Output::Done),
+        ["restart", name] => (Operation::Restart { target: service(name) }, Output::Done),
+        ["signal", name, signal] => (Operation::Signal { target: service(name), signal: String::from(*signal) }, Output::Done),
+        ["logs", rest @ ..] => {
+            let mut follow = false;
+            let mut lines: Option<usize> = None;
+            let mut name: Option<&str> = None;
+            let mut iter = rest.iter();
+            while let Some(arg) = iter.next() {
+                match *arg {
+                    "-f" | "--follow" => follow = true,
+                    "-n" | "--lines" => {
+                        let n = iter.next().ok_or_else(|| { anyhow!("{} needs a number", arg) })?;
+                        lines = Some(n.parse().with_context(|| { format!("invalid line count {}", n) })?);
+                    },
+                    a if name.is_none() && !a.starts_with('-') => name = Some(a),
+                    a => return Err(anyhow!("unexpected argument {}\n\n{}", a, USAGE)),
+                }
+            }
+            let name = name.ok_or_else(|| { anyhow!("logs needs a service name\n\n{}", USAGE) })?;
+            (Operation::Logs { target: service(name), follow, lines }, Output::Lines)
+        },
+        ["cron", "list"] => (Operation::Status, Output::Cron),
+        ["cron", "run", name] => (Operation::Run { target: cron(name) }, Output::Done),
+        ["cron", "enable", name] => (Operation::Start { target: cron(name) }, Output::Done),
+        ["cron", "disable", name] => (Operation::Stop { target: cron(name) }, Output::Done),
+        ["reload"] => (Operation::Reload { target: None }, Output::Lines),
+        ["reload", name] => (Operation::Reload { target: Some(service(name)) }, Output::Done),
+        ["shutdown"] => (Operation::Shutdown { exit_code: None }, Output::Done),
+        ["shutdown", "--exit-code", code] => {
+            let code: u8 = code.parse().with_context(|| { format!("invalid exit code {}", code) })?;
+            (Operation::Shutdown { exit_code: Some(code) }, Output::Done)
+        },
+        _ => return Err(anyhow!("{}", USAGE)),
+    };
+
+    Ok(parsed)
+}
+
+fn print(response: &Response, output: &Output) {
+    match output {
+        Output::Done => {},
+        Output::Status => {
+            if let Some(status) = &response.status {
+                println!("{:<24} {:<10} {:<8} {:<10} {:<9} LAST EXIT", "SERVICE", "STATE", "PID", "UPTIME", "RESTARTS");
+                for s in status.services.iter() {
+                    let last_exit = match (s.last_exit_code, s.last_signal) {
+                        (Some(c), _) => format!("code {}", c),
+                        (None, Some(sig)) => format!("signal {}", sig),
+                        _ => String::new(),
+                    };
+                    println!("{:<24} {:<10} {:<8} {:<10} {:<9} {}", s.name, s.state,
+                        s.pid.map(|p| { p.to_string() }).unwrap_or_default(),
+                        s.uptime_secs.map(uptime).unwrap_or_default(),
+                        s.restarts,
+                        last_exit);
+                    if let Some(reason) = &s.last_failure {
+                        println!("  last failure: {}", reason);
+                    }
+                }
+                if !status.cron.is_empty() {
+                    println!();
+                    print(response, &Output::Cron);
+                }
+                if !status.timers.is_empty() {
+                    println!();
+                    println!("{:<24} {:<24} {:<26} {:<26} SCHEDULE", "TIMER", "SERVICE", "NEXT ELAPSE", "LAST TRIGGER");
+                    for t in status.timers.iter() {
+                        println!("{:<24} {:<24} {:<26} {:<26} {}", t.name, t.service,
+                            t.next_elapse.as_deref().unwrap_or("-"), t.last_trigger.as_deref().unwrap_or("-"), t.schedule);
+                    }
+                }
+            }
+        },
+        Output::Cron => {
+            if let Some(status) = &response.status {
+                println!("{:<24} {:<20} {:<8} {:<26} RUNNING", "CRON", "SCHEDULE", "ENABLED", "NEXT RUN");
+                for c in status.cron.iter() {
+                    let running: Vec<String> = c.running.iter().map(|p| { p.to_string() }).collect();
+                    println!("{:<24} {:<20} {:<8} {:<26} {}", c.name, c.schedule, c.enabled,
+                        c.next_run.as_deref().unwrap_or("-"), running.join(","));
+                }
+            }
+        },
+        Output::Lines => {
+            if let Some(lines) = &response.lines {
+                let mut out = std::io::stdout().lock();
+                for l in lines {
+                    let _ = writeln!(out, "{}", l);
+                }
+                let _ = out.flush();
+            }
+        },
+    }
+}
+
+fn uptime(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
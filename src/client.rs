@@ -0,0 +1,76 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use crate::protocol::{self, Operation, Request, Response};
+
+/// Blocking connection to the control socket of a running wingmate.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+}
+
+/// The socket named by `WINGMATE_CONTROL_SOCKET`, or the default one.
+pub fn default_socket() -> PathBuf {
+    env::var_os(protocol::SOCKET_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| { PathBuf::from(protocol::DEFAULT_SOCKET) })
+}
+
+impl Client {
+    pub fn connect(socket: &Path) -> io::Result<Self> {
+        let stream = UnixStream::connect(socket)?;
+        Ok(Self { reader: BufReader::new(stream) })
+    }
+
+    pub fn send(&mut self, operation: Operation) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&Request { version: protocol::VERSION, operation })?;
+        line.push(b'\n');
+        self.reader.get_mut().write_all(&line)
+    }
+
+    /// Reads the next response; `None` once wingmate closed the connection.
+    pub fn receive(&mut self) -> io::Result<Option<Response>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
+
+/// Asks wingmate whether it is healthy and prints what is wrong if not.
+/// Anything but a healthy answer exits with 1, which is what
+/// `HEALTHCHECK` expects of an unhealthy container.
+pub fn check_health(socket: &Path) -> ExitCode {
+    let health = Client::connect(socket)
+        .and_then(|mut c| {
+            c.send(Operation::Health)?;
+            c.receive()
+        })
+        .map_err(|e| { format!("connecting to {}: {}", socket.to_string_lossy(), e) })
+        .and_then(|r| {
+            let r = r.ok_or_else(|| { String::from("connection closed by wingmate") })?;
+            if !r.ok {
+                return Err(r.error.unwrap_or_default());
+            }
+            r.health.ok_or_else(|| { String::from("no health in response") })
+        });
+
+    match health {
+        Ok(h) if h.healthy => {
+            println!("healthy");
+            ExitCode::SUCCESS
+        },
+        Ok(h) => {
+            for p in h.problems.iter() {
+                println!("unhealthy: {}", p);
+            }
+            ExitCode::FAILURE
+        },
+        Err(e) => {
+            eprintln!("health check failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,32 +1,123 @@
+mod args;
 mod daemon;
 mod config;
+mod logger;
 pub(crate) mod error;
 
 use std::env;
 use anyhow::Context;
+use log::{debug, warn};
+use args::{Args, Mode};
 
-const WINGMATE_CONFIG_PATH: &'static str = "WINGMATE_CONFIG_PATH";
+const WINGMATE_CONFIG_PATH: &str = "WINGMATE_CONFIG_PATH";
+const WINGMATE_CONFIG_MERGE: &str = "WINGMATE_CONFIG_MERGE";
+const DEFAULT_CONFIG_PATH: &str = "/etc/wingmate";
 
-pub async fn start() -> Result<(), error::WingmateInitError> {
-    let mut vec_search: Vec<String> = Vec::new();
+/// Runs wingmate with the command line `args`, without the program name,
+/// and returns the exit code.
+pub async fn start(args: Vec<String>) -> Result<u8, error::WingmateInitError> {
+    let args = match Args::parse(args) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("wingmate: {}\n\n{}", e, args::USAGE);
+            return Ok(2);
+        }
+    };
+    match args.mode {
+        Mode::Help => {
+            println!("{}", args::USAGE);
+            return Ok(0);
+        },
+        Mode::Version => {
+            println!("wingmate {}", env!("CARGO_PKG_VERSION"));
+            return Ok(0);
+        },
+        _ => {},
+    }
+
+    logger::init(args.log_level, args.log_format);
 
-    match env::var(WINGMATE_CONFIG_PATH) {
-        Ok(paths) => {
-            for p in paths.split(':') {
-                vec_search.push(String::from(p));
+    let mut vec_search: Vec<String> = args.config.clone();
+
+    if vec_search.is_empty() {
+        match env::var(WINGMATE_CONFIG_PATH) {
+            Ok(paths) => {
+                for p in paths.split(':') {
+                    vec_search.push(String::from(p));
+                }
+            },
+            Err(e) => {
+                if let env::VarError::NotUnicode(_) = e {
+                    return Err(e).context(format!("reading {} env var", WINGMATE_CONFIG_PATH))
+                        .map_err(|e| {error::WingmateInitError::Other { source: e }} );
+                } else {
+                    vec_search.push(String::from(DEFAULT_CONFIG_PATH));
+                }
             }
+        }
+    }
+
+    let mut search_mode = config::SearchMode::FirstMatch;
+    if args.merge {
+        search_mode = config::SearchMode::Merge;
+    } else if let Ok(m) = env::var(WINGMATE_CONFIG_MERGE) {
+        match config::parse_bool(&m) {
+            Some(true) => search_mode = config::SearchMode::Merge,
+            Some(false) => {},
+            None => warn!("ignoring invalid {} \"{}\"", WINGMATE_CONFIG_MERGE, m),
+        }
+    }
+
+    if args.mode == Mode::Check {
+        return Ok(check(vec_search, search_mode, args.inline));
+    }
+
+    let config = config::Config::find(vec_search, search_mode, args.inline)?;
+    logger::configure(&config.log);
+    debug!("loaded config: {:?}", &config);
+
+    match args.mode {
+        Mode::PrintConfig => {
+            print!("{}", config.describe());
+            Ok(0)
+        },
+        _ => daemon::start(config).await,
+    }
+}
+
+/// Loads the configuration the way a run would and reports every problem
+/// found instead of stopping at the first. Only errors fail the check.
+fn check(search_path: Vec<String>, mode: config::SearchMode, inline: config::InlineConfig) -> u8 {
+    let (config, mut problems) = config::Config::check(search_path, mode, inline);
+    let summary = match config {
+        Ok(cfg) => {
+            logger::configure(&cfg.log);
+            problems.extend(daemon::lint(&cfg));
+            Some(format!("{} service(s), {} cron entries, {} timer(s)", cfg.services.len(), cfg.cron.len(), cfg.timers.len()))
         },
         Err(e) => {
-            if let env::VarError::NotUnicode(_) = e {
-                return Err(e).context(format!("reading {} env var", WINGMATE_CONFIG_PATH))
-                    .map_err(|e| {error::WingmateInitError::Other { source: e }} );
-            } else {
-                vec_search.push(String::from("/etc/wingmate"));
-            }
+            problems.push(config::Problem::Error(format!("{:#}", anyhow::Error::new(e))));
+            None
+        },
+    };
+
+    let mut errors = 0;
+    for p in problems.iter() {
+        match p {
+            config::Problem::Error(msg) => {
+                errors += 1;
+                println!("error: {}", msg);
+            },
+            config::Problem::Warning(msg) => println!("warning: {}", msg),
         }
     }
 
-    let config = config::Config::find(vec_search)?;
-    // dbg!(&config);
-    daemon::start(config).await
-}
\ No newline at end of file
+    if errors > 0 {
+        println!("configuration is invalid: {} error(s), {} warning(s)", errors, problems.len() - errors);
+        return 1;
+    }
+    if let Some(s) = summary {
+        println!("configuration is valid: {}", s);
+    }
+    0
+}
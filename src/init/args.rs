@@ -0,0 +1,174 @@
+use std::str::FromStr;
+use log::LevelFilter;
+use crate::init::config::{InlineConfig, LogFormat};
+
+pub const USAGE: &str = "usage: wingmate [OPTIONS] [-- COMMAND [ARG]...]
+       wingmate health
+
+A COMMAND runs as the main process: it receives the signals sent to wingmate
+and wingmate exits with its exit code once it exits. Services and cron entries
+still run next to it.
+
+options:
+  -c, --config PATH            read the configuration from PATH, may be given
+                               more than once (WINGMATE_CONFIG_PATH)
+      --merge                  layer every config path over the ones before
+                               instead of using the first that defines
+                               anything (WINGMATE_CONFIG_MERGE)
+      --log-level LEVEL        off, error, warn, info, debug or trace
+                               (WINGMATE_LOG_LEVEL)
+      --log-format FORMAT      text or json (WINGMATE_LOG_FORMAT)
+      --service '[NAME:] CMD'  run CMD as a service, may be given more than once
+      --cron '[NAME:] SPEC CMD'
+                               run CMD on a cron schedule, may be given more
+                               than once
+      --check                  check the configuration and exit
+      --print-config           print the configuration and exit
+  -V, --version                print the version and exit
+  -h, --help                   print this help and exit";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Run,
+    Check,
+    PrintConfig,
+    Version,
+    Help,
+}
+
+/// The command line of wingmate. What is not given here falls back to the
+/// environment, then to the config file.
+#[derive(Debug)]
+pub struct Args {
+    pub mode: Mode,
+    pub config: Vec<String>,
+    pub merge: bool,
+    pub log_level: Option<LevelFilter>,
+    pub log_format: Option<LogFormat>,
+    pub inline: InlineConfig,
+}
+
+impl Args {
+    /// `--option=value` is the same as `--option value`.
+    pub fn parse(args: Vec<String>) -> Result<Args, String> {
+        let mut parsed = Args {
+            mode: Mode::Run,
+            config: Vec::new(),
+            merge: false,
+            log_level: None,
+            log_format: None,
+            inline: InlineConfig::default(),
+        };
+
+        let mut iter = args.into_iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--" {
+                let command: Vec<String> = iter.by_ref().collect();
+                if command.is_empty() {
+                    return Err(String::from("-- needs a command"));
+                }
+                parsed.inline.main = Some(command);
+                break;
+            }
+
+            let (flag, attached) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (String::from(f), Some(String::from(v))),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || {
+                attached.clone().or_else(|| { iter.next() }).ok_or_else(|| { format!("{} needs a value", flag) })
+            };
+
+            match flag.as_str() {
+                "--merge" | "--check" | "--print-config" | "--version" | "--help" if attached.is_some() => {
+                    return Err(format!("{} takes no value", flag));
+                },
+                "-c" | "--config" => parsed.config.push(value()?),
+                "--merge" => parsed.merge = true,
+                "--log-level" => {
+                    let v = value()?;
+                    parsed.log_level = Some(LevelFilter::from_str(&v).map_err(|_| { format!("invalid log level \"{}\"", v) })?);
+                },
+                "--log-format" => {
+                    let v = value()?;
+                    parsed.log_format = Some(LogFormat::from_str(&v).map_err(|_| { format!("invalid log format \"{}\"", v) })?);
+                },
+                "--service" => parsed.inline.services.push(value()?),
+                "--cron" => parsed.inline.cron.push(value()?),
+                "--check" => parsed.mode = Mode::Check,
+                "--print-config" => parsed.mode = Mode::PrintConfig,
+                "-V" | "--version" => parsed.mode = Mode::Version,
+                "-h" | "--help" => parsed.mode = Mode::Help,
+                _ => return Err(format!("unknown argument \"{}\"", arg)),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| { String::from(*a) }).collect())
+    }
+
+    #[test]
+    fn defaults() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.mode, Mode::Run);
+        assert!(args.config.is_empty());
+        assert!(!args.merge);
+        assert_eq!(args.log_level, None);
+        assert_eq!(args.inline.main, None);
+    }
+
+    #[test]
+    fn options_and_attached_values() {
+        let args = parse(&["-c", "/etc/a", "--config=/etc/b", "--merge", "--log-level", "debug", "--log-format=json",
+            "--service", "web: serve --port=80", "--cron=*/5 * * * * backup", "--check"]).unwrap();
+        assert_eq!(args.config, vec!["/etc/a", "/etc/b"]);
+        assert!(args.merge);
+        assert_eq!(args.log_level, Some(LevelFilter::Debug));
+        assert!(matches!(args.log_format, Some(LogFormat::Json)));
+        assert_eq!(args.inline.services, vec!["web: serve --port=80"]);
+        assert_eq!(args.inline.cron, vec!["*/5 * * * * backup"]);
+        assert_eq!(args.mode, Mode::Check);
+    }
+
+    #[test]
+    fn value_may_look_like_an_option() {
+        let args = parse(&["--service", "--check", "-c", "--"]).unwrap();
+        assert_eq!(args.inline.services, vec!["--check"]);
+        assert_eq!(args.config, vec!["--"]);
+        assert_eq!(args.mode, Mode::Run);
+    }
+
+    #[test]
+    fn main_command_after_double_dash() {
+        let args = parse(&["--merge", "--", "app", "--help", "--", "-c"]).unwrap();
+        assert!(args.merge);
+        assert_eq!(args.mode, Mode::Run);
+        assert_eq!(args.inline.main, Some(vec![String::from("app"), String::from("--help"), String::from("--"), String::from("-c")]));
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        for args in [
+            vec!["--"],
+            vec!["-c"],
+            vec!["--config"],
+            vec!["--log-level", "loud"],
+            vec!["--log-format=xml"],
+            vec!["--merge=no"],
+            vec!["--check=yes"],
+            vec!["--bogus"],
+            vec!["-x"],
+            vec!["app"],
+        ] {
+            assert!(parse(&args).is_err(), "{:?}", args);
+        }
+    }
+}
@@ -1,215 +1,767 @@
+mod environment;
+mod file;
+mod inline;
+mod procfile;
+mod supervisord;
+mod systemd;
+
+use std::fmt::{self, Write as _};
 use std::fs;
 use std::env;
-use std::path::PathBuf;
-use std::io::{BufReader, BufRead};
+use std::path::{Path, PathBuf};
 use crate::init::error as wingmate_error;
+use crate::protocol;
 use anyhow::anyhow;
 use nix::unistd::{access, AccessFlags};
 use lazy_static::lazy_static;
 use regex::Regex;
 use anyhow::Context;
+use log::{debug, warn, LevelFilter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use nix::sys::signal::Signal;
+use time::{Date, Duration as TimeDuration, Month, OffsetDateTime, Time, UtcOffset, Weekday};
 
 pub const MAX_TERM_WAIT_TIME_SECS: u64 = 5;
+pub const DEFAULT_START_SECS: u64 = 1;
+const NEXT_RUN_SEARCH_DAYS: i64 = 4 * 366;
 
-const CRON_REGEX_STR: &'static str = r"^\s*(?P<minute>\S+)\s+(?P<hour>\S+)\s+(?P<dom>\S+)\s+(?P<month>\S+)\s+(?P<dow>\S+)\s+(?P<command>\S.*\S)\s*$";
-const MINUTE: &'static str = "minute";
-const HOUR: &'static str = "hour";
-const DAY_OF_MONTH_ABBRV: &'static str = "dom";
-const DAY_OF_MONTH: &'static str = "day of month";
-const MONTH: &'static str = "month";
-const DAY_OF_WEEK_ABBRV: &'static str = "dow";
-const DAY_OF_WEEK: &'static str = "day of week";
-const COMMAND: &'static str = "command";
-const WINGMATE_SHELL_ENV: &'static str = "WINGMATE_SHELL";
+const CRON_REGEX_STR: &str = r"^\s*(?P<minute>\S+)\s+(?P<hour>\S+)\s+(?P<dom>\S+)\s+(?P<month>\S+)\s+(?P<dow>\S+)\s+(?P<command>\S.*\S)\s*$";
+const MINUTE: &str = "minute";
+const HOUR: &str = "hour";
+const DAY_OF_MONTH_ABBRV: &str = "dom";
+const DAY_OF_MONTH: &str = "day of month";
+const MONTH: &str = "month";
+const DAY_OF_WEEK_ABBRV: &str = "dow";
+const DAY_OF_WEEK: &str = "day of week";
+const COMMAND: &str = "command";
+const WINGMATE_SHELL_ENV: &str = "WINGMATE_SHELL";
+const CONFIG_FILE_NAME: &str = "wingmate.toml";
+const SUPERVISORD_FILE_NAME: &str = "supervisord.conf";
+const SUPERVISORD_EXTENSION: &str = "ini";
+const PROCFILE_NAME: &str = "Procfile";
+const UNITS_DIRECTORY: &str = "units";
+const CRONTAB_FILE_NAME: &str = "crontab";
+const CRONTAB_ENTRY_PREFIX: &str = "crontab:";
+const CRONTAB_NAME_COMMENT: &str = "name:";
+const DISABLED_SUFFIX: &str = ".disabled";
+const DOWN_SUFFIX: &str = ".down";
+const RUN_SCRIPT: &str = "run";
+const FINISH_SCRIPT: &str = "finish";
+const DOWN_MARKER: &str = "down";
+const LOG_DIRECTORY: &str = "log";
+const ENV_DIRECTORY: &str = "env";
+const BACKUP_SUFFIXES: [&str; 11] = ["~", ".bak", ".old", ".orig", ".rej", ".swp", ".swo", ".tmp", ".dpkg-old", ".dpkg-dist", ".rpmsave"];
 
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_LOG_MAX_FILES: u32 = 5;
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+pub const DEFAULT_FORWARD_QUEUE_SIZE: usize = 1024;
+pub const DEFAULT_FORWARD_FACILITY: u8 = 3; // daemon
+pub const DEFAULT_HEALTH_FAILED_RUNS: usize = 1;
+pub const DEFAULT_RELOAD_DEBOUNCE_SECS: u64 = 2;
 
-#[derive(Debug)]
+/// The entries of a config directory that make up the configuration.
+pub const CONFIG_ENTRIES: [&str; 7] = ["services", CRONTAB_FILE_NAME, PROCFILE_NAME, ".env", UNITS_DIRECTORY, SUPERVISORD_FILE_NAME, CONFIG_FILE_NAME];
+
+
+#[derive(Debug, PartialEq)]
 pub enum Command {
     ShellPrefixed(String),
-    Direct(String)
+    Direct(String),
+    Inline(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CronTimeFieldSpec {
     Any,
     Exact(u8),
     MultiOccurrence(Vec<u8>)
 }
 
+/// A systemd calendar expression such as `Mon..Fri *-*-* 09:00` or `hourly`,
+/// the schedule of `OnCalendar=`. Unlike in a crontab, the weekday and the
+/// day of the month must both match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarSpec {
+    /// 0 is Sunday, as in a crontab.
+    pub weekday: CronTimeFieldSpec,
+    /// Any year when empty.
+    pub years: Vec<i32>,
+    pub month: CronTimeFieldSpec,
+    pub day: CronTimeFieldSpec,
+    pub hour: CronTimeFieldSpec,
+    pub minute: CronTimeFieldSpec,
+    pub second: CronTimeFieldSpec,
+    /// Matched against UTC rather than the time zone of cron entries.
+    pub utc: bool,
+    pub expression: String,
+}
+
+/// Where the output of a service or a cron command goes. The default inherits
+/// wingmate's own stdout and stderr without touching the stream at all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub stdout: bool,
+    pub file: Option<LogFile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub max_files: u32,
+    pub compress: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Wingmate's own diagnostics, as opposed to the output of the services.
+#[derive(Debug, Clone, Default)]
+pub struct LogConfig {
+    pub level: Option<LevelFilter>,
+    pub format: Option<LogFormat>,
+}
+
+/// The local syslog socket wingmate may listen on in place of a syslog daemon.
+#[derive(Debug, Clone)]
+pub struct SyslogConfig {
+    pub listen: bool,
+    pub socket: PathBuf,
+    pub forward: Option<SyslogForward>,
+}
+
+/// A remote collector receiving RFC 5424 messages.
+#[derive(Debug, Clone)]
+pub struct SyslogForward {
+    pub address: ForwardAddress,
+    pub queue_size: usize,
+    pub facility: u8,
+    pub hostname: Option<String>,
+    pub services: bool,
+    pub events: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForwardAddress {
+    Udp(String),
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+/// Application log files copied to wingmate's stdout. Only the file name
+/// part of the configured path may contain `*` and `?` wildcards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tail {
+    pub name: String,
+    pub directory: PathBuf,
+    pub pattern: String,
+    pub prefix: String,
+    pub from_start: bool,
+}
+
+/// Status snapshots written when `signal` is received and, with `interval`
+/// set, refreshed periodically. Without a file the snapshot goes to stdout.
+#[derive(Debug, Clone)]
+pub struct StatusConfig {
+    pub signal: Signal,
+    pub file: Option<PathBuf>,
+    pub format: LogFormat,
+    pub interval: Option<Duration>,
+}
+
+/// A critical cron entry is unhealthy once its last `failed_runs` runs
+/// have all failed.
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    pub failed_runs: usize,
+}
+
+/// Reloading on changes to the config search path. Changes are applied once
+/// nothing happened for `debounce`.
+#[derive(Debug, Clone)]
+pub struct ReloadConfig {
+    pub watch: bool,
+    pub debounce: Duration,
+}
+
+/// Something wrong with the configuration, as reported by `--check`. Errors
+/// keep wingmate from starting or make it fail at run time; warnings point
+/// at likely mistakes.
+#[derive(Debug, Clone)]
+pub enum Problem {
+    Error(String),
+    Warning(String),
+}
+
+/// Records the error of `result` and lets loading go on when `problems`
+/// collects them, otherwise hands it back.
+fn collect(problems: &mut Option<&mut Vec<Problem>>, result: Result<(), wingmate_error::WingmateInitError>)
+    -> Result<(), wingmate_error::WingmateInitError> {
+    match (result, problems) {
+        (Err(e), Some(p)) => {
+            p.push(Problem::Error(format!("{:#}", anyhow::Error::new(e))));
+            Ok(())
+        },
+        (result, _) => result,
+    }
+}
+
+/// Services and cron entries given on the command line, as `[name: ]command`
+/// and `[name: ]schedule command`.
+#[derive(Debug, Clone, Default)]
+pub struct InlineConfig {
+    pub services: Vec<String>,
+    pub cron: Vec<String>,
+    /// The command after `--`, run as the main process.
+    pub main: Option<Vec<String>>,
+}
+
+/// How the entries of the config search path combine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    /// The first entry defining services or cron entries is used alone.
+    FirstMatch,
+    /// Every entry is read in order, each one layered over the ones before:
+    /// same-named services and cron entries are replaced, and inherited ones
+    /// can be disabled. A crontab replaces the lines of earlier crontabs as a
+    /// whole.
+    Merge,
+}
+
+/// Prometheus metrics served over plain HTTP.
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub listen: MetricsAddress,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricsAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+/// The unix socket accepting control requests, see the `protocol` module.
+#[derive(Debug, Clone)]
+pub struct ControlConfig {
+    pub enabled: bool,
+    pub socket: PathBuf,
+    /// Keeps the recent output of every service and cron entry for
+    /// `wingmatectl logs`. This pipes all output through wingmate instead of
+    /// handing the children our stdout and stderr, so they no longer see a
+    /// terminal, which usually turns their line buffering off, and every byte
+    /// they write costs a copy. Off by default.
+    pub logs: bool,
+}
+
+/// When a service is started again after its process exited by itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Restart {
+    Always,
+    OnFailure,
+    Never,
+}
+
+/// When a service counts as started, after systemd's `Type=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceType {
+    /// Once the process stayed up for `start_secs`.
+    Simple,
+    /// Once the process exited successfully.
+    Oneshot,
+    /// Once the process sent `READY=1` to `NOTIFY_SOCKET`.
+    Notify,
+    /// Once the process exited successfully, leaving behind the daemon whose
+    /// pid is in `pid_file`.
+    Forking,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Service {
+    pub name: String,
+    pub command: Command,
+    pub output: Output,
+    /// Whether the health check fails while the service is down.
+    pub required: bool,
+    /// A service that is not enabled is known but only started on request.
+    pub enabled: bool,
+    /// Gets the terminal wingmate was started on, if there is one.
+    pub foreground: bool,
+    /// When the file in `services/` last changed, so a reload can tell an
+    /// edited script from an unchanged one.
+    pub modified: Option<SystemTime>,
+    /// Working directory; the service directory for runit-style services.
+    pub directory: Option<PathBuf>,
+    /// Variables to set, or to remove when `None`.
+    pub environment: Vec<(String, Option<String>)>,
+    /// Runs after every exit with the exit code and the signal number.
+    pub finish: Option<Command>,
+    /// Reads the stdout of the service from a pipe kept across restarts.
+    pub log: Option<Command>,
+    /// Run in order before each start; a failure fails the start unless
+    /// its flag says to ignore it.
+    pub start_pre: Vec<(Command, bool)>,
+    /// Runs on a stop request before `stop_signal` is sent.
+    pub stop: Option<Command>,
+    /// Runs on a reload request for the service.
+    pub reload: Option<Command>,
+    /// User name or uid the service runs as.
+    pub user: Option<String>,
+    /// Group name or gid replacing the primary group of `user`.
+    pub group: Option<String>,
+    pub service_type: ServiceType,
+    /// Where a forking service writes the pid of its daemon.
+    pub pid_file: Option<PathBuf>,
+    /// Services started before this one. Those in `requires` must start
+    /// successfully, or this one is not started at all.
+    pub after: Vec<String>,
+    pub requires: Vec<String>,
+    pub restart: Restart,
+    /// Pause before starting the service again after it exited.
+    pub restart_delay: Option<Duration>,
+    /// How long the process must stay up to count as started.
+    pub start_secs: Duration,
+    /// Failed starts in a row before giving up, unlimited when `None`.
+    pub start_retries: Option<u32>,
+    pub stop_signal: Signal,
+    /// How long to wait after `stop_signal` before killing the process.
+    pub stop_timeout: Duration,
+}
+
 #[derive(Debug)]
 pub struct Crontab {
+    pub name: String,
     pub minute: CronTimeFieldSpec,
     pub hour: CronTimeFieldSpec,
     pub day_of_month: CronTimeFieldSpec,
     pub month: CronTimeFieldSpec,
     pub day_of_week: CronTimeFieldSpec,
+    pub schedule: String,
     pub command: String,
+    pub output: Output,
+    /// Whether failed runs make the health check fail.
+    pub critical: bool,
+}
+
+/// Starts the service `unit` on a schedule, after a systemd timer unit.
+/// `on_boot` counts from the start of wingmate and `on_unit_active` from the
+/// previous time the timer started the service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timer {
+    pub name: String,
+    pub unit: String,
+    pub calendar: Vec<CalendarSpec>,
+    pub on_boot: Option<Duration>,
+    pub on_unit_active: Option<Duration>,
+    /// Each elapse is delayed by a random time up to this.
+    pub randomized_delay: Duration,
+    /// Whether a calendar elapse missed while wingmate was not running is
+    /// caught up on at start.
+    pub persistent: bool,
 }
 
 #[derive(Debug)]
 pub struct Config {
-    pub services: Vec<Command>,
+    pub services: Vec<Service>,
     pub cron: Vec<Crontab>,
+    pub timers: Vec<Timer>,
+    pub tails: Vec<Tail>,
+    pub log: LogConfig,
+    pub syslog: SyslogConfig,
+    pub control: ControlConfig,
+    pub status: StatusConfig,
+    pub metrics: Option<MetricsConfig>,
+    pub health: HealthConfig,
+    pub reload: ReloadConfig,
+    search_path: Vec<String>,
+    search_mode: SearchMode,
+    inline: InlineConfig,
     shell_path: Option<String>,
 }
 
 impl Config {
-    pub fn find(search_path: Vec<String>) -> Result<Config, wingmate_error::WingmateInitError> {
+    /// Reads the first entry of `search_path` that defines services or cron
+    /// entries, or all of them with `SearchMode::Merge`, then adds those from
+    /// the environment and from `inline`.
+    pub fn find(search_path: Vec<String>, mode: SearchMode, inline: InlineConfig) -> Result<Config, wingmate_error::WingmateInitError> {
+        Self::load(search_path, mode, inline, None)
+    }
+
+    /// Reads the config like `find`, except that a file, unit, variable or
+    /// crontab line that does not load is collected and skipped instead of
+    /// stopping at the first one.
+    pub fn check(search_path: Vec<String>, mode: SearchMode, inline: InlineConfig)
+        -> (Result<Config, wingmate_error::WingmateInitError>, Vec<Problem>) {
+        let mut problems: Vec<Problem> = Vec::new();
+        let config = Self::load(search_path, mode, inline, Some(&mut problems));
+        (config, problems)
+    }
+
+    /// A config without any service or cron entry and default settings.
+    fn new(search_path: Vec<String>, mode: SearchMode, inline: InlineConfig) -> Config {
+        Config {
+            services: Vec::new(),
+            cron: Vec::new(),
+            timers: Vec::new(),
+            tails: Vec::new(),
+            log: LogConfig::default(),
+            syslog: SyslogConfig::default(),
+            control: ControlConfig::default(),
+            status: StatusConfig::default(),
+            metrics: None,
+            health: HealthConfig { failed_runs: DEFAULT_HEALTH_FAILED_RUNS },
+            reload: ReloadConfig::default(),
+            search_path,
+            search_mode: mode,
+            inline,
+            shell_path: None,
+        }
+    }
+
+    fn load(search_path: Vec<String>, mode: SearchMode, inline: InlineConfig, mut problems: Option<&mut Vec<Problem>>)
+        -> Result<Config, wingmate_error::WingmateInitError> {
         if search_path.is_empty() {
-            return Err(wingmate_error::WingmateInitError::InvalidConfigSearchPath.into());
+            return Err(wingmate_error::WingmateInitError::InvalidConfigSearchPath);
         }
 
-        let mut svc_commands: Vec<Command> = Vec::new();
-        let mut cron : Vec<Crontab> = Vec::new();
+        let mut config = Self::new(search_path.clone(), mode, inline.clone());
         'search: for p in search_path {
             let mut buf = PathBuf::new();
             buf.push(p);
             if let Ok(m) = fs::metadata(buf.as_path()) {
                 if m.is_dir() {
-                    let svc = buf.join("services");
-                    if let Ok(svc_iter) = fs::read_dir(svc.as_path()) {
-                        for entry in svc_iter {
-                            if let Ok(dirent) = entry {
-                                let ep = dirent.path();
-                                if let Ok(_) = access(ep.as_path(), AccessFlags::X_OK) {
-                                    // execute directly
-                                    svc_commands.push(Command::Direct(String::from(ep.to_string_lossy())));
-                                } else {
-                                    // call with shell
-                                    svc_commands.push(Command::ShellPrefixed(String::from(ep.to_string_lossy())));
-                                }
-                            }
-                        }
+                    Self::read_services(&buf, &mut config.services);
+                    if buf.join(CRONTAB_FILE_NAME).is_file() {
+                        let cron = Self::read_crontab(&buf, problems.as_deref_mut()).map_err(|e| { wingmate_error::WingmateInitError::Cron { source: e }})?;
+                        // a later crontab replaces all the lines of the one before, so a
+                        // line left out of it is gone
+                        config.cron.retain(|c| { !c.name.starts_with(CRONTAB_ENTRY_PREFIX) });
+                        config.cron.extend(cron);
                     }
 
-                    cron = Self::read_crontab(&mut buf).map_err(|e| { wingmate_error::WingmateInitError::Cron { source: e }})?;
+                    let units = buf.join(UNITS_DIRECTORY);
+                    if units.is_dir() {
+                        systemd::apply(&units, &mut config, problems.as_deref_mut())?;
+                    }
+
+                    let procfile = buf.join(PROCFILE_NAME);
+                    if procfile.is_file() {
+                        collect(&mut problems, procfile::apply(&procfile, &mut config))?;
+                    }
+
+                    let supervisord_file = buf.join(SUPERVISORD_FILE_NAME);
+                    if supervisord_file.is_file() {
+                        collect(&mut problems, supervisord::apply(&supervisord_file, &mut config))?;
+                    }
 
-                    //TODO: need to include cron in the condition
-                    if !svc_commands.is_empty() || !cron.is_empty() {
-                        break 'search;
+                    let config_file = buf.join(CONFIG_FILE_NAME);
+                    if config_file.is_file() {
+                        collect(&mut problems, file::apply(&config_file, &mut config))?;
                     }
+                } else if buf.file_name().is_some_and(|n| { n == PROCFILE_NAME }) {
+                    collect(&mut problems, procfile::apply(&buf, &mut config))?;
+                } else if buf.file_name().is_some_and(|n| { n == SUPERVISORD_FILE_NAME })
+                    || buf.extension().is_some_and(|e| { e == SUPERVISORD_EXTENSION }) {
+                    collect(&mut problems, supervisord::apply(&buf, &mut config))?;
                 } else {
-                    // reserve for future use; when we have a centralized config file
+                    // a plain file in the search path is a centralized config file
+                    collect(&mut problems, file::apply(&buf, &mut config))?;
+                }
+
+                if mode == SearchMode::FirstMatch && (!config.services.is_empty() || !config.cron.is_empty()) {
+                    break 'search;
                 }
             }
         }
 
-        if svc_commands.is_empty() && cron.is_empty() {
-            return Err(wingmate_error::WingmateInitError::NoServiceOrCron.into());
+        let vars = environment::vars(env::vars_os(), problems.as_deref_mut())?;
+        environment::apply_definitions(&mut config, &vars, problems.as_deref_mut())?;
+        inline::apply(&inline, &mut config, problems.as_deref_mut())?;
+        environment::apply_options(&mut config, &vars, problems.as_deref_mut())?;
+
+        if config.services.is_empty() && config.cron.is_empty() && config.inline.main.is_none() {
+            return Err(wingmate_error::WingmateInitError::NoServiceOrCron);
         }
 
-        let mut config = Config { 
-            services: svc_commands,
-            cron,
-            shell_path: None,
-        };
-        config.find_shell().map_err(|e| { wingmate_error::WingmateInitError::FindShell { source: e } })?;
+        // a service started by a timer does not start by itself
+        for t in config.timers.iter() {
+            match config.services.iter_mut().find(|s| { s.name == t.unit }) {
+                Some(svc) => svc.enabled = false,
+                None => collect(&mut problems, Err(wingmate_error::WingmateInitError::TimerUnit { timer: t.name.clone(), unit: t.unit.clone() }))?,
+            }
+        }
+
+        let mut foreground: Vec<&str> = config.services.iter().filter(|s| { s.foreground }).map(|s| { s.name.as_str() }).collect();
+        if config.inline.main.is_some() {
+            foreground.push("the main command");
+        }
+        if foreground.len() > 1 {
+            collect(&mut problems, Err(wingmate_error::WingmateInitError::Foreground(foreground.join(", "))))?;
+        }
+
+        if let Some(socket) = env::var_os(protocol::SOCKET_ENV) {
+            config.control.socket = PathBuf::from(socket);
+        }
+
+        let shell = config.find_shell().map_err(|e| { wingmate_error::WingmateInitError::FindShell { source: e } });
+        collect(&mut problems, shell)?;
 
         Ok(config)
     }
 
-    fn read_crontab(path: &mut PathBuf) -> Result<Vec<Crontab>, wingmate_error::CronParseError> {
-        lazy_static! {
-            static ref CRON_REGEX: Regex = Regex::new(CRON_REGEX_STR).unwrap();
-        }
+    /// Every entry of `services` is a service, except for dotfiles, editor
+    /// backups and READMEs. As with runit, a service is down when its file
+    /// has a `.disabled` suffix or a `<name>.down` file sits next to it.
+    /// A directory is a runit service directory, see `read_service_dir`.
+    /// Adds the services of `path` to `inherited`, replacing same-named ones.
+    /// A `.down` file also keeps an inherited service down.
+    fn read_services(path: &Path, inherited: &mut Vec<Service>) {
+        let mut services: Vec<Service> = Vec::new();
+        let mut down: Vec<String> = Vec::new();
 
-        let cron_path = path.join("crontab");
-        let mut ret_vec: Vec<Crontab> = Vec::new();
+        let svc = path.join("services");
+        if let Ok(svc_iter) = fs::read_dir(svc.as_path()) {
+            for dirent in svc_iter.flatten() {
+                let file_name = String::from(dirent.file_name().to_string_lossy());
+                if is_ignored(&file_name) {
+                    debug!("ignoring {} in {}", file_name, svc.to_string_lossy());
+                    continue;
+                }
+                if let Some(name) = file_name.strip_suffix(DOWN_SUFFIX) {
+                    down.push(String::from(name));
+                    continue;
+                }
+                let (name, enabled) = match file_name.strip_suffix(DISABLED_SUFFIX) {
+                    Some(name) => (String::from(name), false),
+                    None => (file_name, true),
+                };
 
-        if let Ok(f) = fs::File::open(cron_path.as_path()) {
-            for line in BufReader::new(f).lines() {
-                if let Ok(l) = line {
-                    let cap = CRON_REGEX.captures(&l).ok_or::<wingmate_error::CronParseError>(
-                        wingmate_error::CronParseError::InvalidSyntax(String::from(&l))
-                    )?;
-                    
-                    let mut match_str = cap.name(MINUTE).ok_or::<wingmate_error::CronParseError>(
-                        wingmate_error::CronParseError::FieldMatch { cron_line: String::from(&l), field_name: String::from(MINUTE) }
-                    )?;
-                    let minute = Self::to_cron_time_field_spec(&match_str, 60u8).map_err(|e| { 
-                        wingmate_error::CronParseError::Parse {
-                            source: e,
-                            cron_line: String::from(&l),
-                            matched: String::from(match_str.as_str()),
-                            field_name: String::from(MINUTE)
-                        }
-                    })?;
-    
-                    match_str = cap.name(HOUR).ok_or::<wingmate_error::CronParseError>(
-                        wingmate_error::CronParseError::FieldMatch { cron_line: String::from(&l), field_name: String::from(HOUR) }
-                    )?;
-                    let hour = Self::to_cron_time_field_spec(&match_str, 24u8).map_err(|e| { 
-                        wingmate_error::CronParseError::Parse {
-                            source: e,
-                            cron_line: String::from(&l),
-                            matched: String::from(match_str.as_str()),
-                            field_name: String::from(HOUR)
-                        }
-                    })?;
-    
-                    match_str = cap.name(DAY_OF_MONTH_ABBRV).ok_or::<wingmate_error::CronParseError>(
-                        wingmate_error::CronParseError::FieldMatch { cron_line: String::from(&l), field_name: String::from(DAY_OF_MONTH) }
-                    )?;
-                    let dom = Self::to_cron_time_field_spec(&match_str, 31u8).map_err(|e| {
-                        wingmate_error::CronParseError::Parse {
-                            source: e,
-                            cron_line: String::from(&l),
-                            matched: String::from(match_str.as_str()),
-                            field_name: String::from(DAY_OF_MONTH)
+                let ep = dirent.path();
+                let mut service = if ep.is_dir() {
+                    match Self::read_service_dir(name, &ep) {
+                        Some(s) => s,
+                        None => {
+                            warn!("{} has no {} script, skipping", ep.to_string_lossy(), RUN_SCRIPT);
+                            continue;
                         }
-                    })?;
-    
-                    match_str = cap.name(MONTH).ok_or::<wingmate_error::CronParseError>(
-                        wingmate_error::CronParseError::FieldMatch { cron_line: String::from(&l), field_name: String::from(MONTH) }
-                    )?;
-                    let month = Self::to_cron_time_field_spec(&match_str, 12u8).map_err(|e| {
-                        wingmate_error::CronParseError::Parse {
-                            source: e,
-                            cron_line: String::from(&l),
-                            matched: String::from(match_str.as_str()),
-                            field_name: String::from(MONTH)
-                        }
-                    })?;
-    
-                    match_str = cap.name(DAY_OF_WEEK_ABBRV).ok_or::<wingmate_error::CronParseError>(
-                        wingmate_error::CronParseError::FieldMatch { cron_line: String::from(&l), field_name: String::from(DAY_OF_WEEK) }
-                    )?;
-                    let dow = Self::to_cron_time_field_spec(&match_str, 7u8).map_err(|e| {
-                        wingmate_error::CronParseError::Parse {
-                            source: e,
-                            cron_line: String::from(&l),
-                            matched: String::from(match_str.as_str()),
-                            field_name: String::from(DAY_OF_WEEK)
+                    }
+                } else {
+                    let mut service = Service::new(name, script_command(&ep));
+                    service.modified = modified(&ep);
+                    service
+                };
+                service.enabled = service.enabled && enabled;
+
+                // with both `x` and `x.disabled` around, `x` wins
+                match services.iter().position(|s| { s.name == service.name }) {
+                    Some(i) => {
+                        warn!("service {} exists both enabled and disabled, using the enabled one", service.name);
+                        if service.enabled {
+                            services[i] = service;
                         }
-                    })?;
-    
-                    match_str = cap.name(COMMAND).ok_or::<wingmate_error::CronParseError>(
-                        wingmate_error::CronParseError::FieldMatch { cron_line: String::from(&l), field_name: String::from(COMMAND) }
-                    )?;
-    
-                    ret_vec.push(Crontab {
-                        minute,
-                        hour,
-                        day_of_month: dom,
-                        month,
-                        day_of_week: dow,
-                        command: String::from(match_str.as_str())
-                    })
+                    },
+                    None => services.push(service),
+                }
+            }
+        }
+
+        for service in services {
+            match inherited.iter().position(|s| { s.name == service.name }) {
+                Some(i) => inherited[i] = service,
+                None => inherited.push(service),
+            }
+        }
+        for s in inherited.iter_mut() {
+            if down.contains(&s.name) {
+                s.enabled = false;
+            }
+        }
+    }
+
+    /// A runit or s6 style service directory: `run` is the service, `finish`
+    /// runs after each exit, `log/run` gets the service's stdout, `env` holds
+    /// one variable per file and a `down` file keeps the service down.
+    fn read_service_dir(name: String, dir: &Path) -> Option<Service> {
+        let run = dir.join(RUN_SCRIPT);
+        if !run.is_file() {
+            return None;
+        }
+
+        let mut service = Service::new(name, script_command(&run));
+        service.directory = Some(dir.to_path_buf());
+        service.enabled = !dir.join(DOWN_MARKER).exists();
+        service.environment = read_env_dir(&dir.join(ENV_DIRECTORY));
+
+        let finish = dir.join(FINISH_SCRIPT);
+        if finish.is_file() {
+            service.finish = Some(script_command(&finish));
+        }
+        let log = dir.join(LOG_DIRECTORY).join(RUN_SCRIPT);
+        if log.is_file() {
+            service.log = Some(script_command(&log));
+        }
+
+        // any of the scripts changing means the service changed
+        service.modified = [&run, &finish, &log].iter().filter_map(|p| { modified(p) }).max();
+        Some(service)
+    }
+
+    /// A line that does not parse fails the whole crontab, unless `problems`
+    /// collects it.
+    fn read_crontab(path: &Path, problems: Option<&mut Vec<Problem>>) -> Result<Vec<Crontab>, wingmate_error::CronParseError> {
+        let cron_path = path.join(CRONTAB_FILE_NAME);
+        match fs::read_to_string(cron_path.as_path()) {
+            Ok(content) => Self::parse_crontab(&cron_path.to_string_lossy(), &content, problems),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// Blank lines and lines starting with `#` are skipped. A line is named
+    /// after the `# name:` comment right before it, or else after its command,
+    /// so the name stays the same when other lines are added or removed.
+    fn parse_crontab(source: &str, content: &str, mut problems: Option<&mut Vec<Problem>>) -> Result<Vec<Crontab>, wingmate_error::CronParseError> {
+        let mut ret_vec: Vec<Crontab> = Vec::new();
+        let mut name: Option<&str> = None;
+
+        for (idx, l) in content.lines().enumerate() {
+            let trimmed = l.trim();
+            if let Some(comment) = trimmed.strip_prefix('#') {
+                if let Some(n) = comment.trim_start().strip_prefix(CRONTAB_NAME_COMMENT) {
+                    name = Some(n.trim()).filter(|n| { !n.is_empty() });
                 }
+                continue;
+            }
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            let mut cron = match (Self::parse_cron_line(l), problems.as_deref_mut()) {
+                (Ok(c), _) => c,
+                (Err(e), Some(p)) => {
+                    p.push(Problem::Error(format!("{} line {}: {:#}", source, idx + 1, anyhow::Error::new(e))));
+                    name = None;
+                    continue;
+                },
+                (Err(e), None) => return Err(e),
+            };
+            let base = format!("{}{}", CRONTAB_ENTRY_PREFIX, name.take().unwrap_or(cron.command.as_str()));
+            // the same command twice keeps its order among the repeats
+            cron.name = base.clone();
+            let mut n = 1;
+            while ret_vec.iter().any(|c| { c.name == cron.name }) {
+                n += 1;
+                cron.name = format!("{}#{}", base, n);
             }
+            ret_vec.push(cron);
         }
 
         Ok(ret_vec)
     }
 
-    fn to_cron_time_field_spec(match_str: &regex::Match, max: u8) -> Result<CronTimeFieldSpec, anyhow::Error> {
+    fn parse_cron_line(l: &str) -> Result<Crontab, wingmate_error::CronParseError> {
+        lazy_static! {
+            static ref CRON_REGEX: Regex = Regex::new(CRON_REGEX_STR).unwrap();
+        }
+
+        let cap = CRON_REGEX.captures(l).ok_or::<wingmate_error::CronParseError>(
+            wingmate_error::CronParseError::InvalidSyntax(String::from(l))
+        )?;
+
+        let mut match_str = cap.name(MINUTE).ok_or::<wingmate_error::CronParseError>(
+            wingmate_error::CronParseError::FieldMatch { cron_line: String::from(l), field_name: String::from(MINUTE) }
+        )?;
+        let minute = Self::to_cron_time_field_spec(&match_str, 0u8, 60u8).map_err(|e| {
+            wingmate_error::CronParseError::Parse {
+                source: e,
+                cron_line: String::from(l),
+                matched: String::from(match_str.as_str()),
+                field_name: String::from(MINUTE)
+            }
+        })?;
+
+        match_str = cap.name(HOUR).ok_or::<wingmate_error::CronParseError>(
+            wingmate_error::CronParseError::FieldMatch { cron_line: String::from(l), field_name: String::from(HOUR) }
+        )?;
+        let hour = Self::to_cron_time_field_spec(&match_str, 0u8, 24u8).map_err(|e| {
+            wingmate_error::CronParseError::Parse {
+                source: e,
+                cron_line: String::from(l),
+                matched: String::from(match_str.as_str()),
+                field_name: String::from(HOUR)
+            }
+        })?;
+
+        match_str = cap.name(DAY_OF_MONTH_ABBRV).ok_or::<wingmate_error::CronParseError>(
+            wingmate_error::CronParseError::FieldMatch { cron_line: String::from(l), field_name: String::from(DAY_OF_MONTH) }
+        )?;
+        let dom = Self::to_cron_time_field_spec(&match_str, 1u8, 32u8).map_err(|e| {
+            wingmate_error::CronParseError::Parse {
+                source: e,
+                cron_line: String::from(l),
+                matched: String::from(match_str.as_str()),
+                field_name: String::from(DAY_OF_MONTH)
+            }
+        })?;
+
+        match_str = cap.name(MONTH).ok_or::<wingmate_error::CronParseError>(
+            wingmate_error::CronParseError::FieldMatch { cron_line: String::from(l), field_name: String::from(MONTH) }
+        )?;
+        let month = Self::to_cron_time_field_spec(&match_str, 1u8, 13u8).map_err(|e| {
+            wingmate_error::CronParseError::Parse {
+                source: e,
+                cron_line: String::from(l),
+                matched: String::from(match_str.as_str()),
+                field_name: String::from(MONTH)
+            }
+        })?;
+
+        match_str = cap.name(DAY_OF_WEEK_ABBRV).ok_or::<wingmate_error::CronParseError>(
+            wingmate_error::CronParseError::FieldMatch { cron_line: String::from(l), field_name: String::from(DAY_OF_WEEK) }
+        )?;
+        let dow = Self::to_cron_time_field_spec(&match_str, 0u8, 7u8).map_err(|e| {
+            wingmate_error::CronParseError::Parse {
+                source: e,
+                cron_line: String::from(l),
+                matched: String::from(match_str.as_str()),
+                field_name: String::from(DAY_OF_WEEK)
+            }
+        })?;
+
+        match_str = cap.name(COMMAND).ok_or::<wingmate_error::CronParseError>(
+            wingmate_error::CronParseError::FieldMatch { cron_line: String::from(l), field_name: String::from(COMMAND) }
+        )?;
+
+
+        Ok(Crontab {
+            name: String::new(),
+            minute,
+            hour,
+            day_of_month: dom,
+            month,
+            day_of_week: dow,
+            schedule: [MINUTE, HOUR, DAY_OF_MONTH_ABBRV, MONTH, DAY_OF_WEEK_ABBRV].iter()
+                .filter_map(|f| { cap.name(f).map(|m| { m.as_str() }) })
+                .collect::<Vec<&str>>()
+                .join(" "),
+            command: String::from(match_str.as_str()),
+            output: Output::default(),
+            critical: false,
+        })
+    }
+
+    /// Values from `min` up to but excluding `max`.
+    fn to_cron_time_field_spec(match_str: &regex::Match, min: u8, max: u8) -> Result<CronTimeFieldSpec, anyhow::Error> {
         let field = match_str.as_str();
 
         if field == "*" {
-            return Ok(CronTimeFieldSpec::Any);
-        } else if field.starts_with("*/") {
-            let every = field[2..].parse::<u8>().context("parsing on field matching \"every\" pattern")?;
-            if every >= max {
+            Ok(CronTimeFieldSpec::Any)
+        } else if let Some(every_str) = field.strip_prefix("*/") {
+            let every = every_str.parse::<u8>().context("parsing on field matching \"every\" pattern")?;
+            if every == 0 || every >= max {
                 return Err(anyhow!("invalid value {}", every));
             }
             let mut next_value = every;
@@ -218,26 +770,26 @@ impl Config {
                 multi.push(next_value);
                 next_value += every;
             }
-            return Ok(CronTimeFieldSpec::MultiOccurrence(multi));
+            Ok(CronTimeFieldSpec::MultiOccurrence(multi))
         } else if field.contains(",") {
             let multi: Vec<&str> = field.split(",").collect();
             let mut multi_occurrence: Vec<u8> = Vec::new();
 
             for m in multi {
                 let ur = m.parse::<u8>().context("parsing on field matching \"multi occurrence\" pattern")?;
-                if ur >= max {
+                if ur < min || ur >= max {
                     return Err(anyhow!("invalid value {}", field));
                 }
                 multi_occurrence.push(ur);
             }
 
-            return Ok(CronTimeFieldSpec::MultiOccurrence(multi_occurrence));
+            Ok(CronTimeFieldSpec::MultiOccurrence(multi_occurrence))
         } else {
             let n = field.parse::<u8>().context("parsing on field matching \"exact\" pattern")?;
-            if n >= max {
+            if n < min || n >= max {
                 return Err(anyhow!("invalid value {}", n));
             }
-            return Ok(CronTimeFieldSpec::Exact(n));
+            Ok(CronTimeFieldSpec::Exact(n))
         }
     }
 
@@ -270,7 +822,7 @@ impl Config {
             search_path.push(p);
 
             let shell_path = search_path.join(&shell);
-            if let Ok(_) = fs::metadata(shell_path.as_path()) {
+            if fs::metadata(shell_path.as_path()).is_ok() {
                 self.shell_path = Some(String::from(shell_path.to_string_lossy()));
                 return Ok(());
             }
@@ -279,44 +831,556 @@ impl Config {
         Err(wingmate_error::FindShellError::ShellNotFound)
     }
 
-    pub fn get_service_iter(&self) -> std::slice::Iter<Command> {
+    pub fn get_service_iter(&self) -> std::slice::Iter<'_, Service> {
         self.services.iter()
     }
 
-    pub fn get_cron_iter(&self) -> std::slice::Iter<Crontab> {
+    /// The services in an order that starts each one after those it depends
+    /// on. Dependencies on unknown services play no part in the order.
+    pub fn start_order(&self) -> Result<Vec<&Service>, wingmate_error::WingmateInitError> {
+        let mut order: Vec<&Service> = Vec::new();
+        let mut visiting: Vec<&str> = Vec::new();
+        for svc in self.get_service_iter() {
+            self.visit(svc, &mut visiting, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit<'a>(&'a self, svc: &'a Service, visiting: &mut Vec<&'a str>, order: &mut Vec<&'a Service>)
+        -> Result<(), wingmate_error::WingmateInitError> {
+        if order.iter().any(|s| { s.name == svc.name }) {
+            return Ok(());
+        }
+        if let Some(i) = visiting.iter().position(|n| { *n == svc.name }) {
+            let mut cycle = visiting[i..].to_vec();
+            cycle.push(&svc.name);
+            return Err(wingmate_error::WingmateInitError::DependencyCycle(cycle.join(" -> ")));
+        }
+
+        visiting.push(&svc.name);
+        for (dep, _) in svc.dependencies() {
+            if let Some(d) = self.services.iter().find(|s| { s.name == dep }) {
+                self.visit(d, visiting, order)?;
+            }
+        }
+        visiting.pop();
+        order.push(svc);
+        Ok(())
+    }
+
+    pub fn get_cron_iter(&self) -> std::slice::Iter<'_, Crontab> {
         self.cron.iter()
     }
 
+    /// The paths this config was found in, for reading it again.
+    pub fn get_search_path(&self) -> Vec<String> {
+        self.search_path.clone()
+    }
+
+    /// Whether the search path was merged, for reading the config again.
+    pub fn get_search_mode(&self) -> SearchMode {
+        self.search_mode
+    }
+
+    /// The definitions given on the command line, for reading the config again.
+    pub fn get_inline(&self) -> InlineConfig {
+        self.inline.clone()
+    }
+
     pub fn get_shell(&self) -> Option<String> {
         if let Some(shell) = &self.shell_path {
             return Some(shell.clone());
         }
         None
     }
+
+    /// The effective configuration in a human readable form, after every
+    /// source has been merged.
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "search path: {}{}", self.search_path.join(":"),
+            if self.search_mode == SearchMode::Merge { " (merged)" } else { "" });
+        let _ = writeln!(out, "shell: {}", self.shell_path.as_deref().unwrap_or("-"));
+        if self.control.enabled {
+            let _ = writeln!(out, "control socket: {}{}", self.control.socket.to_string_lossy(),
+                if self.control.logs { ", keeping output for logs" } else { "" });
+        }
+        if let Some(level) = self.log.level {
+            let _ = writeln!(out, "log level: {}", level);
+        }
+        if let Some(main) = &self.inline.main {
+            let _ = writeln!(out, "main command: {}", main.join(" "));
+        }
+
+        for svc in self.services.iter() {
+            let _ = writeln!(out, "\nservice {}", svc.name);
+            let _ = writeln!(out, "  command: {}", svc.command);
+            let _ = writeln!(out, "  type: {:?}, restart: {:?}, enabled: {}, required: {}",
+                svc.service_type, svc.restart, svc.enabled, svc.required);
+            if svc.foreground {
+                let _ = writeln!(out, "  foreground: true");
+            }
+            if let Some(user) = &svc.user {
+                let _ = writeln!(out, "  user: {}", user);
+            }
+            if let Some(dir) = &svc.directory {
+                let _ = writeln!(out, "  directory: {}", dir.to_string_lossy());
+            }
+            let deps: Vec<&str> = svc.dependencies().iter().map(|(d, _)| { *d }).collect();
+            if !deps.is_empty() {
+                let _ = writeln!(out, "  after: {}", deps.join(", "));
+            }
+        }
+
+        for c in self.cron.iter() {
+            let _ = writeln!(out, "\ncron {}", c.name);
+            let _ = writeln!(out, "  schedule: {}", c.schedule);
+            let _ = writeln!(out, "  command: {}", c.command);
+        }
+
+        for t in self.timers.iter() {
+            let _ = writeln!(out, "\ntimer {}", t.name);
+            let _ = writeln!(out, "  service: {}", t.unit);
+            let _ = writeln!(out, "  schedule: {}", t.schedule());
+        }
+
+        for t in self.tails.iter() {
+            let _ = writeln!(out, "\ntail {}", t.name);
+            let _ = writeln!(out, "  files: {}", t.directory.join(&t.pattern).to_string_lossy());
+        }
+        out
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::ShellPrefixed(p) => write!(f, "{} (through the shell)", p),
+            Command::Direct(p) | Command::Inline(p) => write!(f, "{}", p),
+        }
+    }
 }
 
 impl Clone for Command {
     fn clone(&self) -> Self {
         match self {
             Command::Direct(d) => Command::Direct(String::from(d)),
-            Command::ShellPrefixed(s) => Command::ShellPrefixed(String::from(s))
+            Command::ShellPrefixed(s) => Command::ShellPrefixed(String::from(s)),
+            Command::Inline(i) => Command::Inline(String::from(i)),
         }
     }
 }
 
 impl Clone for Crontab {
     fn clone(&self) -> Self {
-        Self { 
+        Self {
+            name: self.name.clone(),
             minute: self.minute.clone(),
             hour: self.hour.clone(),
             day_of_month: self.day_of_month.clone(),
             month: self.month.clone(),
             day_of_week: self.day_of_week.clone(),
-            command: self.command.clone()
+            schedule: self.schedule.clone(),
+            command: self.command.clone(),
+            output: self.output.clone(),
+            critical: self.critical,
+        }
+    }
+}
+
+impl Crontab {
+    /// Whether both entries run the same command on the same schedule.
+    pub fn same_job(&self, other: &Crontab) -> bool {
+        self.schedule == other.schedule && self.command == other.command && self.output == other.output
+    }
+
+    pub fn is_due(&self, time: &OffsetDateTime) -> bool {
+        self.minute.is_match(time.minute()) &&
+            self.hour.is_match(time.hour()) &&
+            self.day_of_month.is_match(time.day()) &&
+            self.month.is_match(time.month() as u8) &&
+            self.day_of_week.is_match(weekday_map(time.weekday()))
+    }
+
+    /// The first whole minute after `after` the entry is due, searching up to
+    /// a few years ahead.
+    pub fn next_run(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let mut t = after.replace_time(Time::from_hms(after.hour(), after.minute(), 0).ok()?) + TimeDuration::minutes(1);
+        let limit = t + TimeDuration::days(NEXT_RUN_SEARCH_DAYS);
+
+        while t < limit {
+            if !self.month.is_match(t.month() as u8) {
+                let year = if t.month() == Month::December { t.year() + 1 } else { t.year() };
+                t = t.replace_date(Date::from_calendar_date(year, t.month().next(), 1).ok()?).replace_time(Time::MIDNIGHT);
+            } else if !self.day_of_month.is_match(t.day()) || !self.day_of_week.is_match(weekday_map(t.weekday())) {
+                t = t.replace_time(Time::MIDNIGHT) + TimeDuration::days(1);
+            } else if !self.hour.is_match(t.hour()) {
+                t = t.replace_time(Time::from_hms(t.hour(), 0, 0).ok()?) + TimeDuration::hours(1);
+            } else if !self.minute.is_match(t.minute()) {
+                t += TimeDuration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+
+        None
+    }
+}
+
+impl Timer {
+    /// The triggers of the timer, for the status table.
+    pub fn schedule(&self) -> String {
+        let mut parts: Vec<String> = self.calendar.iter().map(|c| { c.expression.clone() }).collect();
+        if let Some(d) = self.on_boot {
+            parts.push(format!("{}s after start", d.as_secs()));
+        }
+        if let Some(d) = self.on_unit_active {
+            parts.push(format!("{}s after the last start", d.as_secs()));
+        }
+        parts.join("; ")
+    }
+}
+
+impl Service {
+    pub fn new(name: String, command: Command) -> Self {
+        Self {
+            name,
+            command,
+            output: Output::default(),
+            required: true,
+            enabled: true,
+            foreground: false,
+            modified: None,
+            directory: None,
+            environment: Vec::new(),
+            finish: None,
+            log: None,
+            start_pre: Vec::new(),
+            stop: None,
+            reload: None,
+            user: None,
+            group: None,
+            service_type: ServiceType::Simple,
+            pid_file: None,
+            after: Vec::new(),
+            requires: Vec::new(),
+            restart: Restart::Always,
+            restart_delay: None,
+            start_secs: Duration::from_secs(DEFAULT_START_SECS),
+            start_retries: None,
+            stop_signal: Signal::SIGTERM,
+            stop_timeout: Duration::from_secs(MAX_TERM_WAIT_TIME_SECS),
+        }
+    }
+
+    /// Every command the service may run.
+    pub fn commands(&self) -> impl Iterator<Item = &Command> {
+        [Some(&self.command), self.finish.as_ref(), self.log.as_ref(), self.stop.as_ref(), self.reload.as_ref()].into_iter()
+            .flatten()
+            .chain(self.start_pre.iter().map(|(c, _)| { c }))
+    }
+
+    /// Services this one waits for, with whether it requires them.
+    pub fn dependencies(&self) -> Vec<(&str, bool)> {
+        let mut deps: Vec<(&str, bool)> = self.requires.iter().map(|r| { (r.as_str(), true) }).collect();
+        for a in self.after.iter() {
+            if !deps.iter().any(|(d, _)| { d == a }) {
+                deps.push((a.as_str(), false));
+            }
+        }
+        deps
+    }
+}
+
+/// Executable scripts run directly, anything else through the shell.
+fn script_command(path: &Path) -> Command {
+    if access(path, AccessFlags::X_OK).is_ok() {
+        Command::Direct(String::from(path.to_string_lossy()))
+    } else {
+        Command::ShellPrefixed(String::from(path.to_string_lossy()))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| { m.modified() }).ok()
+}
+
+/// Reads an envdir: the file name is the variable, the first line of the
+/// file its value. An empty file removes the variable.
+fn read_env_dir(dir: &Path) -> Vec<(String, Option<String>)> {
+    let mut environment: Vec<(String, Option<String>)> = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let key = String::from(entry.file_name().to_string_lossy());
+            if key.starts_with('.') || key.contains('=') {
+                continue;
+            }
+            let content = match fs::read(entry.path()) {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("reading {}: {}", entry.path().to_string_lossy(), e);
+                    continue;
+                }
+            };
+            let value = if content.is_empty() {
+                None
+            } else {
+                let line = content.split(|b| { *b == b'\n' }).next().unwrap_or_default();
+                let value = String::from_utf8_lossy(line).replace('\0', "\n");
+                Some(String::from(value.trim_end_matches([' ', '\t'])))
+            };
+            environment.push((key, value));
+        }
+    }
+
+    environment.sort();
+    environment
+}
+
+/// Dotfiles, editor backups and READMEs in `services` are not services.
+pub fn is_ignored(file_name: &str) -> bool {
+    file_name.starts_with('.')
+        || (file_name.starts_with('#') && file_name.ends_with('#'))
+        || file_name.to_ascii_uppercase().starts_with("README")
+        || BACKUP_SUFFIXES.iter().any(|s| { file_name.ends_with(s) })
+}
+
+/// Shell-style matching of a file name supporting `*` and `?`.
+pub fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0usize, 0usize);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((bp, bn)) = backtrack {
+            p = bp + 1;
+            n = bn + 1;
+            backtrack = Some((bp, bn + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == b'*' {
+        p += 1;
+    }
+
+    p == pattern.len()
+}
+
+fn weekday_map(wd: Weekday) -> u8 {
+    match wd {
+        Weekday::Sunday => 0,
+        Weekday::Monday => 1,
+        Weekday::Tuesday => 2,
+        Weekday::Wednesday => 3,
+        Weekday::Thursday => 4,
+        Weekday::Friday => 5,
+        Weekday::Saturday => 6
+    }
+}
+
+/// The booleans of ini style files and environment variables.
+pub fn parse_bool(text: &str) -> Option<bool> {
+    match text.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Accepts `TERM`, `SIGTERM` or a signal number.
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(n) = name.parse::<i32>() {
+        return Signal::try_from(n).ok();
+    }
+
+    let upper = name.to_ascii_uppercase();
+    if upper.starts_with("SIG") {
+        Signal::from_str(&upper).ok()
+    } else {
+        Signal::from_str(&format!("SIG{}", upper)).ok()
+    }
+}
+
+impl CalendarSpec {
+    /// Parses a calendar expression: an optional weekday list, an optional
+    /// `year-month-day` date, an optional `hour:minute[:second]` time and an
+    /// optional `UTC`, or one of the shorthands such as `daily`. Every field
+    /// takes `*`, lists, `a..b` ranges and `/step` repetitions.
+    pub fn parse(expression: &str) -> Option<CalendarSpec> {
+        let normalized = match expression.trim().to_ascii_lowercase().as_str() {
+            "minutely" => "*-*-* *:*:00",
+            "hourly" => "*-*-* *:00:00",
+            "daily" => "*-*-* 00:00:00",
+            "weekly" => "Mon *-*-* 00:00:00",
+            "monthly" => "*-*-01 00:00:00",
+            "quarterly" => "*-01,04,07,10-01 00:00:00",
+            "semiannually" => "*-01,07-01 00:00:00",
+            "yearly" | "annually" => "*-01-01 00:00:00",
+            _ => expression.trim(),
+        };
+
+        let mut words: Vec<&str> = normalized.split_whitespace().collect();
+        let utc = words.last().is_some_and(|w| { w.eq_ignore_ascii_case("UTC") });
+        if utc {
+            words.pop();
+        }
+        if words.is_empty() || words.len() > 3 {
+            return None;
+        }
+
+        let weekday = match words.first() {
+            Some(w) if w.starts_with(|c: char| { c.is_ascii_alphabetic() }) => {
+                let days = calendar_weekdays(w)?;
+                words.remove(0);
+                days
+            },
+            _ => CronTimeFieldSpec::Any,
+        };
+
+        let (mut date, mut time): (Option<&str>, Option<&str>) = (None, None);
+        for w in words {
+            if w.contains(':') && time.is_none() {
+                time = Some(w);
+            } else if w.contains('-') && date.is_none() && time.is_none() {
+                date = Some(w);
+            } else {
+                return None;
+            }
         }
+
+        let date: Vec<&str> = date.unwrap_or("*-*-*").split('-').collect();
+        let (year, month, day) = match date.as_slice() {
+            [y, m, d] => (*y, *m, *d),
+            [m, d] => ("*", *m, *d),
+            _ => return None,
+        };
+        let time: Vec<&str> = time.unwrap_or("00:00:00").split(':').collect();
+        let (hour, minute, second) = match time.as_slice() {
+            [h, m] => (*h, *m, "00"),
+            [h, m, s] => (*h, *m, *s),
+            _ => return None,
+        };
+
+        Some(CalendarSpec {
+            weekday,
+            years: calendar_values(year, 1970, 2199)?.unwrap_or_default().into_iter().map(|y| { y as i32 }).collect(),
+            month: calendar_field(month, 1, 12)?,
+            day: calendar_field(day, 1, 31)?,
+            hour: calendar_field(hour, 0, 23)?,
+            minute: calendar_field(minute, 0, 59)?,
+            second: calendar_field(second, 0, 59)?,
+            utc,
+            expression: String::from(expression.trim()),
+        })
+    }
+
+    /// The first whole second after `after` matching the expression, searching
+    /// up to a few years ahead, in the offset of `after` unless it is a UTC
+    /// expression.
+    pub fn next_elapse(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let after = if self.utc { after.to_offset(UtcOffset::UTC) } else { after };
+        let mut t = after.replace_nanosecond(0).ok()? + TimeDuration::seconds(1);
+        let limit = t + TimeDuration::days(NEXT_RUN_SEARCH_DAYS);
+
+        while t < limit {
+            if !self.years.is_empty() && !self.years.contains(&t.year()) {
+                t = t.replace_date(Date::from_calendar_date(t.year() + 1, Month::January, 1).ok()?).replace_time(Time::MIDNIGHT);
+            } else if !self.month.is_match(t.month() as u8) {
+                let year = if t.month() == Month::December { t.year() + 1 } else { t.year() };
+                t = t.replace_date(Date::from_calendar_date(year, t.month().next(), 1).ok()?).replace_time(Time::MIDNIGHT);
+            } else if !self.day.is_match(t.day()) || !self.weekday.is_match(weekday_map(t.weekday())) {
+                t = t.replace_time(Time::MIDNIGHT) + TimeDuration::days(1);
+            } else if !self.hour.is_match(t.hour()) {
+                t = t.replace_time(Time::from_hms(t.hour(), 0, 0).ok()?) + TimeDuration::hours(1);
+            } else if !self.minute.is_match(t.minute()) {
+                t = t.replace_time(Time::from_hms(t.hour(), t.minute(), 0).ok()?) + TimeDuration::minutes(1);
+            } else if !self.second.is_match(t.second()) {
+                t += TimeDuration::seconds(1);
+            } else {
+                return Some(t);
+            }
+        }
+
+        None
     }
 }
 
+/// The values of a calendar field between `min` and `max`, `None` within the
+/// result standing for `*`.
+fn calendar_values(text: &str, min: u32, max: u32) -> Option<Option<Vec<u32>>> {
+    if text == "*" {
+        return Some(None);
+    }
+
+    let mut values: Vec<u32> = Vec::new();
+    for item in text.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((r, s)) => (r, Some(s.parse::<u32>().ok().filter(|s| { *s > 0 })?)),
+            None => (item, None),
+        };
+        let (start, end) = match range.split_once("..") {
+            _ if range == "*" => (min, max),
+            Some((a, b)) => (a.parse::<u32>().ok()?, b.parse::<u32>().ok()?),
+            None => {
+                let a = range.parse::<u32>().ok()?;
+                (a, if step.is_some() { max } else { a })
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        values.extend((start..=end).step_by(step.unwrap_or(1) as usize));
+    }
+    values.sort();
+    values.dedup();
+
+    Some(Some(values))
+}
+
+fn calendar_field(text: &str, min: u8, max: u8) -> Option<CronTimeFieldSpec> {
+    let values = calendar_values(text, min as u32, max as u32)?;
+    Some(match values {
+        None => CronTimeFieldSpec::Any,
+        Some(v) if v.len() == 1 => CronTimeFieldSpec::Exact(v[0] as u8),
+        Some(v) => CronTimeFieldSpec::MultiOccurrence(v.into_iter().map(|x| { x as u8 }).collect()),
+    })
+}
+
+/// `Mon`, `Mon,Fri` or `Mon..Fri`, in short or long form.
+fn calendar_weekdays(text: &str) -> Option<CronTimeFieldSpec> {
+    const NAMES: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+    let index = |name: &str| -> Option<u8> {
+        let name = name.to_ascii_lowercase();
+        let i = NAMES.iter().position(|n| { name == *n || name == n[..3] })?;
+        // Monday is 1 and Sunday 7, which the modulo turns into 0
+        Some(i as u8 + 1)
+    };
+
+    let mut days: Vec<u8> = Vec::new();
+    for item in text.split(',') {
+        let (start, end) = match item.split_once("..") {
+            Some((a, b)) => (index(a)?, index(b)?),
+            None => (index(item)?, index(item)?),
+        };
+        if start > end {
+            return None;
+        }
+        days.extend((start..=end).map(|d| { d % 7 }));
+    }
+    days.sort();
+    days.dedup();
+
+    Some(CronTimeFieldSpec::MultiOccurrence(days))
+}
+
 impl CronTimeFieldSpec {
     pub fn is_match(&self, current: u8) -> bool {
         match self {
@@ -334,66 +1398,269 @@ impl CronTimeFieldSpec {
     }
 }
 
-impl Clone for CronTimeFieldSpec {
-    fn clone(&self) -> Self {
-        match self {
-            Self::Any => Self::Any,
-            Self::Exact(x) => Self::Exact(*x),
-            Self::MultiOccurrence(x) => {
-                Self::MultiOccurrence(x.clone())
-            },
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            stdout: true,
+            file: None,
         }
     }
 }
 
-struct CronFieldCmpHelper<'a>(u8, u8, Option<&'a Vec<u8>>);
-impl PartialEq for CronTimeFieldSpec {
-    fn eq(&self, other: &Self) -> bool {
-        let lhs: CronFieldCmpHelper;
-        let rhs: CronFieldCmpHelper;
-        match self {
-            CronTimeFieldSpec::Any => { lhs = CronFieldCmpHelper(0, 0, None); }
-            CronTimeFieldSpec::Exact(x) => { lhs = CronFieldCmpHelper(1, *x, None); }
-            CronTimeFieldSpec::MultiOccurrence(v) => { lhs = CronFieldCmpHelper(1, 0, Some(v)); }
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            listen: false,
+            socket: PathBuf::from(DEFAULT_SYSLOG_SOCKET),
+            forward: None,
         }
+    }
+}
 
-        match other {
-            CronTimeFieldSpec::Any => { rhs = CronFieldCmpHelper(0, 0, None); }
-            CronTimeFieldSpec::Exact(x) => { rhs = CronFieldCmpHelper(1, *x, None); }
-            CronTimeFieldSpec::MultiOccurrence(v) => { rhs = CronFieldCmpHelper(2, 0, Some(v)); }
+impl Default for StatusConfig {
+    fn default() -> Self {
+        Self {
+            signal: Signal::SIGUSR1,
+            file: None,
+            format: LogFormat::Text,
+            interval: None,
         }
+    }
+}
 
-        if lhs.0 == rhs.0 {
-            if lhs.0 == 3u8 {
-                if let Some(lv) = lhs.2 {
-                    if let Some(rv) = rhs.2 {
-                        if lv.len() != rv.len() {
-                            return false;
-                        }
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self {
+            watch: false,
+            debounce: Duration::from_secs(DEFAULT_RELOAD_DEBOUNCE_SECS),
+        }
+    }
+}
 
-                        let mut l_iter = lv.iter();
-                        let mut r_iter = rv.iter();
-                        'item: loop {
-                            if let Some(liv) = l_iter.next() {
-                                if let Some(riv) = r_iter.next() {
-                                    if *liv != *riv {
-                                        return false;
-                                    }
-                                } else {
-                                    break 'item;
-                                }
-                            } else {
-                                break 'item;
-                            }
-                        }
-                        return true;
-                    }
-                }
-            } else {
-                return lhs.1 == rhs.1;
-            }
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            socket: PathBuf::from(protocol::DEFAULT_SOCKET),
+            logs: false,
         }
+    }
+}
 
-        false
+impl Output {
+    /// Inherit mode hands our own stdout/stderr to the child; anything else
+    /// needs the output piped through wingmate.
+    pub fn is_inherit(&self) -> bool {
+        self.stdout && self.file.is_none()
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn cron_field_bounds() {
+        for line in ["59 23 31 12 6 true", "0 0 1 1 0 true", "*/30 */12 1,15,31 1,12 0,6 true"] {
+            assert!(Config::parse_cron_line(line).is_ok(), "{}", line);
+        }
+        for line in ["60 * * * * true", "* 24 * * * true", "* * 0 * * true", "* * 32 * * true", "* * * 0 * true",
+            "* * * 13 * true", "* * * * 7 true", "* * 1,0 * * true", "* * * 0,6 * true", "*/0 * * * * true",
+            "*/60 * * * * true"] {
+            assert!(matches!(Config::parse_cron_line(line), Err(wingmate_error::CronParseError::Parse { .. })), "{}", line);
+        }
+    }
+
+    fn names(cron: &[Crontab]) -> Vec<&str> {
+        cron.iter().map(|c| { c.name.as_str() }).collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn crontab_names() {
+        let content = "# nightly jobs\n\n0 3 * * * backup --all\n# name: rotate\n30 3 * * * logrotate /etc/lr.conf\n* * * * * true\n*/5 * * * * true\n";
+        let cron = Config::parse_crontab("crontab", content, None).unwrap();
+        assert_eq!(names(&cron), ["crontab:backup --all", "crontab:rotate", "crontab:true", "crontab:true#2"]);
+
+        // a line added in front leaves the names of the others alone
+        let inserted = format!("15 * * * * date\n{}", content);
+        let cron = Config::parse_crontab("crontab", &inserted, None).unwrap();
+        assert_eq!(names(&cron), ["crontab:date", "crontab:backup --all", "crontab:rotate", "crontab:true", "crontab:true#2"]);
+    }
+
+    #[test]
+    fn crontab_problems() {
+        let content = "# name: broken\n61 * * * * true\n0 * * * * date\n";
+        assert!(Config::parse_crontab("crontab", content, None).is_err());
+
+        let mut problems: Vec<Problem> = Vec::new();
+        let cron = Config::parse_crontab("crontab", content, Some(&mut problems)).unwrap();
+        assert_eq!(names(&cron), ["crontab:date"]);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(&problems[0], Problem::Error(m) if m.starts_with("crontab line 2:")));
+    }
+
+    /// A fresh directory under the temp dir, gone when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = env::temp_dir().join(format!("wingmate-test-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("services")).unwrap();
+            TempDir(dir)
+        }
+
+        fn write(&self, path: &str, content: &str) -> &TempDir {
+            fs::write(self.0.join(path), content).unwrap();
+            self
+        }
+
+        fn path(&self) -> String {
+            self.0.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn merged_search_path() {
+        let base = TempDir::new("merge-base");
+        base.write("services/web", "#!/bin/sh\n")
+            .write("services/worker", "#!/bin/sh\n")
+            .write("crontab", "0 3 * * * backup\n0 4 * * * cleanup\n");
+        let over = TempDir::new("merge-over");
+        over.write("services/worker.disabled", "#!/bin/sh\n")
+            .write("services/extra", "#!/bin/sh\n")
+            .write("crontab", "# name: backup\n0 5 * * * backup --full\n");
+
+        let search_path = vec![base.path(), over.path()];
+        let first = Config::find(search_path.clone(), SearchMode::FirstMatch, InlineConfig::default()).unwrap();
+        let mut services: Vec<&str> = first.get_service_iter().map(|s| { s.name.as_str() }).collect();
+        services.sort();
+        assert_eq!(services, ["web", "worker"]);
+        assert_eq!(names(&first.cron), ["crontab:backup", "crontab:cleanup"]);
+
+        let merged = Config::find(search_path, SearchMode::Merge, InlineConfig::default()).unwrap();
+        let mut services: Vec<(&str, bool)> = merged.get_service_iter().map(|s| { (s.name.as_str(), s.enabled) }).collect();
+        services.sort();
+        assert_eq!(services, [("extra", true), ("web", true), ("worker", false)]);
+        // the later crontab replaces the earlier one as a whole
+        assert_eq!(names(&merged.cron), ["crontab:backup"]);
+        assert_eq!(merged.cron[0].command, "backup --full");
+    }
+
+    #[test]
+    fn cron_same_job() {
+        let job = Config::parse_cron_line("0 3 * * * backup").unwrap();
+        let mut critical = Config::parse_cron_line("0 3 * * * backup").unwrap();
+        critical.name = String::from("crontab:backup");
+        critical.critical = true;
+        assert!(job.same_job(&critical));
+
+        assert!(!job.same_job(&Config::parse_cron_line("0 4 * * * backup").unwrap()));
+        assert!(!job.same_job(&Config::parse_cron_line("0 3 * * * backup --full").unwrap()));
+        let mut quiet = Config::parse_cron_line("0 3 * * * backup").unwrap();
+        quiet.output.stdout = false;
+        assert!(!job.same_job(&quiet));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        for (pattern, name) in [("*.log", "app.log"), ("*.log", ".log"), ("app-?.log", "app-1.log"), ("*", ""),
+            ("a*b*c", "aXbYbc"), ("app.log", "app.log"), ("**.log", "x.log")] {
+            assert!(glob_match(pattern.as_bytes(), name.as_bytes()), "{} {}", pattern, name);
+        }
+        for (pattern, name) in [("*.log", "app.log.1"), ("app-?.log", "app-10.log"), ("app.log", "app.lo"), ("?", ""),
+            ("a*b*c", "aXbYc d")] {
+            assert!(!glob_match(pattern.as_bytes(), name.as_bytes()), "{} {}", pattern, name);
+        }
+    }
+
+    #[test]
+    fn field_spec_equality() {
+        let multi = || { CronTimeFieldSpec::MultiOccurrence(vec![1, 15]) };
+        assert_eq!(multi(), multi());
+        assert_ne!(multi(), CronTimeFieldSpec::MultiOccurrence(vec![1, 16]));
+        assert_ne!(multi(), CronTimeFieldSpec::MultiOccurrence(vec![1]));
+        assert_ne!(multi(), CronTimeFieldSpec::Exact(1));
+        assert_ne!(CronTimeFieldSpec::Exact(0), CronTimeFieldSpec::Any);
+        assert_eq!(CronTimeFieldSpec::Exact(5), CronTimeFieldSpec::Exact(5));
+        assert_eq!(CronTimeFieldSpec::Any, CronTimeFieldSpec::Any);
+    }
+
+    #[test]
+    fn calendar_spec_equality() {
+        let spec = CalendarSpec::parse("Mon,Fri *-*-1,15 09:00").unwrap();
+        assert_eq!(spec, CalendarSpec::parse("Mon,Fri *-*-1,15 09:00").unwrap());
+        assert_ne!(spec, CalendarSpec::parse("Mon,Fri *-*-1,16 09:00").unwrap());
+    }
+
+    #[test]
+    fn calendar_full_expression() {
+        let spec = CalendarSpec::parse("Mon..Fri 2030-*-01 09:30:15 UTC").unwrap();
+        assert_eq!(spec.weekday, CronTimeFieldSpec::MultiOccurrence(vec![1, 2, 3, 4, 5]));
+        assert_eq!(spec.years, vec![2030]);
+        assert_eq!(spec.month, CronTimeFieldSpec::Any);
+        assert_eq!(spec.day, CronTimeFieldSpec::Exact(1));
+        assert_eq!(spec.hour, CronTimeFieldSpec::Exact(9));
+        assert_eq!(spec.minute, CronTimeFieldSpec::Exact(30));
+        assert_eq!(spec.second, CronTimeFieldSpec::Exact(15));
+        assert!(spec.utc);
+    }
+
+    #[test]
+    fn calendar_shorthands() {
+        assert_eq!(CalendarSpec::parse("daily").unwrap().hour, CronTimeFieldSpec::Exact(0));
+        assert_eq!(CalendarSpec::parse("weekly").unwrap().weekday, CronTimeFieldSpec::MultiOccurrence(vec![1]));
+        assert_eq!(CalendarSpec::parse("Quarterly").unwrap().month, CronTimeFieldSpec::MultiOccurrence(vec![1, 4, 7, 10]));
+        assert_eq!(CalendarSpec::parse("hourly").unwrap().hour, CronTimeFieldSpec::Any);
+    }
+
+    #[test]
+    fn calendar_ranges_and_steps() {
+        let spec = CalendarSpec::parse("*-*-* *:0/15").unwrap();
+        assert_eq!(spec.minute, CronTimeFieldSpec::MultiOccurrence(vec![0, 15, 30, 45]));
+        assert_eq!(spec.second, CronTimeFieldSpec::Exact(0));
+        let spec = CalendarSpec::parse("Sat,Sun 12-24..31").unwrap();
+        assert_eq!(spec.weekday, CronTimeFieldSpec::MultiOccurrence(vec![0, 6]));
+        assert_eq!(spec.month, CronTimeFieldSpec::Exact(12));
+        assert_eq!(spec.day, CronTimeFieldSpec::MultiOccurrence((24..=31).collect()));
+    }
+
+    #[test]
+    fn calendar_rejects_invalid() {
+        for expression in ["", "UTC", "Someday", "*-13-01", "*-*-32", "24:00", "*:*:60", "*-*-* 1:2:3:4",
+            "10:00 *-*-*", "*-*-* *:0/0", "Fri..Mon", "5..3:00", "a b c d"] {
+            assert!(CalendarSpec::parse(expression).is_none(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn calendar_next_elapse() {
+        let spec = CalendarSpec::parse("Mon *-*-* 09:00 UTC").unwrap();
+        // a Sunday
+        let after = datetime!(2024-06-02 10:00:00 UTC);
+        assert_eq!(spec.next_elapse(after), Some(datetime!(2024-06-03 09:00:00 UTC)));
+        assert_eq!(spec.next_elapse(datetime!(2024-06-03 09:00:00 UTC)), Some(datetime!(2024-06-10 09:00:00 UTC)));
+
+        let spec = CalendarSpec::parse("*-02-30").unwrap();
+        assert_eq!(spec.next_elapse(after), None);
+    }
+}
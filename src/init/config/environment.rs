@@ -0,0 +1,243 @@
+use std::ffi::OsString;
+use std::time::Duration;
+use crate::init::config::{self, parse_bool, Config, Command, Problem, Restart, Service};
+use crate::init::config::systemd::parse_timespan;
+use crate::init::error::{WingmateInitError, ConfigFileError};
+
+const SERVICE_PREFIX: &str = "WINGMATE_SERVICE_";
+const CRON_PREFIX: &str = "WINGMATE_CRON_";
+/// Separates the name from the option in `WINGMATE_SERVICE_WEB__RESTART`, so
+/// names may contain single underscores.
+const OPTION_SEPARATOR: &str = "__";
+
+/// A variable with one of our prefixes.
+pub(super) struct Var {
+    key: String,
+    prefix: &'static str,
+    /// The name after the prefix.
+    rest: String,
+    value: String,
+}
+
+/// The variables of `vars` with one of our prefixes, sorted. Others are
+/// left alone whatever they contain, while a prefixed one whose name or
+/// value is not UTF-8 fails, or with `problems` is recorded and skipped.
+pub(super) fn vars(vars: impl Iterator<Item = (OsString, OsString)>, mut problems: Option<&mut Vec<Problem>>)
+    -> Result<Vec<Var>, WingmateInitError> {
+    let mut prefixed: Vec<Var> = Vec::new();
+    for (k, v) in vars {
+        let prefix = match [SERVICE_PREFIX, CRON_PREFIX].into_iter().find(|p| { k.as_encoded_bytes().starts_with(p.as_bytes()) }) {
+            Some(p) => p,
+            None => continue,
+        };
+        let (key, value) = match (k.into_string(), v.into_string()) {
+            (Ok(key), Ok(value)) => (key, value),
+            (k, _) => {
+                let name = k.unwrap_or_else(|k| { k.to_string_lossy().into_owned() });
+                config::collect(&mut problems, Err(WingmateInitError::Environment { name, source: ConfigFileError::NotUtf8 }))?;
+                continue;
+            }
+        };
+        let rest = String::from(&key[prefix.len()..]);
+        prefixed.push(Var { key, prefix, rest, value });
+    }
+    prefixed.sort_by(|a, b| { a.key.cmp(&b.key) });
+    Ok(prefixed)
+}
+
+/// Adds services from `WINGMATE_SERVICE_<NAME>=command` and cron entries from
+/// `WINGMATE_CRON_<NAME>="schedule command"`, replacing same-named ones from
+/// files. Names are lowercased. With `problems`, a variable that does not
+/// parse is recorded there and skipped.
+pub(super) fn apply_definitions(config: &mut Config, vars: &[Var], mut problems: Option<&mut Vec<Problem>>) -> Result<(), WingmateInitError> {
+    for Var { key, prefix, rest, value } in vars {
+        let value = value.clone();
+        let wrap = |e: ConfigFileError| { WingmateInitError::Environment { name: key.clone(), source: e } };
+        if rest.contains(OPTION_SEPARATOR) {
+            continue;
+        }
+
+        let name = rest.to_ascii_lowercase();
+        if name.is_empty() || value.trim().is_empty() {
+            config::collect(&mut problems, Err(wrap(ConfigFileError::InvalidValue { key: key.clone(), value })))?;
+            continue;
+        }
+        if *prefix == SERVICE_PREFIX {
+            let svc = Service::new(name, Command::Inline(value));
+            match config.services.iter().position(|s| { s.name == svc.name }) {
+                Some(i) => config.services[i] = svc,
+                None => config.services.push(svc),
+            }
+        } else {
+            let mut cron = match Config::parse_cron_line(&value) {
+                Ok(cron) => cron,
+                Err(e) => {
+                    config::collect(&mut problems, Err(WingmateInitError::Cron { source: e }))?;
+                    continue;
+                }
+            };
+            cron.name = name;
+            match config.cron.iter().position(|c| { c.name == cron.name }) {
+                Some(i) => config.cron[i] = cron,
+                None => config.cron.push(cron),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies `WINGMATE_SERVICE_<NAME>__<OPTION>` and
+/// `WINGMATE_CRON_<NAME>__<OPTION>`, once every service and cron entry is
+/// known, whichever source it came from.
+pub(super) fn apply_options(config: &mut Config, vars: &[Var], mut problems: Option<&mut Vec<Problem>>) -> Result<(), WingmateInitError> {
+    for Var { key, prefix, rest, value } in vars {
+        let (name, option) = match rest.split_once(OPTION_SEPARATOR) {
+            Some((name, option)) => (name.to_ascii_lowercase(), option.to_ascii_lowercase()),
+            None => continue,
+        };
+        let result = if *prefix == SERVICE_PREFIX {
+            match config.services.iter_mut().find(|s| { s.name == name }) {
+                Some(svc) => service_option(svc, &option, value),
+                None => Err(ConfigFileError::UnknownService(name)),
+            }
+        } else {
+            match config.cron.iter_mut().find(|c| { c.name == name }) {
+                Some(cron) => match option.as_str() {
+                    "critical" => parse_bool(value).map(|b| { cron.critical = b; }).ok_or_else(|| { invalid(&option, value) }),
+                    _ => Err(ConfigFileError::UnknownOption(option)),
+                },
+                None => Err(ConfigFileError::UnknownCron(name)),
+            }
+        };
+        config::collect(&mut problems, result.map_err(|e| { WingmateInitError::Environment { name: key.clone(), source: e } }))?;
+    }
+
+    Ok(())
+}
+
+fn service_option(svc: &mut Service, option: &str, value: &str) -> Result<(), ConfigFileError> {
+    let invalid = || { invalid(option, value) };
+    let duration = || -> Result<Duration, ConfigFileError> { parse_timespan(value).ok_or_else(invalid) };
+    let names = || -> Vec<String> {
+        value.split(|c: char| { c == ',' || c.is_whitespace() }).filter(|n| { !n.is_empty() }).map(String::from).collect()
+    };
+
+    match option {
+        "restart" => {
+            svc.restart = match value.to_ascii_lowercase().as_str() {
+                "always" => Restart::Always,
+                "on-failure" => Restart::OnFailure,
+                "never" | "no" => Restart::Never,
+                _ => return Err(invalid()),
+            };
+        },
+        "restart_delay" => svc.restart_delay = Some(duration()?),
+        "start_secs" => svc.start_secs = duration()?,
+        "start_retries" => svc.start_retries = Some(value.parse().map_err(|_| { invalid() })?),
+        "stop_signal" => svc.stop_signal = config::parse_signal(value).ok_or_else(invalid)?,
+        "stop_timeout" => svc.stop_timeout = duration()?,
+        "user" => svc.user = Some(String::from(value)),
+        "group" => svc.group = Some(String::from(value)),
+        "directory" => svc.directory = Some(value.into()),
+        "enabled" => svc.enabled = parse_bool(value).ok_or_else(invalid)?,
+        "required" => svc.required = parse_bool(value).ok_or_else(invalid)?,
+        "foreground" => svc.foreground = parse_bool(value).ok_or_else(invalid)?,
+        "after" => svc.after = names(),
+        "requires" => svc.requires = names(),
+        _ => return Err(ConfigFileError::UnknownOption(String::from(option))),
+    }
+    Ok(())
+}
+
+fn invalid(key: &str, value: &str) -> ConfigFileError {
+    ConfigFileError::InvalidValue { key: String::from(key), value: String::from(value) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStringExt;
+    use crate::init::config::{InlineConfig, SearchMode};
+
+    fn os(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter().map(|(k, v)| { (OsString::from(k), OsString::from(v)) }).collect()
+    }
+
+    fn load(vars: &[(&str, &str)]) -> (Config, Vec<Problem>) {
+        let mut problems: Vec<Problem> = Vec::new();
+        let mut config = Config::new(Vec::new(), SearchMode::FirstMatch, InlineConfig::default());
+        let vars = super::vars(os(vars).into_iter(), Some(&mut problems)).unwrap();
+        apply_definitions(&mut config, &vars, Some(&mut problems)).unwrap();
+        apply_options(&mut config, &vars, Some(&mut problems)).unwrap();
+        (config, problems)
+    }
+
+    #[test]
+    fn definitions_and_options() {
+        let (config, problems) = load(&[
+            ("WINGMATE_SERVICE_WEB_APP", "serve --port 80"),
+            ("WINGMATE_SERVICE_WEB_APP__RESTART", "never"),
+            ("WINGMATE_SERVICE_WEB_APP__STOP_TIMEOUT", "1min 30s"),
+            ("WINGMATE_SERVICE_WEB_APP__AFTER", "db, cache"),
+            ("WINGMATE_CRON_BACKUP", "0 3 * * * backup --all"),
+            ("WINGMATE_CRON_BACKUP__CRITICAL", "yes"),
+            ("PATH", "/bin"),
+        ]);
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(config.services.len(), 1);
+        let svc = &config.services[0];
+        assert_eq!(svc.name, "web_app");
+        assert_eq!(svc.command, Command::Inline(String::from("serve --port 80")));
+        assert_eq!(svc.restart, Restart::Never);
+        assert_eq!(svc.stop_timeout, Duration::from_secs(90));
+        assert_eq!(svc.after, vec!["db", "cache"]);
+        assert_eq!(config.cron.len(), 1);
+        assert_eq!(config.cron[0].name, "backup");
+        assert_eq!(config.cron[0].command, "backup --all");
+        assert!(config.cron[0].critical);
+    }
+
+    #[test]
+    fn rejected_variables() {
+        let (config, problems) = load(&[
+            ("WINGMATE_SERVICE_", "serve"),
+            ("WINGMATE_SERVICE_EMPTY", "  "),
+            ("WINGMATE_CRON_BAD", "every day backup"),
+            ("WINGMATE_SERVICE_WEB", "serve"),
+            ("WINGMATE_SERVICE_WEB__RESTART", "sometimes"),
+            ("WINGMATE_SERVICE_WEB__COLOR", "blue"),
+            ("WINGMATE_SERVICE_NONE__RESTART", "always"),
+            ("WINGMATE_CRON_NONE__CRITICAL", "yes"),
+        ]);
+        assert_eq!(config.services.len(), 1);
+        assert!(config.cron.is_empty());
+        assert_eq!(problems.len(), 7);
+    }
+
+    #[test]
+    fn strict_without_problems() {
+        let mut config = Config::new(Vec::new(), SearchMode::FirstMatch, InlineConfig::default());
+        let vars = super::vars(os(&[("WINGMATE_CRON_BAD", "nope")]).into_iter(), None).unwrap();
+        assert!(apply_definitions(&mut config, &vars, None).is_err());
+    }
+
+    #[test]
+    fn non_utf8_variables() {
+        let bad = || { OsString::from_vec(vec![b'x', 0xff]) };
+        let vars = vec![
+            (bad(), bad()),
+            (OsString::from("OTHER"), bad()),
+            (OsString::from("WINGMATE_SERVICE_BAD"), bad()),
+            (OsString::from_vec(b"WINGMATE_CRON_\xff".to_vec()), OsString::from("* * * * * true")),
+            (OsString::from("WINGMATE_SERVICE_GOOD"), OsString::from("serve")),
+        ];
+
+        let mut problems: Vec<Problem> = Vec::new();
+        let prefixed = super::vars(vars.clone().into_iter(), Some(&mut problems)).unwrap();
+        assert_eq!(prefixed.len(), 1);
+        assert_eq!(prefixed[0].rest, "GOOD");
+        assert_eq!(problems.len(), 2);
+        assert!(super::vars(vars.into_iter(), None).is_err());
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use log::LevelFilter;
+use nix::sys::signal::Signal;
+use serde::Deserialize;
+use crate::init::config::{self, Config, StatusConfig, MetricsConfig, MetricsAddress, Command, Service, Output, LogFile, LogFormat, Tail, SyslogForward, ForwardAddress, DEFAULT_FORWARD_QUEUE_SIZE, DEFAULT_FORWARD_FACILITY, DEFAULT_LOG_MAX_SIZE, DEFAULT_LOG_MAX_FILES};
+use crate::init::error::{WingmateInitError, ConfigFileError};
+
+/// Signals that cannot be caught or that wingmate already acts on, so they
+/// cannot ask for a status report. The terminal ones are passed on to the
+/// process holding the terminal, or ignored while running in the background.
+const RESERVED_SIGNALS: [Signal; 11] = [
+    Signal::SIGKILL, Signal::SIGSTOP, Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP, Signal::SIGCHLD,
+    Signal::SIGWINCH, Signal::SIGTSTP, Signal::SIGCONT, Signal::SIGTTIN, Signal::SIGTTOU,
+];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    log: Option<LogConfigSection>,
+
+    syslog: Option<SyslogSection>,
+
+    control: Option<ControlSection>,
+
+    status: Option<StatusSection>,
+
+    metrics: Option<MetricsSection>,
+
+    health: Option<HealthSection>,
+
+    reload: Option<ReloadSection>,
+
+    #[serde(default)]
+    service: BTreeMap<String, ServiceSection>,
+
+    #[serde(default)]
+    cron: BTreeMap<String, CronSection>,
+
+    #[serde(default)]
+    tail: BTreeMap<String, TailSection>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogConfigSection {
+    level: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyslogSection {
+    listen: Option<bool>,
+    socket: Option<PathBuf>,
+    forward: Option<ForwardSection>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ControlSection {
+    enabled: Option<bool>,
+    socket: Option<PathBuf>,
+    logs: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatusSection {
+    signal: Option<String>,
+    file: Option<PathBuf>,
+    format: Option<String>,
+    interval: Option<Interval>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HealthSection {
+    failed_runs: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReloadSection {
+    watch: Option<bool>,
+    debounce: Option<Interval>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MetricsSection {
+    listen: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ForwardSection {
+    address: String,
+    queue_size: Option<usize>,
+    facility: Option<String>,
+    hostname: Option<String>,
+    services: Option<bool>,
+    events: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceSection {
+    command: Option<String>,
+    log: Option<LogSection>,
+    required: Option<bool>,
+    enabled: Option<bool>,
+    foreground: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CronSection {
+    schedule: Option<String>,
+    command: Option<String>,
+    log: Option<LogSection>,
+    critical: Option<bool>,
+    /// `false` drops the entry, one from an earlier config path included.
+    enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TailSection {
+    path: PathBuf,
+    prefix: Option<String>,
+    from_start: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogSection {
+    stdout: Option<bool>,
+    file: Option<PathBuf>,
+    max_size: Option<Size>,
+    max_files: Option<u32>,
+    compress: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Interval {
+    Seconds(u64),
+    Text(String),
+}
+
+pub(super) fn apply(path: &Path, config: &mut Config) -> Result<(), WingmateInitError> {
+    let wrap = |e: ConfigFileError| {
+        WingmateInitError::ConfigFile { path: String::from(path.to_string_lossy()), source: e }
+    };
+
+    let content = fs::read_to_string(path).map_err(|e| { wrap(ConfigFileError::Read { source: e }) })?;
+    let file: ConfigFile = toml::from_str(&content).map_err(|e| { wrap(ConfigFileError::Parse { source: e }) })?;
+
+    if let Some(log) = file.log {
+        if let Some(level) = log.level {
+            config.log.level = Some(LevelFilter::from_str(&level)
+                .map_err(|_| { wrap(ConfigFileError::InvalidValue { key: String::from("log.level"), value: level.clone() }) })?);
+        }
+        if let Some(format) = log.format {
+            config.log.format = Some(LogFormat::from_str(&format)
+                .map_err(|_| { wrap(ConfigFileError::InvalidValue { key: String::from("log.format"), value: format.clone() }) })?);
+        }
+    }
+
+    if let Some(syslog) = file.syslog {
+        if let Some(listen) = syslog.listen {
+            config.syslog.listen = listen;
+        }
+        if let Some(socket) = syslog.socket {
+            config.syslog.socket = socket;
+        }
+        if let Some(forward) = syslog.forward {
+            config.syslog.forward = Some(to_forward(forward).map_err(wrap)?);
+        }
+    }
+
+    if let Some(control) = file.control {
+        if let Some(enabled) = control.enabled {
+            config.control.enabled = enabled;
+        }
+        if let Some(logs) = control.logs {
+            config.control.logs = logs;
+        }
+        if let Some(socket) = control.socket {
+            config.control.socket = socket;
+        }
+    }
+
+    if let Some(status) = file.status {
+        config.status = to_status(status, config.status.clone()).map_err(wrap)?;
+    }
+
+    if let Some(reload) = file.reload {
+        if let Some(watch) = reload.watch {
+            config.reload.watch = watch;
+        }
+        if let Some(debounce) = reload.debounce {
+            let secs = match debounce {
+                Interval::Seconds(s) => s,
+                Interval::Text(t) => parse_interval(&t).ok_or_else(|| {
+                    wrap(ConfigFileError::InvalidValue { key: String::from("reload.debounce"), value: t.clone() })
+                })?,
+            };
+            config.reload.debounce = Duration::from_secs(secs);
+        }
+    }
+
+    if let Some(health) = file.health {
+        if let Some(failed_runs) = health.failed_runs {
+            if failed_runs == 0 {
+                return Err(wrap(ConfigFileError::InvalidValue { key: String::from("health.failed_runs"), value: String::from("0") }));
+            }
+            config.health.failed_runs = failed_runs;
+        }
+    }
+
+    if let Some(metrics) = file.metrics {
+        let listen = match metrics.listen.strip_prefix("unix://") {
+            Some(path) => MetricsAddress::Unix(PathBuf::from(path)),
+            None if metrics.listen.contains(':') => MetricsAddress::Tcp(metrics.listen.clone()),
+            None => return Err(wrap(ConfigFileError::InvalidValue { key: String::from("metrics.listen"), value: metrics.listen })),
+        };
+        config.metrics = Some(MetricsConfig { listen });
+    }
+
+    for (name, section) in file.service {
+        let idx = match section.command {
+            Some(command) => {
+                let svc = Service::new(name.clone(), Command::Inline(command));
+                match config.services.iter().position(|s| s.name == name) {
+                    Some(i) => {
+                        config.services[i] = svc;
+                        i
+                    },
+                    None => {
+                        config.services.push(svc);
+                        config.services.len() - 1
+                    }
+                }
+            },
+            None => {
+                config.services.iter().position(|s| s.name == name)
+                    .ok_or_else(|| { wrap(ConfigFileError::UnknownService(name.clone())) })?
+            }
+        };
+
+        if let Some(log) = section.log {
+            config.services[idx].output = to_output(&format!("service.{}.log", name), log).map_err(wrap)?;
+        }
+        if let Some(required) = section.required {
+            config.services[idx].required = required;
+        }
+        if let Some(enabled) = section.enabled {
+            config.services[idx].enabled = enabled;
+        }
+        if let Some(foreground) = section.foreground {
+            config.services[idx].foreground = foreground;
+        }
+    }
+
+    for (name, section) in file.cron {
+        let idx = match (section.schedule, section.command) {
+            (Some(schedule), Some(command)) => {
+                let mut cron = Config::parse_cron_line(&format!("{} {}", schedule, command))
+                    .map_err(|e| { WingmateInitError::Cron { source: e } })?;
+                cron.name = name.clone();
+                match config.cron.iter().position(|c| c.name == name) {
+                    Some(i) => {
+                        config.cron[i] = cron;
+                        i
+                    },
+                    None => {
+                        config.cron.push(cron);
+                        config.cron.len() - 1
+                    }
+                }
+            },
+            (None, None) => {
+                config.cron.iter().position(|c| c.name == name)
+                    .ok_or_else(|| { wrap(ConfigFileError::UnknownCron(name.clone())) })?
+            },
+            _ => {
+                return Err(wrap(ConfigFileError::IncompleteCron(name)));
+            }
+        };
+
+        if section.enabled == Some(false) {
+            config.cron.remove(idx);
+            continue;
+        }
+        if let Some(log) = section.log {
+            config.cron[idx].output = to_output(&format!("cron.{}.log", name), log).map_err(wrap)?;
+        }
+        if let Some(critical) = section.critical {
+            config.cron[idx].critical = critical;
+        }
+    }
+
+    for (name, section) in file.tail {
+        let tail = to_tail(&name, section).map_err(wrap)?;
+        match config.tails.iter().position(|t| t.name == name) {
+            Some(i) => config.tails[i] = tail,
+            None => config.tails.push(tail),
+        }
+    }
+
+    Ok(())
+}
+
+fn to_tail(name: &str, section: TailSection) -> Result<Tail, ConfigFileError> {
+    let invalid = || {
+        ConfigFileError::InvalidValue { key: format!("tail.{}.path", name), value: String::from(section.path.to_string_lossy()) }
+    };
+
+    let pattern = section.path.file_name().ok_or_else(invalid)?;
+    let directory = section.path.parent().ok_or_else(invalid)?;
+    let has_wildcard = |s: &str| { s.contains('*') || s.contains('?') };
+    if !section.path.is_absolute() || has_wildcard(&directory.to_string_lossy()) {
+        return Err(invalid());
+    }
+
+    Ok(Tail {
+        name: String::from(name),
+        directory: directory.to_path_buf(),
+        pattern: String::from(pattern.to_string_lossy()),
+        prefix: section.prefix.clone().unwrap_or_else(|| { String::from(name) }),
+        from_start: section.from_start.unwrap_or(false),
+    })
+}
+
+fn to_status(section: StatusSection, mut status: StatusConfig) -> Result<StatusConfig, ConfigFileError> {
+    let invalid = |key: &str, value: &str| {
+        ConfigFileError::InvalidValue { key: format!("status.{}", key), value: String::from(value) }
+    };
+
+    if let Some(signal) = section.signal {
+        status.signal = config::parse_signal(&signal)
+            .filter(|s| { !RESERVED_SIGNALS.contains(s) })
+            .ok_or_else(|| { invalid("signal", &signal) })?;
+    }
+    if let Some(file) = section.file {
+        status.file = Some(file);
+    }
+    if let Some(format) = section.format {
+        status.format = LogFormat::from_str(&format).map_err(|_| { invalid("format", &format) })?;
+    }
+    if let Some(interval) = section.interval {
+        let secs = match interval {
+            Interval::Seconds(s) => s,
+            Interval::Text(t) => parse_interval(&t).ok_or_else(|| { invalid("interval", &t) })?,
+        };
+        // zero turns the refresh off
+        status.interval = if secs == 0 { None } else { Some(Duration::from_secs(secs)) };
+    }
+
+    Ok(status)
+}
+
+/// `key` names the section for errors, such as `service.web.log`.
+fn to_output(key: &str, log: LogSection) -> Result<Output, ConfigFileError> {
+    let file = match log.file {
+        Some(path) => {
+            let max_size = match log.max_size {
+                Some(size) => {
+                    let value = match &size {
+                        Size::Bytes(b) => b.to_string(),
+                        Size::Text(t) => t.clone(),
+                    };
+                    // a limit of nothing would rotate before every line
+                    match parse_size(size)? {
+                        0 => return Err(ConfigFileError::InvalidValue { key: format!("{}.max_size", key), value }),
+                        n => n,
+                    }
+                },
+                None => DEFAULT_LOG_MAX_SIZE,
+            };
+            Some(LogFile {
+                path,
+                max_size,
+                max_files: log.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES),
+                compress: log.compress.unwrap_or(false),
+            })
+        },
+        None => None,
+    };
+
+    Ok(Output {
+        stdout: log.stdout.unwrap_or(true),
+        file,
+    })
+}
+
+fn to_forward(section: ForwardSection) -> Result<SyslogForward, ConfigFileError> {
+    let invalid = |key: &str, value: &str| {
+        ConfigFileError::InvalidValue { key: format!("syslog.forward.{}", key), value: String::from(value) }
+    };
+
+    let address = if let Some(a) = section.address.strip_prefix("udp://") {
+        ForwardAddress::Udp(String::from(a))
+    } else if let Some(a) = section.address.strip_prefix("tcp://") {
+        ForwardAddress::Tcp(String::from(a))
+    } else if let Some(a) = section.address.strip_prefix("unix://") {
+        ForwardAddress::Unix(PathBuf::from(a))
+    } else {
+        return Err(invalid("address", &section.address));
+    };
+
+    let facility = match section.facility {
+        Some(f) => parse_facility(&f).ok_or_else(|| { invalid("facility", &f) })?,
+        None => DEFAULT_FORWARD_FACILITY,
+    };
+
+    let queue_size = section.queue_size.unwrap_or(DEFAULT_FORWARD_QUEUE_SIZE);
+    if queue_size == 0 {
+        return Err(invalid("queue_size", "0"));
+    }
+
+    Ok(SyslogForward {
+        address,
+        queue_size,
+        facility,
+        hostname: section.hostname,
+        services: section.services.unwrap_or(true),
+        events: section.events.unwrap_or(true),
+    })
+}
+
+fn parse_facility(name: &str) -> Option<u8> {
+    const FACILITIES: [&str; 24] = [
+        "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news",
+        "uucp", "cron", "authpriv", "ftp", "ntp", "security", "console", "solaris-cron",
+        "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+    ];
+
+    if let Ok(n) = name.parse::<u8>() {
+        return if n < 24 { Some(n) } else { None };
+    }
+
+    FACILITIES.iter().position(|f| { *f == name }).map(|p| { p as u8 })
+}
+
+/// Accepts a number of seconds with an optional `s`, `m` or `h` suffix.
+fn parse_interval(text: &str) -> Option<u64> {
+    let trimmed = text.trim();
+    let (digits, multiplier) = match trimmed.char_indices().last() {
+        Some((i, 's')) => (&trimmed[..i], 1u64),
+        Some((i, 'm')) => (&trimmed[..i], 60u64),
+        Some((i, 'h')) => (&trimmed[..i], 3600u64),
+        _ => (trimmed, 1u64),
+    };
+
+    digits.trim().parse::<u64>().ok().and_then(|n| { n.checked_mul(multiplier) })
+}
+
+fn parse_size(size: Size) -> Result<u64, ConfigFileError> {
+    let text = match size {
+        Size::Bytes(b) => return Ok(b),
+        Size::Text(t) => t,
+    };
+
+    let trimmed = text.trim();
+    let (digits, multiplier) = match trimmed.char_indices().last() {
+        Some((i, 'k')) | Some((i, 'K')) => (&trimmed[..i], 1024u64),
+        Some((i, 'm')) | Some((i, 'M')) => (&trimmed[..i], 1024u64 * 1024),
+        Some((i, 'g')) | Some((i, 'G')) => (&trimmed[..i], 1024u64 * 1024 * 1024),
+        _ => (trimmed, 1u64),
+    };
+
+    digits.trim().parse::<u64>().ok()
+        .and_then(|n| { n.checked_mul(multiplier) })
+        .ok_or(ConfigFileError::InvalidSize(text.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Size {
+        Size::Text(String::from(s))
+    }
+
+    #[test]
+    fn size_suffixes() {
+        assert_eq!(parse_size(Size::Bytes(1500)).unwrap(), 1500);
+        assert_eq!(parse_size(text("1500")).unwrap(), 1500);
+        assert_eq!(parse_size(text("4k")).unwrap(), 4 * 1024);
+        assert_eq!(parse_size(text("4K")).unwrap(), 4 * 1024);
+        assert_eq!(parse_size(text(" 10 M ")).unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size(text("2g")).unwrap(), 2 * 1024 * 1024 * 1024);
+        for bad in ["", "k", "10x", "1.5M", "-1k", "99999999999G"] {
+            assert!(matches!(parse_size(text(bad)), Err(ConfigFileError::InvalidSize(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn log_max_size() {
+        let log = |max_size: &str| {
+            let section: LogSection = toml::from_str(&format!("file = \"/var/log/web.log\"\nmax_size = {}", max_size)).unwrap();
+            to_output("service.web.log", section)
+        };
+        let output = log("\"1M\"").unwrap();
+        assert_eq!(output.file.unwrap().max_size, 1024 * 1024);
+        for zero in ["0", "\"0\"", "\"0k\""] {
+            match log(zero) {
+                Err(ConfigFileError::InvalidValue { key, .. }) => assert_eq!(key, "service.web.log.max_size"),
+                other => panic!("{}: {:?}", zero, other),
+            }
+        }
+    }
+}
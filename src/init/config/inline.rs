@@ -0,0 +1,134 @@
+use std::path::Path;
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::init::config::{self, Config, Command, InlineConfig, Problem, Service};
+use crate::init::error::WingmateInitError;
+
+// a name with nothing after it still counts as named, so it fails as empty
+const NAMED_REGEX_STR: &str = r"^(?P<name>[A-Za-z0-9_-]+)\s*:\s*(?P<command>.*?)\s*$";
+const CRON_NAME_PREFIX: &str = "cmdline:";
+
+/// Adds the services and cron entries given on the command line, replacing
+/// same-named ones. Both may be named with a `name: ` prefix, as in a
+/// Procfile. Otherwise a service is named after its program and a cron entry
+/// after its position. With `problems`, a definition that does not parse is
+/// recorded there and skipped.
+pub(super) fn apply(inline: &InlineConfig, config: &mut Config, mut problems: Option<&mut Vec<Problem>>) -> Result<(), WingmateInitError> {
+    let mut names: Vec<String> = Vec::new();
+    for definition in inline.services.iter() {
+        let (name, command) = match split_name(definition) {
+            Some((name, command)) => (name, command),
+            None => {
+                let program = definition.split_whitespace().next().unwrap_or_default();
+                let base = Path::new(program).file_name().map(|n| { String::from(n.to_string_lossy()) }).unwrap_or_default();
+                let mut name = base.clone();
+                let mut n = 1;
+                while names.contains(&name) {
+                    n += 1;
+                    name = format!("{}-{}", base, n);
+                }
+                (name, String::from(definition.trim()))
+            }
+        };
+        if name.is_empty() || command.is_empty() {
+            config::collect(&mut problems, Err(WingmateInitError::InlineDefinition(definition.clone())))?;
+            continue;
+        }
+        names.push(name.clone());
+
+        let svc = Service::new(name, Command::Inline(command));
+        match config.services.iter().position(|s| { s.name == svc.name }) {
+            Some(i) => config.services[i] = svc,
+            None => config.services.push(svc),
+        }
+    }
+
+    for (idx, definition) in inline.cron.iter().enumerate() {
+        let (name, line) = split_name(definition).unwrap_or_else(|| {
+            (format!("{}{}", CRON_NAME_PREFIX, idx + 1), definition.clone())
+        });
+        let mut cron = match Config::parse_cron_line(&line) {
+            Ok(cron) => cron,
+            Err(e) => {
+                config::collect(&mut problems, Err(WingmateInitError::Cron { source: e }))?;
+                continue;
+            }
+        };
+        cron.name = name;
+        match config.cron.iter().position(|c| { c.name == cron.name }) {
+            Some(i) => config.cron[i] = cron,
+            None => config.cron.push(cron),
+        }
+    }
+
+    Ok(())
+}
+
+fn split_name(definition: &str) -> Option<(String, String)> {
+    lazy_static! {
+        static ref NAMED_REGEX: Regex = Regex::new(NAMED_REGEX_STR).unwrap();
+    }
+
+    NAMED_REGEX.captures(definition.trim()).map(|cap| { (String::from(&cap["name"]), String::from(&cap["command"])) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::config::SearchMode;
+
+    fn inline(services: &[&str], cron: &[&str]) -> InlineConfig {
+        InlineConfig {
+            services: services.iter().map(|s| { String::from(*s) }).collect(),
+            cron: cron.iter().map(|s| { String::from(*s) }).collect(),
+            main: None,
+        }
+    }
+
+    fn empty() -> Config {
+        Config::new(vec![String::from("/nonexistent")], SearchMode::FirstMatch, InlineConfig::default())
+    }
+
+    #[test]
+    fn named_and_unnamed_definitions() {
+        let mut config = empty();
+        let defs = inline(
+            &["web: serve --port 80", "/usr/bin/worker -q", "worker --other", "  sh -c 'echo a: b'  "],
+            &["0 3 * * * backup", "rotate: 30 3 * * * logrotate"],
+        );
+        apply(&defs, &mut config, None).unwrap();
+
+        let services: Vec<(&str, &Command)> = config.services.iter().map(|s| { (s.name.as_str(), &s.command) }).collect();
+        assert_eq!(services, [
+            ("web", &Command::Inline(String::from("serve --port 80"))),
+            ("worker", &Command::Inline(String::from("/usr/bin/worker -q"))),
+            ("worker-2", &Command::Inline(String::from("worker --other"))),
+            ("sh", &Command::Inline(String::from("sh -c 'echo a: b'"))),
+        ]);
+        let cron: Vec<(&str, &str)> = config.cron.iter().map(|c| { (c.name.as_str(), c.command.as_str()) }).collect();
+        assert_eq!(cron, [("cmdline:1", "backup"), ("rotate", "logrotate")]);
+    }
+
+    #[test]
+    fn replaces_same_named_entries() {
+        let mut config = empty();
+        config.services.push(Service::new(String::from("web"), Command::Direct(String::from("/srv/web"))));
+        apply(&inline(&["web: serve"], &[]), &mut config, None).unwrap();
+        assert_eq!(config.services.len(), 1);
+        assert_eq!(config.services[0].command, Command::Inline(String::from("serve")));
+    }
+
+    #[test]
+    fn invalid_definitions() {
+        for (services, cron) in [(vec!["web:"], vec![]), (vec!["   "], vec![]), (vec![], vec!["* * * * true"]), (vec![], vec!["job: 61 * * * * true"])] {
+            assert!(apply(&inline(&services, &cron), &mut empty(), None).is_err(), "{:?} {:?}", services, cron);
+        }
+
+        let mut config = empty();
+        let mut problems: Vec<Problem> = Vec::new();
+        apply(&inline(&["web:", "ok: serve"], &["* * * * true", "0 * * * * date"]), &mut config, Some(&mut problems)).unwrap();
+        assert_eq!(problems.len(), 2);
+        assert_eq!(config.services.len(), 1);
+        assert_eq!(config.cron.len(), 1);
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::init::config::{Config, Command, Service};
+use crate::init::error::{WingmateInitError, ConfigFileError};
+
+const PROCFILE_REGEX_STR: &str = r"^(?P<name>[A-Za-z0-9_-]+)\s*:\s*(?P<command>\S.*?)\s*$";
+const ENV_FILE_NAME: &str = ".env";
+const EXPORT_PREFIX: &str = "export ";
+
+/// Reads a Procfile of `name: command` lines into services run through the
+/// shell. A `.env` file next to it sets the environment of these services,
+/// as foreman does.
+pub(super) fn apply(path: &Path, config: &mut Config) -> Result<(), WingmateInitError> {
+    let wrap = |p: &Path, e: ConfigFileError| {
+        WingmateInitError::ConfigFile { path: String::from(p.to_string_lossy()), source: e }
+    };
+
+    let env_path = path.parent().unwrap_or(Path::new(".")).join(ENV_FILE_NAME);
+    let environment = match fs::read_to_string(&env_path) {
+        Ok(content) => parse_env(&content).map_err(|e| { wrap(&env_path, e) })?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(wrap(&env_path, ConfigFileError::Read { source: e })),
+    };
+
+    let content = fs::read_to_string(path).map_err(|e| { wrap(path, ConfigFileError::Read { source: e }) })?;
+    for (name, command) in parse(&content).map_err(|e| { wrap(path, e) })? {
+        let mut svc = Service::new(name, Command::Inline(command));
+        svc.environment = environment.clone();
+
+        match config.services.iter().position(|s| { s.name == svc.name }) {
+            Some(i) => config.services[i] = svc,
+            None => config.services.push(svc),
+        }
+    }
+
+    Ok(())
+}
+
+/// The `name: command` lines of a Procfile, skipping blank lines and comments.
+fn parse(content: &str) -> Result<Vec<(String, String)>, ConfigFileError> {
+    lazy_static! {
+        static ref PROCFILE_REGEX: Regex = Regex::new(PROCFILE_REGEX_STR).unwrap();
+    }
+
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let cap = PROCFILE_REGEX.captures(trimmed).ok_or_else(|| {
+            ConfigFileError::Syntax { line: idx + 1, message: format!("expected name: command, got \"{}\"", trimmed) }
+        })?;
+        entries.push((String::from(&cap["name"]), String::from(&cap["command"])));
+    }
+
+    Ok(entries)
+}
+
+/// `KEY=value` lines with an optional `export`, comments and single or
+/// double quotes; double quotes understand `\n` and `\"`.
+pub(super) fn parse_env(content: &str) -> Result<Vec<(String, Option<String>)>, ConfigFileError> {
+    let mut environment: Vec<(String, Option<String>)> = Vec::new();
+
+    for (idx, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let trimmed = trimmed.strip_prefix(EXPORT_PREFIX).unwrap_or(trimmed);
+        let syntax = |message: &str| { ConfigFileError::Syntax { line: idx + 1, message: String::from(message) } };
+
+        let (key, raw) = trimmed.split_once('=').ok_or_else(|| { syntax("expected KEY=value") })?;
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(syntax("invalid variable name"));
+        }
+
+        let raw = raw.trim();
+        let value = if let Some(quoted) = raw.strip_prefix('"') {
+            let end = quoted.rfind('"').ok_or_else(|| { syntax("unterminated double quote") })?;
+            unescape(&quoted[..end])
+        } else if let Some(quoted) = raw.strip_prefix('\'') {
+            let end = quoted.rfind('\'').ok_or_else(|| { syntax("unterminated single quote") })?;
+            String::from(&quoted[..end])
+        } else {
+            match raw.find(" #") {
+                Some(i) => String::from(raw[..i].trim_end()),
+                None => String::from(raw),
+            }
+        };
+
+        match environment.iter().position(|(k, _)| { k == key }) {
+            Some(i) => environment[i].1 = Some(value),
+            None => environment.push((String::from(key), Some(value))),
+        }
+    }
+
+    Ok(environment)
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(key: &str, value: &str) -> (String, Option<String>) {
+        (String::from(key), Some(String::from(value)))
+    }
+
+    #[test]
+    fn procfile_lines() {
+        let entries = parse("# processes\n\nweb: bundle exec puma -p $PORT  \nworker_2 :sh -c 'echo a: b'\n  # indented comment\n").unwrap();
+        assert_eq!(entries, vec![
+            (String::from("web"), String::from("bundle exec puma -p $PORT")),
+            (String::from("worker_2"), String::from("sh -c 'echo a: b'")),
+        ]);
+    }
+
+    #[test]
+    fn procfile_rejects_invalid() {
+        for content in ["web", "web:", "web:   ", "my web: serve", ": serve", "wéb: serve"] {
+            assert!(matches!(parse(content), Err(ConfigFileError::Syntax { line: 1, .. })), "{}", content);
+        }
+        assert!(matches!(parse("web: serve\nbroken\n"), Err(ConfigFileError::Syntax { line: 2, .. })));
+    }
+
+    #[test]
+    fn env_quoting_and_comments() {
+        let env = parse_env("# settings\nexport A=1\nB = plain value # comment\nC=\"line\\nnext \\\"q\\\" # kept\"\n\
+            D='no \\n escapes # kept'\nE=\nF=a#b\nA=2\n").unwrap();
+        assert_eq!(env, vec![
+            var("A", "2"),
+            var("B", "plain value"),
+            var("C", "line\nnext \"q\" # kept"),
+            var("D", "no \\n escapes # kept"),
+            var("E", ""),
+            var("F", "a#b"),
+        ]);
+    }
+
+    #[test]
+    fn env_rejects_invalid() {
+        for content in ["NOVALUE", "=1", "MY VAR=1", "A=\"open", "A='open"] {
+            assert!(matches!(parse_env(content), Err(ConfigFileError::Syntax { line: 1, .. })), "{}", content);
+        }
+    }
+}
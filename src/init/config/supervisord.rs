@@ -0,0 +1,452 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use regex::{Captures, Regex};
+use crate::init::config::{self, parse_bool, Config, Command, Service, Output, LogFile, Restart, DEFAULT_LOG_MAX_SIZE, DEFAULT_LOG_MAX_FILES};
+use crate::init::error::{WingmateInitError, ConfigFileError};
+
+const PROGRAM_PREFIX: &str = "program:";
+const EXPANSION_REGEX_STR: &str = r"%\((?P<key>[A-Za-z0-9_]+)\)(?P<width>0?\d*)(?P<kind>[sd])";
+const ENV_EXPANSION_PREFIX: &str = "ENV_";
+
+// supervisord's own defaults, which differ from wingmate's
+const DEFAULT_START_RETRIES: u32 = 3;
+const DEFAULT_STOP_WAIT_SECS: u64 = 10;
+
+/// Sections that only configure supervisord itself.
+const IGNORED_SECTIONS: [&str; 5] = ["supervisord", "supervisorctl", "unix_http_server", "inet_http_server", "rpcinterface:"];
+
+struct Section {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+/// Reads the `[program:x]` sections of a supervisord configuration, and of
+/// the files its `[include]` section names, into services. Keys wingmate has
+/// no equivalent for are reported and skipped.
+pub(super) fn apply(path: &Path, config: &mut Config) -> Result<(), WingmateInitError> {
+    let sections = read(path)?;
+    let here = path.parent().unwrap_or(Path::new("."));
+
+    let mut included: Vec<PathBuf> = Vec::new();
+    for section in sections.iter() {
+        if section.name == "include" {
+            for (key, value) in section.entries.iter() {
+                match key.as_str() {
+                    "files" => included.extend(glob_files(here, value)),
+                    _ => unsupported(path, &section.name, key),
+                }
+            }
+        }
+    }
+
+    apply_sections(path, sections, config)?;
+    for p in included {
+        // includes nest only one level deep, as in supervisord
+        let sections = read(&p)?;
+        apply_sections(&p, sections, config)?;
+    }
+
+    Ok(())
+}
+
+fn apply_sections(path: &Path, sections: Vec<Section>, config: &mut Config) -> Result<(), WingmateInitError> {
+    let wrap = |e: ConfigFileError| {
+        WingmateInitError::ConfigFile { path: String::from(path.to_string_lossy()), source: e }
+    };
+
+    for section in sections {
+        if let Some(program) = section.name.strip_prefix(PROGRAM_PREFIX) {
+            for svc in to_services(path, program, &section).map_err(wrap)? {
+                match config.services.iter().position(|s| { s.name == svc.name }) {
+                    Some(i) => config.services[i] = svc,
+                    None => config.services.push(svc),
+                }
+            }
+        } else if section.name == "include" || IGNORED_SECTIONS.iter().any(|s| { section.name.starts_with(s) }) {
+            debug!("{}: skipping [{}]", path.to_string_lossy(), section.name);
+        } else {
+            warn!("{}: [{}] is not supported, ignoring it", path.to_string_lossy(), section.name);
+        }
+    }
+
+    Ok(())
+}
+
+fn to_services(path: &Path, program: &str, section: &Section) -> Result<Vec<Service>, ConfigFileError> {
+    let invalid = |key: &str, value: &str| {
+        ConfigFileError::InvalidValue { key: format!("program:{}.{}", program, key), value: String::from(value) }
+    };
+    let get = |key: &str| { section.entries.iter().rev().find(|(k, _)| { k == key }).map(|(_, v)| { v.as_str() }) };
+
+    let numprocs = match get("numprocs") {
+        Some(n) => n.parse::<u32>().ok().filter(|n| { *n > 0 }).ok_or_else(|| { invalid("numprocs", n) })?,
+        None => 1,
+    };
+    let numprocs_start = match get("numprocs_start") {
+        Some(n) => n.parse::<u32>().map_err(|_| { invalid("numprocs_start", n) })?,
+        None => 0,
+    };
+    let process_name = match get("process_name") {
+        Some(p) => p,
+        None if numprocs > 1 => "%(program_name)s_%(process_num)02d",
+        None => "%(program_name)s",
+    };
+    let command = get("command").ok_or_else(|| { invalid("command", "") })?;
+    let here = path.parent().map(|p| { p.to_string_lossy().into_owned() }).unwrap_or_default();
+
+    for (key, _) in section.entries.iter() {
+        if !matches!(key.as_str(), "command" | "process_name" | "numprocs" | "numprocs_start" | "directory" | "user"
+            | "environment" | "autostart" | "autorestart" | "startsecs" | "startretries" | "stopsignal"
+            | "stopwaitsecs" | "stdout_logfile") {
+            unsupported(path, &section.name, key);
+        }
+    }
+
+    let end = numprocs_start.checked_add(numprocs).ok_or_else(|| { invalid("numprocs_start", &numprocs_start.to_string()) })?;
+    let mut services: Vec<Service> = Vec::new();
+    for num in numprocs_start..end {
+        let expand = |value: &str| { expand(value, program, num, numprocs, &here, |name| { env::var(name).ok() }) };
+
+        let mut svc = Service::new(expand(process_name), Command::Inline(expand(command)));
+        svc.restart = Restart::OnFailure;
+        svc.start_retries = Some(DEFAULT_START_RETRIES);
+        svc.stop_timeout = Duration::from_secs(DEFAULT_STOP_WAIT_SECS);
+
+        if let Some(dir) = get("directory") {
+            svc.directory = Some(PathBuf::from(expand(dir)));
+        }
+        if let Some(user) = get("user") {
+            svc.user = Some(String::from(user));
+        }
+        if let Some(environment) = get("environment") {
+            svc.environment = parse_environment(&expand(environment)).ok_or_else(|| { invalid("environment", environment) })?;
+        }
+        if let Some(autostart) = get("autostart") {
+            svc.enabled = parse_bool(autostart).ok_or_else(|| { invalid("autostart", autostart) })?;
+        }
+        if let Some(autorestart) = get("autorestart") {
+            svc.restart = match autorestart.to_ascii_lowercase().as_str() {
+                "unexpected" => Restart::OnFailure,
+                other => match parse_bool(other) {
+                    Some(true) => Restart::Always,
+                    Some(false) => Restart::Never,
+                    None => return Err(invalid("autorestart", autorestart)),
+                },
+            };
+        }
+        if let Some(secs) = get("startsecs") {
+            svc.start_secs = Duration::from_secs(secs.parse().map_err(|_| { invalid("startsecs", secs) })?);
+        }
+        if let Some(retries) = get("startretries") {
+            svc.start_retries = Some(retries.parse().map_err(|_| { invalid("startretries", retries) })?);
+        }
+        if let Some(signal) = get("stopsignal") {
+            svc.stop_signal = config::parse_signal(signal).ok_or_else(|| { invalid("stopsignal", signal) })?;
+        }
+        if let Some(secs) = get("stopwaitsecs") {
+            svc.stop_timeout = Duration::from_secs(secs.parse().map_err(|_| { invalid("stopwaitsecs", secs) })?);
+        }
+        if let Some(logfile) = get("stdout_logfile") {
+            svc.output = to_output(&expand(logfile));
+        }
+
+        services.push(svc);
+    }
+
+    Ok(services)
+}
+
+/// `AUTO` has no equivalent and keeps the default of forwarding to stdout.
+/// A file receives stderr as well, since wingmate keeps one output per service.
+fn to_output(logfile: &str) -> Output {
+    match logfile {
+        "/dev/stdout" | "/dev/fd/1" | "/proc/self/fd/1" | "AUTO" => Output::default(),
+        "NONE" => Output { stdout: false, file: None },
+        path => Output {
+            stdout: false,
+            file: Some(LogFile {
+                path: PathBuf::from(path),
+                max_size: DEFAULT_LOG_MAX_SIZE,
+                max_files: DEFAULT_LOG_MAX_FILES,
+                compress: false,
+            }),
+        },
+    }
+}
+
+fn unsupported(path: &Path, section: &str, key: &str) {
+    warn!("{}: [{}] {} is not supported, ignoring it", path.to_string_lossy(), section, key);
+}
+
+fn read(path: &Path) -> Result<Vec<Section>, WingmateInitError> {
+    let wrap = |e: ConfigFileError| {
+        WingmateInitError::ConfigFile { path: String::from(path.to_string_lossy()), source: e }
+    };
+    let content = fs::read_to_string(path).map_err(|e| { wrap(ConfigFileError::Read { source: e }) })?;
+    parse(&content).map_err(wrap)
+}
+
+/// Parses the ini dialect of supervisord: `key = value` or `key: value`,
+/// comments starting with `;` or `#`, inline comments after ` ;` and
+/// indented continuation lines.
+fn parse(content: &str) -> Result<Vec<Section>, ConfigFileError> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (idx, raw) in content.lines().enumerate() {
+        let line_no = idx + 1;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+            continue;
+        }
+        let line = match trimmed.find(" ;").or_else(|| { trimmed.find("\t;") }) {
+            Some(i) => trimmed[..i].trim_end(),
+            None => trimmed,
+        };
+
+        if raw.starts_with([' ', '\t']) {
+            let last = sections.last_mut().and_then(|s| { s.entries.last_mut() });
+            match last {
+                Some((_, value)) => {
+                    value.push('\n');
+                    value.push_str(line);
+                    continue;
+                },
+                None => return Err(ConfigFileError::Syntax { line: line_no, message: String::from("continuation line without a key") }),
+            }
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| { l.strip_suffix(']') }) {
+            sections.push(Section { name: String::from(name.trim()), entries: Vec::new() });
+            continue;
+        }
+
+        let split = line.find(['=', ':']).ok_or_else(|| {
+            ConfigFileError::Syntax { line: line_no, message: format!("expected key = value, got \"{}\"", line) }
+        })?;
+        let section = sections.last_mut().ok_or_else(|| {
+            ConfigFileError::Syntax { line: line_no, message: String::from("key outside of a section") }
+        })?;
+        let key = line[..split].trim().to_ascii_lowercase();
+        let value = String::from(line[split + 1..].trim());
+        section.entries.push((key, value));
+    }
+
+    Ok(sections)
+}
+
+/// Expands `%(name)s` style references the way supervisord does for the
+/// values it knows about, looking `%(ENV_x)s` up with `env`.
+fn expand(value: &str, program: &str, num: u32, numprocs: u32, here: &str, env: impl Fn(&str) -> Option<String>) -> String {
+    lazy_static! {
+        static ref EXPANSION_REGEX: Regex = Regex::new(EXPANSION_REGEX_STR).unwrap();
+    }
+
+    EXPANSION_REGEX.replace_all(value, |cap: &Captures| {
+        let key = &cap["key"];
+        let text = match key {
+            "program_name" | "group_name" => String::from(program),
+            "process_num" => num.to_string(),
+            "numprocs" => numprocs.to_string(),
+            "here" => String::from(here),
+            k if k.starts_with(ENV_EXPANSION_PREFIX) => env(&k[ENV_EXPANSION_PREFIX.len()..]).unwrap_or_default(),
+            _ => {
+                warn!("unknown expansion {} in \"{}\"", &cap[0], value);
+                return String::from(&cap[0]);
+            },
+        };
+
+        let width: usize = cap["width"].parse().unwrap_or(0);
+        if cap["width"].starts_with('0') {
+            format!("{:0>width$}", text, width = width)
+        } else {
+            format!("{:>width$}", text, width = width)
+        }
+    }).into_owned()
+}
+
+/// `KEY="value",OTHER=value` with optional single or double quotes.
+fn parse_environment(text: &str) -> Option<Vec<(String, Option<String>)>> {
+    let mut environment: Vec<(String, Option<String>)> = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| { c.is_whitespace() || *c == ',' }) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let key: String = chars.by_ref().take_while(|c| { *c != '=' }).collect();
+        let key = key.trim();
+        if key.is_empty() {
+            return None;
+        }
+
+        let mut value = String::new();
+        match chars.peek() {
+            Some(q) if *q == '"' || *q == '\'' => {
+                let quote = *q;
+                chars.next();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == quote {
+                        closed = true;
+                        break;
+                    }
+                    value.push(c);
+                }
+                while chars.peek().is_some_and(|c| { *c == ' ' || *c == '\t' }) {
+                    chars.next();
+                }
+                if !closed || chars.peek().is_some_and(|c| { *c != ',' }) {
+                    return None;
+                }
+            },
+            _ => {
+                while let Some(c) = chars.peek() {
+                    if *c == ',' {
+                        break;
+                    }
+                    value.push(*c);
+                    chars.next();
+                }
+                value = String::from(value.trim());
+            },
+        }
+
+        environment.push((String::from(key), Some(value)));
+    }
+
+    Some(environment)
+}
+
+/// The whitespace separated patterns of `[include] files`, relative to the
+/// including file, with wildcards in the file name part only.
+fn glob_files(here: &Path, patterns: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for pattern in patterns.split_whitespace() {
+        let full = here.join(pattern);
+        let (dir, name) = match (full.parent(), full.file_name()) {
+            (Some(d), Some(n)) => (d.to_path_buf(), n.to_string_lossy().into_owned()),
+            _ => continue,
+        };
+
+        let mut matched: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(entries) => entries.flatten()
+                .filter(|e| { config::glob_match(name.as_bytes(), e.file_name().as_encoded_bytes()) })
+                .map(|e| { e.path() })
+                .filter(|p| { p.is_file() })
+                .collect(),
+            Err(e) => {
+                debug!("cannot read {}: {}", dir.to_string_lossy(), e);
+                Vec::new()
+            }
+        };
+        matched.sort();
+        files.extend(matched);
+    }
+
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::signal::Signal;
+
+    fn section(name: &str, entries: &[(&str, &str)]) -> Section {
+        Section { name: String::from(name), entries: entries.iter().map(|(k, v)| { (String::from(*k), String::from(*v)) }).collect() }
+    }
+
+    #[test]
+    fn parse_comments_and_continuations() {
+        let sections = parse("; a comment\n# another\n[program:web]\nCommand = serve ; inline\nstopsignal: INT\n\
+            environment=A=1,\n  B=2\nurl=http://x/#frag;not-a-comment\n").unwrap();
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].name, "program:web");
+        assert_eq!(sections[0].entries, vec![
+            (String::from("command"), String::from("serve")),
+            (String::from("stopsignal"), String::from("INT")),
+            (String::from("environment"), String::from("A=1,\nB=2")),
+            (String::from("url"), String::from("http://x/#frag;not-a-comment")),
+        ]);
+    }
+
+    #[test]
+    fn parse_rejects_invalid() {
+        assert!(matches!(parse("  orphan\n"), Err(ConfigFileError::Syntax { line: 1, .. })));
+        assert!(matches!(parse("key = value\n"), Err(ConfigFileError::Syntax { line: 1, .. })));
+        assert!(matches!(parse("[program:x]\ncommand = a\nno separator\n"), Err(ConfigFileError::Syntax { line: 3, .. })));
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn expand_known_values() {
+        assert_eq!(expand("%(program_name)s_%(process_num)02d", "web", 3, 4, "/etc", no_env), "web_03");
+        assert_eq!(expand("%(here)s/%(numprocs)d", "web", 0, 4, "/etc", no_env), "/etc/4");
+        assert_eq!(expand("[%(process_num)3d]", "web", 7, 1, "", no_env), "[  7]");
+        assert_eq!(expand("%(nope)s and 100%", "web", 0, 1, "", no_env), "%(nope)s and 100%");
+    }
+
+    #[test]
+    fn expand_environment() {
+        let env = |name: &str| { (name == "PORT").then(|| { String::from("8080") }) };
+        assert_eq!(expand("--port %(ENV_PORT)s", "web", 0, 1, "", env), "--port 8080");
+        assert_eq!(expand("[%(ENV_UNSET)s]", "web", 0, 1, "", env), "[]");
+        assert_eq!(expand("[%(ENV_PORT)6s]", "web", 0, 1, "", env), "[  8080]");
+    }
+
+    #[test]
+    fn environment_quoting() {
+        let env = parse_environment("A=1, B=\"x, y\" ,C='say \"hi\"',D= spaced ").unwrap();
+        assert_eq!(env, vec![
+            (String::from("A"), Some(String::from("1"))),
+            (String::from("B"), Some(String::from("x, y"))),
+            (String::from("C"), Some(String::from("say \"hi\""))),
+            (String::from("D"), Some(String::from("spaced"))),
+        ]);
+        assert_eq!(parse_environment("").unwrap(), vec![]);
+        assert!(parse_environment("A=\"open").is_none());
+        assert!(parse_environment("A='it''s'").is_none());
+        assert!(parse_environment("=value").is_none());
+    }
+
+    #[test]
+    fn numprocs_services() {
+        let s = section("program:worker", &[("command", "work --id %(process_num)d"), ("numprocs", "2"),
+            ("numprocs_start", "1"), ("autorestart", "true"), ("stopsignal", "QUIT")]);
+        let services = to_services(Path::new("/etc/supervisord.conf"), "worker", &s).unwrap();
+        assert_eq!(services.len(), 2);
+        assert_eq!(services[0].name, "worker_01");
+        assert_eq!(services[1].name, "worker_02");
+        assert_eq!(services[1].command, Command::Inline(String::from("work --id 2")));
+        assert_eq!(services[0].restart, Restart::Always);
+        assert_eq!(services[0].stop_signal, Signal::SIGQUIT);
+        assert_eq!(services[0].start_retries, Some(DEFAULT_START_RETRIES));
+    }
+
+    #[test]
+    fn program_rejects_invalid() {
+        let path = Path::new("/etc/supervisord.conf");
+        for entries in [
+            vec![("numprocs", "2")],
+            vec![("command", "x"), ("numprocs", "0")],
+            vec![("command", "x"), ("numprocs", "2"), ("numprocs_start", "4294967295")],
+            vec![("command", "x"), ("autorestart", "maybe")],
+            vec![("command", "x"), ("startsecs", "-1")],
+            vec![("command", "x"), ("stopsignal", "NOPE")],
+            vec![("command", "x"), ("environment", "A=\"unclosed")],
+        ] {
+            let s = section("program:x", &entries);
+            assert!(matches!(to_services(path, "x", &s), Err(ConfigFileError::InvalidValue { .. })), "{:?}", entries);
+        }
+    }
+}
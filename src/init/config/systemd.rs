@@ -0,0 +1,574 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{debug, warn};
+use crate::init::config::{self, parse_bool, CalendarSpec, Config, Command, Problem, Service, ServiceType, Restart, Timer};
+use crate::init::config::procfile;
+use crate::init::error::{WingmateInitError, ConfigFileError};
+
+const SERVICE_SUFFIX: &str = ".service";
+const TIMER_SUFFIX: &str = ".timer";
+const TEMPLATE_MARKER: char = '@';
+
+// systemd's own defaults, which differ from wingmate's
+const DEFAULT_STOP_TIMEOUT_SECS: u64 = 90;
+
+/// Keys of `[Unit]` that only describe the unit.
+const DESCRIPTIVE_KEYS: [&str; 2] = ["Description", "Documentation"];
+
+struct Section {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    /// The last value of a key.
+    fn value(&self, key: &str) -> Option<&str> {
+        self.entries.iter().rev().find(|(k, _)| { k == key }).map(|(_, v)| { v.as_str() })
+    }
+
+    /// Every value of a list key; an empty assignment clears the list.
+    fn values(&self, key: &str) -> Vec<&str> {
+        let mut values: Vec<&str> = Vec::new();
+        for (_, v) in self.entries.iter().filter(|(k, _)| { k == key }) {
+            if v.is_empty() {
+                values.clear();
+            } else {
+                values.push(v);
+            }
+        }
+        values
+    }
+}
+
+/// Reads the `*.service` units of the `units` directory into services named
+/// after the units, and the `*.timer` units into timers. Keys wingmate has
+/// no equivalent for are reported and skipped, and template units are not
+/// supported. With `problems`, a unit that does not load is recorded there
+/// and skipped.
+pub(super) fn apply(dir: &Path, config: &mut Config, mut problems: Option<&mut Vec<Problem>>) -> Result<(), WingmateInitError> {
+    let mut units: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten()
+            .filter(|e| { !config::is_ignored(&e.file_name().to_string_lossy()) })
+            .map(|e| { e.path() })
+            .filter(|p| { p.is_file() })
+            .collect(),
+        Err(e) => {
+            debug!("cannot read {}: {}", dir.to_string_lossy(), e);
+            return Ok(());
+        }
+    };
+    units.sort();
+
+    for path in units {
+        let file_name = path.file_name().map(|n| { n.to_string_lossy().into_owned() }).unwrap_or_default();
+        let (name, is_timer) = match (file_name.strip_suffix(SERVICE_SUFFIX), file_name.strip_suffix(TIMER_SUFFIX)) {
+            (Some(n), _) => (n, false),
+            (_, Some(n)) => (n, true),
+            _ => {
+                debug!("ignoring {} in {}", file_name, dir.to_string_lossy());
+                continue;
+            }
+        };
+        if name.is_empty() || name.ends_with(TEMPLATE_MARKER) {
+            warn!("{}: template units are not supported, skipping", path.to_string_lossy());
+            continue;
+        }
+
+        config::collect(&mut problems, apply_unit(&path, name, is_timer, config))?;
+    }
+
+    Ok(())
+}
+
+fn apply_unit(path: &Path, name: &str, is_timer: bool, config: &mut Config) -> Result<(), WingmateInitError> {
+    let sections = read(path)?;
+    let wrap = |e: ConfigFileError| {
+        WingmateInitError::ConfigFile { path: String::from(path.to_string_lossy()), source: e }
+    };
+    if is_timer {
+        let timer = to_timer(path, name, &sections).map_err(wrap)?;
+        match config.timers.iter().position(|t| { t.name == timer.name }) {
+            Some(i) => config.timers[i] = timer,
+            None => config.timers.push(timer),
+        }
+    } else {
+        let svc = to_service(path, name, &sections).map_err(wrap)?;
+        match config.services.iter().position(|s| { s.name == svc.name }) {
+            Some(i) => config.services[i] = svc,
+            None => config.services.push(svc),
+        }
+    }
+    Ok(())
+}
+
+fn to_timer(path: &Path, name: &str, sections: &[Section]) -> Result<Timer, ConfigFileError> {
+    let invalid = |key: &str, value: &str| {
+        ConfigFileError::InvalidValue { key: String::from(key), value: String::from(value) }
+    };
+    let empty = Section { name: String::from("Timer"), entries: Vec::new() };
+    let timer = sections.iter().find(|s| { s.name == "Timer" }).unwrap_or(&empty);
+
+    for section in sections.iter() {
+        match section.name.as_str() {
+            "Unit" => {
+                for (key, _) in section.entries.iter().filter(|(k, _)| { !DESCRIPTIVE_KEYS.contains(&k.as_str()) }) {
+                    unsupported(path, &section.name, key);
+                }
+            },
+            "Timer" => {
+                for (key, _) in section.entries.iter() {
+                    if !matches!(key.as_str(), "OnCalendar" | "OnBootSec" | "OnUnitActiveSec" | "RandomizedDelaySec"
+                        | "Persistent" | "Unit") {
+                        unsupported(path, &section.name, key);
+                    }
+                }
+            },
+            "Install" => debug!("{}: skipping [Install]", path.to_string_lossy()),
+            other => warn!("{}: [{}] is not supported, ignoring it", path.to_string_lossy(), other),
+        }
+    }
+
+    let unit = match timer.value("Unit") {
+        Some(u) => String::from(u.strip_suffix(SERVICE_SUFFIX).ok_or_else(|| { invalid("Unit", u) })?),
+        None => String::from(name),
+    };
+    let span = |key: &str| -> Result<Option<Duration>, ConfigFileError> {
+        match timer.value(key) {
+            Some(v) => Ok(Some(parse_timespan(v).filter(|d| { *d != Duration::MAX }).ok_or_else(|| { invalid(key, v) })?)),
+            None => Ok(None),
+        }
+    };
+
+    let mut calendar: Vec<CalendarSpec> = Vec::new();
+    for expression in timer.values("OnCalendar") {
+        calendar.push(CalendarSpec::parse(expression).ok_or_else(|| { invalid("OnCalendar", expression) })?);
+    }
+    let persistent = match timer.value("Persistent") {
+        Some(p) => parse_bool(p).ok_or_else(|| { invalid("Persistent", p) })?,
+        None => false,
+    };
+
+    let t = Timer {
+        name: String::from(name),
+        unit,
+        calendar,
+        on_boot: span("OnBootSec")?,
+        on_unit_active: span("OnUnitActiveSec")?,
+        randomized_delay: span("RandomizedDelaySec")?.unwrap_or_default(),
+        persistent,
+    };
+    if t.calendar.is_empty() && t.on_boot.is_none() && t.on_unit_active.is_none() {
+        return Err(invalid("OnCalendar", ""));
+    }
+    if t.on_unit_active.is_some() && t.on_boot.is_none() && t.calendar.is_empty() {
+        warn!("{}: OnUnitActiveSec alone never elapses, as nothing starts {} first", path.to_string_lossy(), t.unit);
+    }
+
+    Ok(t)
+}
+
+fn to_service(path: &Path, name: &str, sections: &[Section]) -> Result<Service, ConfigFileError> {
+    let invalid = |key: &str, value: &str| {
+        ConfigFileError::InvalidValue { key: String::from(key), value: String::from(value) }
+    };
+    let empty = Section { name: String::from("Service"), entries: Vec::new() };
+    let unit = sections.iter().find(|s| { s.name == "Unit" }).unwrap_or(&empty);
+    let service = sections.iter().find(|s| { s.name == "Service" }).unwrap_or(&empty);
+
+    for section in sections.iter() {
+        match section.name.as_str() {
+            "Unit" => {
+                for (key, _) in section.entries.iter() {
+                    if !matches!(key.as_str(), "After" | "Requires") && !DESCRIPTIVE_KEYS.contains(&key.as_str()) {
+                        unsupported(path, &section.name, key);
+                    }
+                }
+            },
+            "Service" => {
+                for (key, _) in section.entries.iter() {
+                    if !matches!(key.as_str(), "ExecStart" | "ExecStartPre" | "ExecStop" | "ExecReload" | "Restart"
+                        | "RestartSec" | "User" | "Group" | "Environment" | "EnvironmentFile" | "WorkingDirectory"
+                        | "Type" | "KillSignal" | "TimeoutStopSec" | "PIDFile") {
+                        unsupported(path, &section.name, key);
+                    }
+                }
+            },
+            "Install" => debug!("{}: skipping [Install]", path.to_string_lossy()),
+            other => warn!("{}: [{}] is not supported, ignoring it", path.to_string_lossy(), other),
+        }
+    }
+
+    let service_type = match service.value("Type") {
+        None | Some("simple") | Some("exec") | Some("idle") => ServiceType::Simple,
+        Some("oneshot") => ServiceType::Oneshot,
+        Some("notify") | Some("notify-reload") => ServiceType::Notify,
+        Some("forking") => ServiceType::Forking,
+        Some(other) => return Err(invalid("Type", other)),
+    };
+
+    let exec = |key: &str| -> Vec<(Command, bool)> {
+        service.values(key).into_iter().map(|v| { exec_line(path, name, key, v) }).collect()
+    };
+    let mut start = exec("ExecStart");
+    let command = match start.len() {
+        0 => return Err(invalid("ExecStart", "")),
+        // the shell makes way for the process, which then gets MAINPID and the signals
+        1 => match start.remove(0).0 {
+            Command::Inline(i) => Command::Inline(format!("exec {}", i)),
+            other => other,
+        },
+        // only a oneshot service may have several, run one after the other
+        _ if service_type == ServiceType::Oneshot => Command::Inline(join(start)),
+        _ => return Err(invalid("ExecStart", "more than one command for a service that is not Type=oneshot")),
+    };
+
+    let mut svc = Service::new(String::from(name), command);
+    svc.service_type = service_type;
+    svc.restart = Restart::Never;
+    svc.stop_timeout = Duration::from_secs(DEFAULT_STOP_TIMEOUT_SECS);
+    svc.start_pre = exec("ExecStartPre");
+    let stop = exec("ExecStop");
+    if !stop.is_empty() {
+        svc.stop = Some(Command::Inline(join(stop)));
+    }
+    let reload = exec("ExecReload");
+    if !reload.is_empty() {
+        svc.reload = Some(Command::Inline(join(reload)));
+    }
+
+    if let Some(restart) = service.value("Restart") {
+        svc.restart = match restart {
+            "no" => Restart::Never,
+            "always" => Restart::Always,
+            "on-failure" | "on-abnormal" | "on-abort" | "on-watchdog" => Restart::OnFailure,
+            "on-success" => {
+                warn!("{}: Restart=on-success is not supported, not restarting", path.to_string_lossy());
+                Restart::Never
+            },
+            other => return Err(invalid("Restart", other)),
+        };
+    }
+    if let Some(secs) = service.value("RestartSec") {
+        svc.restart_delay = Some(parse_timespan(secs).filter(|d| { *d != Duration::MAX }).ok_or_else(|| { invalid("RestartSec", secs) })?);
+    }
+    if let Some(secs) = service.value("TimeoutStopSec") {
+        svc.stop_timeout = parse_timespan(secs).ok_or_else(|| { invalid("TimeoutStopSec", secs) })?;
+    }
+    if let Some(signal) = service.value("KillSignal") {
+        svc.stop_signal = config::parse_signal(signal).ok_or_else(|| { invalid("KillSignal", signal) })?;
+    }
+    svc.user = service.value("User").map(String::from);
+    svc.group = service.value("Group").map(String::from);
+    svc.pid_file = service.value("PIDFile").map(PathBuf::from);
+    if service_type == ServiceType::Forking && svc.pid_file.is_none() {
+        warn!("{}: Type=forking without PIDFile, the daemon cannot be supervised", path.to_string_lossy());
+    }
+
+    if let Some(dir) = service.value("WorkingDirectory") {
+        let dir = dir.strip_prefix('-').unwrap_or(dir);
+        if dir.starts_with('~') {
+            warn!("{}: WorkingDirectory={} is not supported, ignoring it", path.to_string_lossy(), dir);
+        } else {
+            svc.directory = Some(PathBuf::from(dir));
+        }
+    }
+
+    for assignments in service.values("Environment") {
+        let words = split_words(assignments).ok_or_else(|| { invalid("Environment", assignments) })?;
+        for word in words {
+            let (key, value) = word.split_once('=').ok_or_else(|| { invalid("Environment", &word) })?;
+            set_env(&mut svc.environment, key, value);
+        }
+    }
+    // variables from files override those of Environment=
+    for file in service.values("EnvironmentFile") {
+        let (file, optional) = match file.strip_prefix('-') {
+            Some(f) => (f, true),
+            None => (file, false),
+        };
+        let content = match fs::read_to_string(file) {
+            Ok(c) => c,
+            Err(_) if optional => continue,
+            Err(e) => return Err(ConfigFileError::Read { source: e }),
+        };
+        for (key, value) in procfile::parse_env(&content)? {
+            set_env(&mut svc.environment, &key, &value.unwrap_or_default());
+        }
+    }
+
+    for (key, names) in [("After", &mut svc.after), ("Requires", &mut svc.requires)] {
+        for value in unit.values(key) {
+            for dep in value.split_whitespace() {
+                match dep.strip_suffix(SERVICE_SUFFIX) {
+                    Some(d) if !names.iter().any(|n| { n == d }) => names.push(String::from(d)),
+                    Some(_) => {},
+                    // targets, sockets and the like do not exist here
+                    None => debug!("{}: ignoring {}={}", path.to_string_lossy(), key, dep),
+                }
+            }
+        }
+    }
+
+    Ok(svc)
+}
+
+fn set_env(environment: &mut Vec<(String, Option<String>)>, key: &str, value: &str) {
+    match environment.iter().position(|(k, _)| { k == key }) {
+        Some(i) => environment[i].1 = Some(String::from(value)),
+        None => environment.push((String::from(key), Some(String::from(value)))),
+    }
+}
+
+/// The command line of an `Exec` key, run through the shell, which takes care
+/// of quoting and variable expansion. Of the special prefixes only `-`,
+/// ignoring a failure, has a meaning here; the returned flag tells whether
+/// it was given.
+fn exec_line(path: &Path, name: &str, key: &str, value: &str) -> (Command, bool) {
+    let mut ignore_failure = false;
+    let mut line = value;
+    while let Some(c) = line.chars().next().filter(|c| { "-@:+!".contains(*c) }) {
+        match c {
+            '-' => ignore_failure = true,
+            '@' => warn!("{}: {} with @ is not supported, running the command as is", path.to_string_lossy(), key),
+            _ => debug!("{}: ignoring prefix {} of {}", path.to_string_lossy(), c, key),
+        }
+        line = &line[1..];
+    }
+    if ignore_failure && key != "ExecStartPre" {
+        warn!("{}: {} ignores failures only for ExecStartPre", path.to_string_lossy(), key);
+    }
+
+    (Command::Inline(expand(path, name, line.trim())), ignore_failure)
+}
+
+/// Commands running one after the other as long as they succeed.
+fn join(commands: Vec<(Command, bool)>) -> String {
+    commands.into_iter().map(|(c, _)| {
+        match c {
+            Command::Inline(i) | Command::Direct(i) | Command::ShellPrefixed(i) => i,
+        }
+    }).collect::<Vec<String>>().join(" && ")
+}
+
+/// Expands the specifiers that make sense outside of systemd.
+fn expand(path: &Path, name: &str, value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => {
+                out.push_str(name);
+                out.push_str(SERVICE_SUFFIX);
+            },
+            Some('N') | Some('p') => out.push_str(name),
+            Some('t') => out.push_str("/run"),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                warn!("{}: specifier %{} is not supported, leaving it as is", path.to_string_lossy(), other);
+                out.push('%');
+                out.push(other);
+            },
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+/// Whitespace separated words with single or double quotes and backslash
+/// escapes, as in `Environment="A=b c" D=e`.
+fn split_words(text: &str) -> Option<Vec<String>> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => word.get_or_insert_with(String::new).push(chars.next()?),
+            (q, None) if q == '"' || q == '\'' => {
+                quote = Some(q);
+                word.get_or_insert_with(String::new);
+            },
+            (q, Some(open)) if q == open => quote = None,
+            (w, None) if w.is_whitespace() => {
+                if let Some(w) = word.take() {
+                    words.push(w);
+                }
+            },
+            (other, _) => word.get_or_insert_with(String::new).push(other),
+        }
+    }
+    if quote.is_some() {
+        return None;
+    }
+    if let Some(w) = word {
+        words.push(w);
+    }
+
+    Some(words)
+}
+
+/// systemd time spans such as `90`, `5s`, `1min 30s` or `500ms`; a bare number
+/// is seconds and `infinity` is the longest duration there is.
+pub(super) fn parse_timespan(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if text == "infinity" {
+        return Some(Duration::MAX);
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| { !c.is_ascii_digit() && c != '.' }).unwrap_or(rest.len());
+        if number_len == 0 {
+            return None;
+        }
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = rest[number_len..].trim_start();
+
+        let unit_len = rest.find(|c: char| { !c.is_ascii_alphabetic() }).unwrap_or(rest.len());
+        let secs: f64 = match &rest[..unit_len] {
+            "us" | "usec" => 0.000_001,
+            "ms" | "msec" => 0.001,
+            "" | "s" | "sec" | "second" | "seconds" => 1.0,
+            "m" | "min" | "minute" | "minutes" => 60.0,
+            "h" | "hr" | "hour" | "hours" => 3600.0,
+            "d" | "day" | "days" => 86400.0,
+            "w" | "week" | "weeks" => 7.0 * 86400.0,
+            "M" | "month" | "months" => 2_629_800.0,
+            "y" | "year" | "years" => 31_557_600.0,
+            _ => return None,
+        };
+        total = total.checked_add(Duration::try_from_secs_f64(number * secs).ok()?)?;
+        rest = rest[unit_len..].trim_start();
+    }
+
+    Some(total)
+}
+
+fn unsupported(path: &Path, section: &str, key: &str) {
+    warn!("{}: [{}] {} is not supported, ignoring it", path.to_string_lossy(), section, key);
+}
+
+fn read(path: &Path) -> Result<Vec<Section>, WingmateInitError> {
+    let wrap = |e: ConfigFileError| {
+        WingmateInitError::ConfigFile { path: String::from(path.to_string_lossy()), source: e }
+    };
+    let content = fs::read_to_string(path).map_err(|e| { wrap(ConfigFileError::Read { source: e }) })?;
+    parse(&content).map_err(wrap)
+}
+
+/// Parses the unit file syntax: `Key=Value` entries in sections, comments
+/// starting with `#` or `;` and lines continued with a trailing backslash.
+fn parse(content: &str) -> Result<Vec<Section>, ConfigFileError> {
+    let mut sections: Vec<Section> = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (idx, raw) in content.lines().enumerate() {
+        let trimmed = raw.trim();
+        if pending.is_none() && (trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';')) {
+            continue;
+        }
+
+        let (line_no, mut line) = match pending.take() {
+            // comments within a continued line are dropped
+            Some((n, l)) if trimmed.starts_with('#') || trimmed.starts_with(';') => {
+                pending = Some((n, l));
+                continue;
+            },
+            Some((n, l)) => (n, format!("{} {}", l, trimmed)),
+            None => (idx + 1, String::from(trimmed)),
+        };
+        if let Some(l) = line.strip_suffix('\\') {
+            line = String::from(l.trim_end());
+            pending = Some((line_no, line));
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| { l.strip_suffix(']') }) {
+            sections.push(Section { name: String::from(name.trim()), entries: Vec::new() });
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            ConfigFileError::Syntax { line: line_no, message: format!("expected Key=Value, got \"{}\"", line) }
+        })?;
+        let section = sections.last_mut().ok_or_else(|| {
+            ConfigFileError::Syntax { line: line_no, message: String::from("key outside of a section") }
+        })?;
+        section.entries.push((String::from(key.trim()), String::from(value.trim())));
+    }
+
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timespan_units() {
+        assert_eq!(parse_timespan("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timespan(" 5s "), Some(Duration::from_secs(5)));
+        assert_eq!(parse_timespan("1min 30s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timespan("1h30min"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_timespan("2 hours"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_timespan("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_timespan("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_timespan("250us"), Some(Duration::from_micros(250)));
+        assert_eq!(parse_timespan("1w 1d"), Some(Duration::from_secs(8 * 86400)));
+        assert_eq!(parse_timespan("infinity"), Some(Duration::MAX));
+    }
+
+    #[test]
+    fn timespan_rejects_invalid() {
+        for text in ["", "  ", "s", "5x", "-5s", "1.2.3s", "1e3", "5 s s", "Infinity", "5s,", "１s",
+            "300000000000y 300000000000y"] {
+            assert_eq!(parse_timespan(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn words_quoting() {
+        let words = split_words(r#"A=b "C=d e" 'F="g"' H\ i \"j "" k"l m"n"#).unwrap();
+        assert_eq!(words, vec!["A=b", "C=d e", "F=\"g\"", "H i", "\"j", "", "kl mn"]);
+        assert_eq!(split_words("  ").unwrap(), Vec::<String>::new());
+        assert_eq!(split_words("\"open"), None);
+        assert_eq!(split_words("'open"), None);
+        assert_eq!(split_words("trailing\\"), None);
+    }
+
+    #[test]
+    fn unit_comments_and_continuations() {
+        let sections = parse("# comment\n; another\n[Unit]\nDescription = web\n[Service]\nExecStart=/bin/serve \\\n  # dropped\n  --port 80\nEnvironment=A=1\n").unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].value("Description"), Some("web"));
+        assert_eq!(sections[1].value("ExecStart"), Some("/bin/serve --port 80"));
+        assert_eq!(sections[1].value("Environment"), Some("A=1"));
+    }
+
+    #[test]
+    fn unit_rejects_invalid() {
+        assert!(matches!(parse("Key=value\n"), Err(ConfigFileError::Syntax { line: 1, .. })));
+        assert!(matches!(parse("[Service]\n\nExecStart\n"), Err(ConfigFileError::Syntax { line: 3, .. })));
+    }
+
+    #[test]
+    fn list_values_reset() {
+        let sections = parse("[Unit]\nAfter=a\nAfter=\nAfter=b\nAfter=c\n").unwrap();
+        assert_eq!(sections[0].values("After"), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn specifiers() {
+        let path = Path::new("/etc/wingmate/units/web.service");
+        assert_eq!(expand(path, "web", "%n %N %p %t 100%% %x"), "web.service web web /run 100% %x");
+    }
+}
@@ -2,46 +2,173 @@ mod sighandler;
 mod waiter;
 mod starter;
 mod constants;
+mod output;
+mod syslog;
+mod tail;
+mod watch;
+mod supervisor;
+mod control;
+mod status;
+mod report;
+mod metrics;
+mod autoreload;
+mod timer;
+mod foreground;
+mod tty;
+mod lint;
 
 use tokio::{select, pin};
 use tokio::task::JoinSet;
+use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::time::{Duration,Instant};
 use nix::sys::signal::{Signal, kill};
 use nix::unistd::Pid;
+use log::{debug, error, info, warn};
 use crate::init::config;
+use crate::init::logger;
 use crate::init::error as wmerr;
 use crate::init::error::WingmateInitError;
 
-pub async fn start(cfg: config::Config) -> Result<(), WingmateInitError> {
+pub use lint::lint;
+
+const CONTROL_QUEUE_SIZE: usize = 16;
+
+/// Finds the problems that would keep the configuration from starting.
+pub fn check(cfg: &config::Config) -> Result<(), WingmateInitError> {
+    for c in cfg.get_cron_iter() {
+        starter::check_cron(c)?;
+    }
+    cfg.start_order()?;
+    Ok(())
+}
+
+/// Runs until every service has stopped and returns the exit code wingmate
+/// should terminate with.
+pub async fn start(cfg: config::Config) -> Result<u8, WingmateInitError> {
     let sync_flag = Arc::new(Mutex::new(false));
     let sig_sync_flag = sync_flag.clone();
+    let exit_code = Arc::new(AtomicU8::new(0));
+    let reaped = status::Reaped::default();
 
     let sighandler_cancel = CancellationToken::new();
     let waiter_cancel_sighandler = sighandler_cancel.clone();
     let signal_pump_stop = sighandler_cancel.clone();
 
     let cancel = CancellationToken::new();
-    let starter_service_cancel = cancel.clone();
-    let starter_cron_cancel = cancel.clone();
+    let supervisor_cancel = cancel.clone();
     let signal_pump_start = cancel.clone();
+    let syslog_cancel = cancel.clone();
+    let tail_cancel = cancel.clone();
+    let autoreload_cancel = cancel.clone();
+    let foreground_cancel = cancel.clone();
 
     let mut set: JoinSet<Result<(), wmerr::WingmateInitError>> = JoinSet::new();
+
+    if let Some(forward_cfg) = &cfg.syslog.forward {
+        // keeps forwarding until the very end, so the shutdown is reported too
+        let (forwarder, drain) = logger::forward::start(forward_cfg, sighandler_cancel.clone());
+        logger::set_forwarder(forwarder, forward_cfg.events, forward_cfg.services);
+        set.spawn(async move {
+            drain.await;
+            Ok(())
+        });
+    }
+
     set.spawn(async move {
         signal_pump(signal_pump_start, signal_pump_stop).await
     });
 
+    let (report_tx, report_rx) = mpsc::channel(1);
+    let (reload_tx, reload_rx) = mpsc::channel(1);
+    let report_signal = cfg.status.signal;
+    let main = cfg.get_inline().main;
+    let forwarding = main.is_some();
+    let foreground = cfg.services.iter().find(|s| { s.foreground }).map(|s| { s.name.as_str() })
+        .or(main.as_ref().map(|_| { "the main command" }));
+    if let Some(name) = foreground {
+        if tty::available() {
+            info!(event = "terminal", holder = name; "handing the terminal to {}", name);
+            tty::enable();
+            let tty_cancel = waiter_cancel_sighandler.clone();
+            set.spawn(async move {
+                tty::forward(tty_cancel).await
+            });
+        } else {
+            debug!("no terminal to hand to {}", name);
+        }
+    }
+    if cfg.reload.watch {
+        let reload_cfg = cfg.reload.clone();
+        let search_path = cfg.get_search_path();
+        let autoreload_tx = reload_tx.clone();
+        set.spawn(async move {
+            autoreload::watch(reload_cfg, search_path, autoreload_tx, autoreload_cancel).await
+        });
+    }
+    set.spawn(async move {
+        sighandler::sighandler(sig_sync_flag, cancel, sighandler_cancel, report_signal, report_tx, reload_tx, forwarding).await
+    });
+
+    if cfg.syslog.listen {
+        let syslog_cfg = cfg.syslog.clone();
+        set.spawn(async move {
+            syslog::listen(syslog_cfg, syslog_cancel).await
+        });
+    }
+
+    for t in cfg.tails.iter() {
+        let tail_cfg = t.clone();
+        let tail_cancel = tail_cancel.clone();
+        set.spawn(async move {
+            tail::follow(tail_cfg, tail_cancel).await
+        });
+    }
+
+    let supervisor = supervisor::Supervisor::new(&cfg, sync_flag.clone(), exit_code.clone(), reaped.clone(), supervisor_cancel)?;
+    let (control_tx, control_rx) = mpsc::channel(CONTROL_QUEUE_SIZE);
+    set.spawn(async move {
+        supervisor.run(control_rx, reload_rx).await
+    });
+
+    if let Some(argv) = main {
+        let foreground_flag = sync_flag.clone();
+        let foreground_exit_code = exit_code.clone();
+        let foreground_reaped = reaped.clone();
+        set.spawn(async move {
+            foreground::run(argv, report_signal, foreground_flag, foreground_exit_code, foreground_reaped, foreground_cancel).await
+        });
+    }
+
+    let report_cfg = cfg.status.clone();
+    let report_control = control_tx.clone();
+    let report_cancel = waiter_cancel_sighandler.clone();
     set.spawn(async move {
-        sighandler::sighandler(sig_sync_flag, cancel, sighandler_cancel).await
+        report::run(report_cfg, report_control, report_rx, report_cancel).await
     });
 
-    starter::start_services(&mut set, &cfg, starter_service_cancel)?;
-    starter::start_cron(&mut set, &cfg, starter_cron_cancel)?;
+    if let Some(metrics_cfg) = cfg.metrics.clone() {
+        let metrics_control = control_tx.clone();
+        let metrics_cancel = waiter_cancel_sighandler.clone();
+        set.spawn(async move {
+            metrics::serve(metrics_cfg, metrics_control, metrics_cancel).await
+        });
+    }
+
+    if cfg.control.enabled {
+        // answers until the very end, so status can be queried during shutdown
+        let control_cfg = cfg.control.clone();
+        let control_cancel = waiter_cancel_sighandler.clone();
+        set.spawn(async move {
+            control::serve(control_cfg, control_tx, control_cancel).await
+        });
+    }
 
     //TODO: spawn_blocking for waiter
     set.spawn_blocking(move || {
-        waiter::wait_all(sync_flag, waiter_cancel_sighandler);
+        waiter::wait_all(sync_flag, waiter_cancel_sighandler, reaped);
         Ok(())
     });
 
@@ -49,10 +176,9 @@ pub async fn start(cfg: config::Config) -> Result<(), WingmateInitError> {
         match res {
             Ok(v) => {
                 if let Err(ev) = v {
-                    dbg!(&ev);
                     match ev {
                         WingmateInitError::SpawnError { source, message } => {
-                            eprintln!("{}", WingmateInitError::SpawnError { source, message });
+                            error!(event = "spawn_failed", error:% = source; "failed to spawn: {}", message);
                         },
                         _ => {
                             return Err(ev);
@@ -61,13 +187,13 @@ pub async fn start(cfg: config::Config) -> Result<(), WingmateInitError> {
                 }
             },
             Err(e) => {
-                dbg!(&e);
+                error!("joining task: {}", e);
                 return Err(WingmateInitError::Join { source: e });
             },
         }
     }
 
-    Ok(())
+    Ok(exit_code.load(Ordering::Relaxed))
 }
 
 async fn signal_pump(start: CancellationToken, stop: CancellationToken) -> Result<(), WingmateInitError> {
@@ -90,11 +216,11 @@ async fn signal_pump(start: CancellationToken, stop: CancellationToken) -> Resul
             () = &mut s => {
                 if mode == TERM_MODE {
                     if let Err(e) = kill(Pid::from_raw(ALL_CHILDREN_PID), Signal::SIGTERM) {
-                        eprintln!("daemon: sending TERM signal got {}", e);
+                        warn!("sending TERM signal got {}", e);
                     }
                 } else {
                     if let Err(e) = kill(Pid::from_raw(ALL_CHILDREN_PID), Signal::SIGKILL) {
-                        eprintln!("daemon: sending KILL signal got {}", e);
+                        warn!("sending KILL signal got {}", e);
                     }
                 }
 
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use nix::sys::inotify::{AddWatchFlags, InotifyEvent, WatchDescriptor};
+use tokio::select;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use log::{debug, info, warn};
+use crate::init::config;
+use crate::init::daemon::watch::Watcher;
+use crate::init::error::WingmateInitError;
+
+/// Kubernetes updates ConfigMap volumes by swapping a `..data` symlink, so
+/// entries starting with `..` count as changes to every file of the volume.
+const ATOMIC_WRITER_PREFIX: &str = "..";
+
+/// What a watch descriptor stands for, to tell config changes from unrelated
+/// files living next to the config.
+enum Scope {
+    /// A config directory; only the config entries matter.
+    Directory,
+    /// The `services` or `units` directory; every entry but the ignored ones
+    /// matters.
+    Services,
+    /// The directory holding a config file given directly in the search path.
+    File(OsString),
+}
+
+/// Watches the config search path and asks for a reload through `reload`
+/// once the changes have settled.
+pub async fn watch(cfg: config::ReloadConfig, search_path: Vec<String>, reload: mpsc::Sender<()>,
+    cancel: CancellationToken) -> Result<(), WingmateInitError> {
+
+    let watcher = match Watcher::new() {
+        Ok(w) => w,
+        Err(e) => {
+            warn!("cannot watch the config search path: {}", e);
+            return Ok(());
+        }
+    };
+    let mut scopes = add_watches(&watcher, &search_path);
+    let mut pending = false;
+
+    'watch: loop {
+        select! {
+            events = watcher.next() => {
+                match events {
+                    Ok(events) => {
+                        for ev in events.iter() {
+                            if is_relevant(&mut scopes, ev) {
+                                debug!("config change {:?} on {:?}", ev.mask, ev.name);
+                                pending = true;
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        warn!("reading inotify events: {}", e);
+                        break 'watch;
+                    }
+                }
+            },
+            _ = sleep(cfg.debounce), if pending => {
+                pending = false;
+                info!(event = "config_changed"; "config search path changed");
+                // a reload already pending covers this change too
+                let _ = reload.try_send(());
+                // directories may have been replaced or created meanwhile
+                scopes = add_watches(&watcher, &search_path);
+            },
+            _ = cancel.cancelled() => break 'watch,
+        }
+    }
+
+    Ok(())
+}
+
+fn add_watches(watcher: &Watcher, search_path: &[String]) -> HashMap<WatchDescriptor, Scope> {
+    let mask = AddWatchFlags::IN_CREATE | AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MODIFY
+        | AddWatchFlags::IN_ATTRIB | AddWatchFlags::IN_MOVED_FROM | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_DELETE_SELF | AddWatchFlags::IN_MOVE_SELF;
+    let mut scopes: HashMap<WatchDescriptor, Scope> = HashMap::new();
+    let mut add = |path: &Path, scope: Scope| {
+        match watcher.add(path, mask) {
+            Ok(wd) => {
+                scopes.insert(wd, scope);
+            },
+            Err(e) => debug!("cannot watch {}: {}", path.to_string_lossy(), e),
+        }
+    };
+
+    for p in search_path.iter() {
+        let path = Path::new(p);
+        if path.is_dir() {
+            add(path, Scope::Directory);
+            for dir in ["services", "units"] {
+                let services = path.join(dir);
+                if services.is_dir() {
+                    add(&services, Scope::Services);
+                }
+            }
+        } else if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            add(parent, Scope::File(name.to_os_string()));
+        }
+    }
+
+    scopes
+}
+
+fn is_relevant(scopes: &mut HashMap<WatchDescriptor, Scope>, ev: &InotifyEvent) -> bool {
+    if ev.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+        return true;
+    }
+    if ev.mask.intersects(AddWatchFlags::IN_IGNORED | AddWatchFlags::IN_DELETE_SELF | AddWatchFlags::IN_MOVE_SELF) {
+        return scopes.remove(&ev.wd).is_some();
+    }
+
+    let name = match &ev.name {
+        Some(n) => n.as_os_str(),
+        None => return false,
+    };
+    match scopes.get(&ev.wd) {
+        Some(Scope::Directory) => is_atomic_writer(name) || config::CONFIG_ENTRIES.iter().any(|e| { name == *e }),
+        Some(Scope::Services) => !config::is_ignored(&name.to_string_lossy()),
+        Some(Scope::File(file)) => is_atomic_writer(name) || name == file,
+        None => false,
+    }
+}
+
+fn is_atomic_writer(name: &OsStr) -> bool {
+    name.as_encoded_bytes().starts_with(ATOMIC_WRITER_PREFIX.as_bytes())
+}
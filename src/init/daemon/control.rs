@@ -0,0 +1,177 @@
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::select;
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use log::{debug, warn};
+use crate::init::config;
+use crate::init::daemon::supervisor::{ControlMessage, Reply};
+use crate::init::error::WingmateInitError;
+use crate::protocol::{self, Request, Response};
+
+/// Accepts connections on the control socket. Each line received is a JSON
+/// request and is answered with exactly one JSON line.
+pub async fn serve(cfg: config::ControlConfig, tx: mpsc::Sender<ControlMessage>, cancel: CancellationToken) -> Result<(), WingmateInitError> {
+    let path = cfg.socket.as_path();
+    let listener = match bind(path) {
+        Ok(l) => l,
+        Err(e) => {
+            // not fatal; the services run just as well without it
+            warn!("control socket {} unavailable: {}", path.to_string_lossy(), e);
+            return Ok(());
+        }
+    };
+    debug!("control socket listening on {}", path.to_string_lossy());
+
+    'accept: loop {
+        select! {
+            result = listener.accept() => {
+                match result {
+                    Ok((stream, _)) => {
+                        tokio::spawn(connection(stream, tx.clone()));
+                    },
+                    Err(e) => warn!("accepting on {}: {}", path.to_string_lossy(), e),
+                }
+            },
+            _ = cancel.cancelled() => {
+                break 'accept;
+            }
+        }
+    }
+
+    let _ = fs::remove_file(path);
+    Ok(())
+}
+
+fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    if let Ok(m) = fs::symlink_metadata(path) {
+        if m.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+async fn connection(stream: UnixStream, tx: mpsc::Sender<ControlMessage>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match parse(&line) {
+            Ok(request) => dispatch(&tx, request).await,
+            Err(r) => Reply { response: r, follow: None },
+        };
+
+        if send(&mut write, &reply.response).await.is_err() {
+            break;
+        }
+
+        if let Some(mut rx) = reply.follow {
+            // streams until the client hangs up; anything it sends is ignored
+            'follow: loop {
+                select! {
+                    received = rx.recv() => {
+                        let line = match received {
+                            Ok(l) => l,
+                            Err(broadcast::error::RecvError::Lagged(n)) => format!("... {} lines skipped", n),
+                            Err(broadcast::error::RecvError::Closed) => break 'follow,
+                        };
+                        let mut response = Response::ok();
+                        response.lines = Some(vec![line]);
+                        if send(&mut write, &response).await.is_err() {
+                            break 'follow;
+                        }
+                    },
+                    next = lines.next_line() => {
+                        if !matches!(next, Ok(Some(_))) {
+                            break 'follow;
+                        }
+                    }
+                }
+            }
+            break;
+        }
+    }
+}
+
+async fn send(write: &mut OwnedWriteHalf, response: &Response) -> std::io::Result<()> {
+    let mut out = serde_json::to_vec(response).unwrap_or_default();
+    out.push(b'\n');
+    write.write_all(&out).await
+}
+
+/// The version is checked before the operation, so that a newer client gets
+/// a useful answer instead of a parse error on an unknown operation.
+fn parse(line: &str) -> Result<Request, Response> {
+    let value: serde_json::Value = serde_json::from_str(line)
+        .map_err(|e| { Response::error(format!("invalid request: {}", e)) })?;
+
+    match value.get("version").and_then(|v| { v.as_u64() }) {
+        Some(v) if v == protocol::VERSION as u64 => {},
+        Some(v) => return Err(Response::error(format!("unsupported protocol version {}", v))),
+        None => return Err(Response::error("missing protocol version")),
+    }
+
+    serde_json::from_value(value).map_err(|e| { Response::error(format!("invalid request: {}", e)) })
+}
+
+async fn dispatch(tx: &mpsc::Sender<ControlMessage>, request: Request) -> Reply {
+    let shutting_down = || { Reply { response: Response::error("shutting down"), follow: None } };
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if tx.send((request, reply_tx)).await.is_err() {
+        return shutting_down();
+    }
+
+    reply_rx.await.unwrap_or_else(|_| { shutting_down() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Operation, Target};
+
+    fn error(line: &str) -> String {
+        parse(line).unwrap_err().error.unwrap()
+    }
+
+    #[test]
+    fn parses_requests() {
+        let request = parse(r#"{"version":1,"op":"logs","target":{"service":"web"},"lines":5}"#).unwrap();
+        assert!(matches!(request.operation,
+            Operation::Logs { target: Target::Service(ref s), follow: false, lines: Some(5) } if s == "web"));
+
+        // what the client sends reads back the same
+        let sent = Request { version: protocol::VERSION, operation: Operation::Run { target: Target::Cron(String::from("crontab:backup")) } };
+        let request = parse(&serde_json::to_string(&sent).unwrap()).unwrap();
+        assert!(matches!(request.operation, Operation::Run { target: Target::Cron(ref c) } if c == "crontab:backup"));
+    }
+
+    #[test]
+    fn checks_version_first() {
+        assert_eq!(error(r#"{"op":"status"}"#), "missing protocol version");
+        assert_eq!(error(r#"{"version":"1","op":"status"}"#), "missing protocol version");
+        // an operation this daemon does not know still gets the version answer
+        assert_eq!(error(r#"{"version":2,"op":"migrate"}"#), "unsupported protocol version 2");
+        assert_eq!(error(r#"{"version":0,"op":"status"}"#), "unsupported protocol version 0");
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        for line in ["status", r#"{"version":1"#, r#"{"version":1,"op":"migrate"}"#, r#"{"version":1,"op":"start"}"#,
+            r#"{"version":1,"op":"start","target":{"timer":"x"}}"#] {
+            assert!(error(line).starts_with("invalid request: "), "{}", line);
+        }
+    }
+}
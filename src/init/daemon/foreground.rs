@@ -0,0 +1,111 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU8, Ordering};
+use tokio::process::Command;
+use tokio::select;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use log::{error, info, warn};
+use crate::init::daemon::sighandler;
+use crate::init::daemon::starter;
+use crate::init::daemon::tty;
+use crate::init::daemon::status::{Exit, Reaped};
+use crate::init::error::WingmateInitError;
+
+/// Passed on to the main process, except for the status report signal.
+const FORWARDED_SIGNALS: [Signal; 6] = [
+    Signal::SIGHUP, Signal::SIGINT, Signal::SIGTERM, Signal::SIGQUIT, Signal::SIGUSR1, Signal::SIGUSR2,
+];
+/// Exit codes of a command that cannot be run, as with a shell.
+const EXIT_NOT_FOUND: u8 = 127;
+const EXIT_NOT_EXECUTABLE: u8 = 126;
+const EXIT_SIGNAL_BASE: i32 = 128;
+
+/// Runs `argv` as the main process, the way tini does: it gets the signals
+/// sent to wingmate, and once it exits wingmate stops everything else and
+/// exits with its exit code, or 128 plus the signal that killed it.
+pub async fn run(argv: Vec<String>, report_signal: Signal, flag: Arc<Mutex<bool>>, exit_code: Arc<AtomicU8>, reaped: Reaped,
+    cancel: CancellationToken) -> Result<(), WingmateInitError> {
+
+    let program = argv.first().cloned().unwrap_or_default();
+    let terminal = tty::active();
+    let mut command = Command::new(&program);
+    command.args(&argv[1..]);
+    tty::isolate(&mut command, terminal);
+
+    let mut child = match command.spawn() {
+        Ok(c) => c,
+        Err(e) => {
+            error!(event = "main_failed", command = program.as_str(), error:% = e; "cannot run the main command");
+            let code = if e.kind() == io::ErrorKind::NotFound { EXIT_NOT_FOUND } else { EXIT_NOT_EXECUTABLE };
+            stop(code, &flag, &exit_code, &cancel);
+            return Ok(());
+        }
+    };
+    let pid = child.id().unwrap_or_default();
+    reaped.register(pid);
+    if terminal {
+        tty::acquired(pid);
+    }
+    info!(event = "main_started", command = program.as_str(), pid = pid; "main command started");
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Signal>();
+    let mut forwarders: JoinSet<()> = JoinSet::new();
+    for sig in FORWARDED_SIGNALS.into_iter().filter(|s| { *s != report_signal }) {
+        let mut stream = signal(SignalKind::from_raw(sig as i32)).map_err(|e| { WingmateInitError::Signal { source: e } })?;
+        let tx = tx.clone();
+        forwarders.spawn(async move {
+            while stream.recv().await.is_some() {
+                if tx.send(sig).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let result = loop {
+        select! {
+            result = child.wait() => break result,
+            Some(sig) = rx.recv() => {
+                info!(event = "signal", signal = sig.as_str(), pid = pid; "forwarding to the main command");
+                if let Err(e) = kill(Pid::from_raw(pid as i32), sig) {
+                    warn!(pid = pid; "forwarding {} got {}", sig.as_str(), e);
+                }
+            },
+        }
+    };
+    forwarders.shutdown().await;
+
+    let exit = match starter::result_match(result) {
+        Ok(Some(status)) => Exit::from_status(status),
+        Ok(None) => reaped.take(pid).await,
+        Err(e) => return Err(WingmateInitError::ChildExit { source: e }),
+    };
+    reaped.release(pid);
+    if terminal {
+        tty::release();
+    }
+
+    let code = match exit {
+        Some(Exit::Code(c)) => c as u8,
+        Some(Exit::Signal(s)) => (EXIT_SIGNAL_BASE + s) as u8,
+        None => 0,
+    };
+    info!(event = "main_exited", pid = pid, exit_code = code; "main command exited, stopping");
+    stop(code, &flag, &exit_code, &cancel);
+
+    Ok(())
+}
+
+/// A shutdown already under way keeps its exit code.
+fn stop(code: u8, flag: &Arc<Mutex<bool>>, exit_code: &Arc<AtomicU8>, cancel: &CancellationToken) {
+    if cancel.is_cancelled() {
+        return;
+    }
+    exit_code.store(code, Ordering::Relaxed);
+    sighandler::initiate_stop(flag.clone(), cancel.clone());
+}
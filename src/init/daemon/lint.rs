@@ -0,0 +1,63 @@
+use time::OffsetDateTime;
+use crate::init::config::{self, Problem};
+use crate::init::daemon::starter;
+
+/// Finds what would go wrong once the configuration runs: schedules that
+/// never match, users, groups and directories that do not exist, and
+/// dependencies that cannot be satisfied.
+pub fn lint(cfg: &config::Config) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+    let now = starter::cron_time().unwrap_or_else(|_| { OffsetDateTime::now_utc() });
+
+    for c in cfg.get_cron_iter() {
+        if let Err(e) = starter::check_cron(c) {
+            problems.push(Problem::Error(format!("cron {} \"{}\": {:#}", c.name, c.schedule, anyhow::Error::new(e))));
+        } else if c.next_run(now).is_none() {
+            problems.push(Problem::Error(format!("cron {} \"{}\" never runs", c.name, c.schedule)));
+        }
+    }
+
+    for t in cfg.timers.iter() {
+        for spec in t.calendar.iter().filter(|spec| { spec.next_elapse(now).is_none() }) {
+            problems.push(Problem::Error(format!("timer {} \"{}\" never elapses", t.name, spec.expression)));
+        }
+    }
+
+    for svc in cfg.get_service_iter() {
+        for c in svc.commands() {
+            if let config::Command::ShellPrefixed(path) = c {
+                problems.push(Problem::Warning(format!("service {}: {} is not executable and runs through the shell", svc.name, path)));
+            }
+        }
+        if let Some(user) = &svc.user {
+            if let Err(e) = starter::resolve_user(user) {
+                problems.push(Problem::Error(format!("service {}: {}", svc.name, e)));
+            }
+        }
+        if let Some(group) = &svc.group {
+            if let Err(e) = starter::resolve_group(group) {
+                problems.push(Problem::Error(format!("service {}: {}", svc.name, e)));
+            }
+        }
+        if let Some(dir) = svc.directory.as_ref().filter(|d| { !d.is_dir() }) {
+            problems.push(Problem::Error(format!("service {}: directory {} does not exist", svc.name, dir.to_string_lossy())));
+        }
+
+        for (dep, required) in svc.dependencies() {
+            if cfg.get_service_iter().any(|s| { s.name == dep }) {
+                continue;
+            }
+            if required {
+                problems.push(Problem::Error(format!("service {} requires unknown service {}", svc.name, dep)));
+            } else {
+                problems.push(Problem::Warning(format!("service {} is ordered after unknown service {}", svc.name, dep)));
+            }
+        }
+    }
+
+    if let Err(e) = cfg.start_order() {
+        problems.push(Problem::Error(e.to_string()));
+    }
+
+    problems
+}
@@ -0,0 +1,241 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::select;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use log::{debug, warn};
+use crate::init::config;
+use crate::init::daemon::supervisor::ControlMessage;
+use crate::init::error::WingmateInitError;
+use crate::protocol::{self, Operation, Request};
+
+const MAX_REQUEST_SIZE: usize = 8192;
+const REQUEST_TIMEOUT_SECS: u64 = 5;
+const METRICS_PATH: &str = "/metrics";
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// Serves the Prometheus text format on `GET /metrics`. The numbers come from
+/// the same status snapshot the control socket hands out.
+pub async fn serve(cfg: config::MetricsConfig, control: mpsc::Sender<ControlMessage>, cancel: CancellationToken) -> Result<(), WingmateInitError> {
+    let listener = match bind(&cfg.listen).await {
+        Ok(l) => l,
+        Err(e) => {
+            warn!("metrics endpoint {:?} unavailable: {}", cfg.listen, e);
+            return Ok(());
+        }
+    };
+    debug!("serving metrics on {:?}", cfg.listen);
+
+    'accept: loop {
+        select! {
+            result = accept(&listener) => {
+                match result {
+                    Ok(conn) => {
+                        let control = control.clone();
+                        tokio::spawn(async move {
+                            match conn {
+                                Connection::Tcp(s) => respond(s, control).await,
+                                Connection::Unix(s) => respond(s, control).await,
+                            }
+                        });
+                    },
+                    Err(e) => warn!("accepting metrics connection: {}", e),
+                }
+            },
+            _ = cancel.cancelled() => {
+                break 'accept;
+            }
+        }
+    }
+
+    if let config::MetricsAddress::Unix(path) = &cfg.listen {
+        let _ = fs::remove_file(path);
+    }
+    Ok(())
+}
+
+async fn bind(address: &config::MetricsAddress) -> std::io::Result<Listener> {
+    match address {
+        config::MetricsAddress::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+        config::MetricsAddress::Unix(path) => {
+            remove_stale(path)?;
+            Ok(Listener::Unix(UnixListener::bind(path)?))
+        },
+    }
+}
+
+fn remove_stale(path: &Path) -> std::io::Result<()> {
+    if let Ok(m) = fs::symlink_metadata(path) {
+        if m.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+async fn accept(listener: &Listener) -> std::io::Result<Connection> {
+    match listener {
+        Listener::Tcp(l) => Ok(Connection::Tcp(l.accept().await?.0)),
+        Listener::Unix(l) => Ok(Connection::Unix(l.accept().await?.0)),
+    }
+}
+
+async fn respond<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, control: mpsc::Sender<ControlMessage>) {
+    let head = match timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS), read_head(&mut stream)).await {
+        Ok(Ok(h)) => h,
+        _ => return,
+    };
+
+    let mut parts = head.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (code, body) = if method != "GET" {
+        ("405 Method Not Allowed", String::from("method not allowed\n"))
+    } else if path != METRICS_PATH {
+        ("404 Not Found", String::from("not found\n"))
+    } else {
+        match query(&control).await {
+            Some(status) => ("200 OK", render(&status)),
+            None => ("503 Service Unavailable", String::from("shutting down\n")),
+        }
+    };
+
+    let response = format!("HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code, body.len(), body);
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Reads up to the end of the request headers; only the request line matters.
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<String> {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    while !buf.windows(4).any(|w| { w == b"\r\n\r\n" }) && !buf.windows(2).any(|w| { w == b"\n\n" }) {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "request too large"));
+        }
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    Ok(String::from(head.lines().next().unwrap_or_default()))
+}
+
+async fn query(control: &mpsc::Sender<ControlMessage>) -> Option<protocol::Status> {
+    let (tx, rx) = oneshot::channel();
+    let request = Request { version: protocol::VERSION, operation: Operation::Status };
+    control.send((request, tx)).await.ok()?;
+    rx.await.ok()?.response.status.map(|s| { *s })
+}
+
+fn render(status: &protocol::Status) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "wingmate_service_up", "gauge", "Whether the service process is running.");
+    for s in status.services.iter() {
+        let up = matches!(s.state.as_str(), "running" | "ready");
+        let _ = writeln!(out, "wingmate_service_up{{service=\"{}\"}} {}", label(&s.name), up as u8);
+    }
+
+    header(&mut out, "wingmate_service_ready", "gauge", "Whether the service is past its startup period.");
+    for s in status.services.iter() {
+        let _ = writeln!(out, "wingmate_service_ready{{service=\"{}\"}} {}", label(&s.name), (s.state == "ready") as u8);
+    }
+
+    header(&mut out, "wingmate_service_restarts_total", "counter", "Restarts after an unexpected exit.");
+    for s in status.services.iter() {
+        let _ = writeln!(out, "wingmate_service_restarts_total{{service=\"{}\"}} {}", label(&s.name), s.restarts);
+    }
+
+    header(&mut out, "wingmate_service_uptime_seconds", "gauge", "Time since the current process started.");
+    for s in status.services.iter() {
+        let _ = writeln!(out, "wingmate_service_uptime_seconds{{service=\"{}\"}} {}", label(&s.name), s.uptime_secs.unwrap_or(0));
+    }
+
+    header(&mut out, "wingmate_service_last_exit_code", "gauge", "Exit code of the previous process, if it exited normally.");
+    for s in status.services.iter() {
+        if let Some(code) = s.last_exit_code {
+            let _ = writeln!(out, "wingmate_service_last_exit_code{{service=\"{}\"}} {}", label(&s.name), code);
+        }
+    }
+
+    header(&mut out, "wingmate_service_last_exit_signal", "gauge", "Signal that ended the previous process, if any.");
+    for s in status.services.iter() {
+        if let Some(sig) = s.last_signal {
+            let _ = writeln!(out, "wingmate_service_last_exit_signal{{service=\"{}\"}} {}", label(&s.name), sig);
+        }
+    }
+
+    header(&mut out, "wingmate_cron_runs_total", "counter", "Completed runs of the cron entry.");
+    for c in status.cron.iter() {
+        let _ = writeln!(out, "wingmate_cron_runs_total{{cron=\"{}\"}} {}", label(&c.name), c.runs_total);
+    }
+
+    header(&mut out, "wingmate_cron_failures_total", "counter", "Runs that could not start or exited unsuccessfully.");
+    for c in status.cron.iter() {
+        let _ = writeln!(out, "wingmate_cron_failures_total{{cron=\"{}\"}} {}", label(&c.name), c.failures_total);
+    }
+
+    header(&mut out, "wingmate_cron_duration_seconds", "summary", "Duration of the cron runs.");
+    for c in status.cron.iter() {
+        let _ = writeln!(out, "wingmate_cron_duration_seconds_sum{{cron=\"{}\"}} {:.3}", label(&c.name), c.duration_ms_total as f64 / 1000.0);
+        let _ = writeln!(out, "wingmate_cron_duration_seconds_count{{cron=\"{}\"}} {}", label(&c.name), c.runs_total);
+    }
+
+    header(&mut out, "wingmate_cron_running", "gauge", "Instances of the cron command currently running.");
+    for c in status.cron.iter() {
+        let _ = writeln!(out, "wingmate_cron_running{{cron=\"{}\"}} {}", label(&c.name), c.running.len());
+    }
+
+    header(&mut out, "wingmate_cron_last_success_timestamp_seconds", "gauge", "End of the latest successful run.");
+    for c in status.cron.iter() {
+        let ts = c.last_success.as_deref().and_then(|t| { OffsetDateTime::parse(t, &Rfc3339).ok() });
+        if let Some(t) = ts {
+            let _ = writeln!(out, "wingmate_cron_last_success_timestamp_seconds{{cron=\"{}\"}} {}", label(&c.name), t.unix_timestamp());
+        }
+    }
+
+    header(&mut out, "wingmate_reaped_orphans_total", "counter", "Processes reaped by wingmate that it did not start.");
+    let _ = writeln!(out, "wingmate_reaped_orphans_total {}", status.reaped_orphans);
+
+    if let Some(f) = &status.forwarding {
+        header(&mut out, "wingmate_syslog_forward_messages_total", "counter", "Messages queued for the syslog collector, by outcome.");
+        for (result, count) in [("sent", f.sent), ("dropped", f.dropped), ("failed", f.failed)] {
+            let _ = writeln!(out, "wingmate_syslog_forward_messages_total{{result=\"{}\"}} {}", result, count);
+        }
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,339 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tokio::io::{AsyncRead, AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use log::warn;
+use crate::init::config;
+use crate::init::daemon::tty;
+use crate::init::logger;
+
+const LOG_HISTORY_LINES: usize = 200;
+const LOG_FOLLOW_QUEUE: usize = 256;
+
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Destination of a service or cron command output. A sink outlives the
+/// individual child processes, so the log file is shared across restarts.
+#[derive(Clone)]
+pub struct Sink {
+    name: Arc<String>,
+    forward: bool,
+    syslog: bool,
+    inherit: bool,
+    file: Option<Arc<Mutex<RotatingFile>>>,
+    history: Option<LogHistory>,
+}
+
+/// The most recent output lines of a service or cron entry, served to
+/// `wingmatectl logs`. Followers receive every line captured afterwards.
+#[derive(Clone)]
+pub struct LogHistory {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    tx: broadcast::Sender<String>,
+}
+
+struct RotatingFile {
+    spec: config::LogFile,
+    file: Option<fs::File>,
+    size: u64,
+    /// Compression of the latest rotated file, which runs off the lock.
+    compressing: Option<JoinHandle<()>>,
+}
+
+impl Sink {
+    /// With `capture` set the output is always piped, so that recent lines
+    /// can be kept for the control socket, see `ControlConfig::logs`. While a
+    /// foreground process holds the terminal, nothing is written to it.
+    pub fn new(name: &str, output: &config::Output, capture: bool) -> Self {
+        let syslog = logger::forwards_output();
+        let terminal = tty::active();
+        Self {
+            name: Arc::new(String::from(name)),
+            forward: output.stdout && !terminal,
+            syslog,
+            inherit: output.is_inherit() && !syslog && !capture && !terminal,
+            file: output.file.as_ref().map(|spec| { Arc::new(Mutex::new(RotatingFile::new(spec.clone()))) }),
+            history: if capture { Some(LogHistory::new()) } else { None },
+        }
+    }
+
+    pub fn history(&self) -> Option<LogHistory> {
+        self.history.clone()
+    }
+
+    pub fn prepare(&self, cmd: &mut Command) {
+        if tty::active() {
+            // reading the terminal from a background process group stops it
+            cmd.stdin(Stdio::null());
+        }
+        if !self.inherit {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+    }
+
+    /// Takes over the piped stdout and stderr of the child. The returned
+    /// handles complete when both streams reach end of file.
+    pub fn attach(&self, child: &mut Child) -> Vec<JoinHandle<()>> {
+        let mut handles: Vec<JoinHandle<()>> = Vec::new();
+        let pid = child.id();
+
+        if let Some(stdout) = child.stdout.take() {
+            handles.push(tokio::spawn(pump(stdout, Stream::Stdout, pid, self.clone())));
+        }
+
+        if let Some(stderr) = child.stderr.take() {
+            handles.push(tokio::spawn(pump(stderr, Stream::Stderr, pid, self.clone())));
+        }
+
+        handles
+    }
+
+    fn write_line(&self, stream: Stream, pid: Option<u32>, line: &[u8]) {
+        if let Some(file) = &self.file {
+            let mut f = file.lock().unwrap();
+            if let Err(e) = f.write_line(line) {
+                warn!("writing to {}: {}", f.spec.path.to_string_lossy(), e);
+            }
+        }
+
+        if self.forward {
+            let result = match stream {
+                Stream::Stdout => io::stdout().lock().write_all(line),
+                Stream::Stderr => io::stderr().lock().write_all(line),
+            };
+            if let Err(e) = result {
+                warn!("forwarding child output: {}", e);
+            }
+        }
+
+        if self.syslog {
+            logger::forward_output(&self.name, pid, matches!(stream, Stream::Stderr), line);
+        }
+
+        if let Some(history) = &self.history {
+            history.push(line);
+        }
+    }
+}
+
+impl LogHistory {
+    fn new() -> Self {
+        let (tx, _) = broadcast::channel(LOG_FOLLOW_QUEUE);
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(LOG_HISTORY_LINES))),
+            tx,
+        }
+    }
+
+    fn push(&self, line: &[u8]) {
+        let text = String::from(String::from_utf8_lossy(line).trim_end_matches(['\n', '\r']));
+        {
+            let mut lines = self.lines.lock().unwrap();
+            if lines.len() == LOG_HISTORY_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(text.clone());
+        }
+        // nobody following is the common case
+        let _ = self.tx.send(text);
+    }
+
+    /// Returns up to `count` of the latest lines, oldest first.
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.tx.subscribe()
+    }
+}
+
+async fn pump<R: AsyncRead + Unpin>(reader: R, stream: Stream, pid: Option<u32>, sink: Sink) {
+    let mut reader = BufReader::new(reader);
+    let mut buf: Vec<u8> = Vec::new();
+
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => break,
+            Ok(_) => sink.write_line(stream, pid, &buf),
+            Err(e) => {
+                warn!("reading child output: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+impl RotatingFile {
+    fn new(spec: config::LogFile) -> Self {
+        Self {
+            spec,
+            file: None,
+            size: 0,
+            compressing: None,
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.file.is_none() {
+            self.open()?;
+        }
+
+        // the file grows past the limit until the previous one is compressed
+        let compressing = self.compressing.as_ref().is_some_and(|h| { !h.is_finished() });
+        if self.size > 0 && self.size + line.len() as u64 > self.spec.max_size && !compressing {
+            self.rotate()?;
+            self.open()?;
+        }
+
+        if let Some(f) = &mut self.file {
+            f.write_all(line)?;
+            self.size += line.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let f = fs::OpenOptions::new().create(true).append(true).open(&self.spec.path)?;
+        self.size = f.metadata()?.len();
+        self.file = Some(f);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+
+        if self.spec.max_files == 0 {
+            return fs::remove_file(&self.spec.path);
+        }
+
+        let oldest = self.rotated_path(self.spec.max_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+
+        for i in (1..self.spec.max_files).rev() {
+            let from = self.rotated_path(i);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(i + 1))?;
+            }
+        }
+
+        if self.spec.compress {
+            let mut plain = self.spec.path.clone().into_os_string();
+            plain.push(".1");
+            let plain = PathBuf::from(plain);
+            fs::rename(&self.spec.path, &plain)?;
+            let gz = self.rotated_path(1);
+            self.compressing = Some(tokio::task::spawn_blocking(move || {
+                if let Err(e) = compress(&plain, &gz).and_then(|_| { fs::remove_file(&plain) }) {
+                    warn!("compressing {}: {}", plain.to_string_lossy(), e);
+                }
+            }));
+            Ok(())
+        } else {
+            fs::rename(&self.spec.path, self.rotated_path(1))
+        }
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut p = self.spec.path.clone().into_os_string();
+        p.push(format!(".{}", index));
+        if self.spec.compress {
+            p.push(".gz");
+        }
+        PathBuf::from(p)
+    }
+}
+
+fn compress(from: &Path, to: &Path) -> io::Result<()> {
+    let mut input = fs::File::open(from)?;
+    let mut encoder = GzEncoder::new(fs::File::create(to)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+
+    fn log_file(name: &str, compress: bool) -> RotatingFile {
+        let dir = std::env::temp_dir().join(format!("wingmate-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        RotatingFile::new(config::LogFile { path: dir.join("out.log"), max_size: 10, max_files: 2, compress })
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn read_gz(path: PathBuf) -> String {
+        let mut text = String::new();
+        GzDecoder::new(fs::File::open(path).unwrap()).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn rotate_shifts_files() {
+        let mut f = log_file("rotate", false);
+        for line in ["1111\n", "2222\n", "3333\n", "4444\n", "5555\n"] {
+            f.write_line(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(f.spec.path.clone()), "5555\n");
+        assert_eq!(read(f.rotated_path(1)), "3333\n4444\n");
+        assert_eq!(read(f.rotated_path(2)), "1111\n2222\n");
+
+        // the oldest file goes once max_files are kept
+        for line in ["6666\n", "7777\n"] {
+            f.write_line(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(f.spec.path.clone()), "7777\n");
+        assert_eq!(read(f.rotated_path(1)), "5555\n6666\n");
+        assert_eq!(read(f.rotated_path(2)), "3333\n4444\n");
+        assert!(!f.rotated_path(3).exists());
+        fs::remove_dir_all(f.spec.path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotate_compresses_files() {
+        let mut f = log_file("compress", true);
+        assert!(f.rotated_path(1).to_string_lossy().ends_with("out.log.1.gz"));
+        for line in ["1111\n", "2222\n", "3333\n"] {
+            f.write_line(line.as_bytes()).unwrap();
+        }
+        f.compressing.take().unwrap().await.unwrap();
+        assert_eq!(read_gz(f.rotated_path(1)), "1111\n2222\n");
+
+        for line in ["4444\n", "5555\n"] {
+            f.write_line(line.as_bytes()).unwrap();
+        }
+        f.compressing.take().unwrap().await.unwrap();
+        assert_eq!(read(f.spec.path.clone()), "5555\n");
+        assert_eq!(read_gz(f.rotated_path(1)), "3333\n4444\n");
+        assert_eq!(read_gz(f.rotated_path(2)), "1111\n2222\n");
+        // the plain copy is gone once compressed
+        let mut plain = f.spec.path.clone().into_os_string();
+        plain.push(".1");
+        assert!(!PathBuf::from(plain).exists());
+        fs::remove_dir_all(f.spec.path.parent().unwrap()).unwrap();
+    }
+}
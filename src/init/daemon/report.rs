@@ -0,0 +1,163 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::select;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, Interval};
+use tokio_util::sync::CancellationToken;
+use log::{debug, warn};
+use crate::init::config;
+use crate::init::daemon::supervisor::ControlMessage;
+use crate::init::error::WingmateInitError;
+use crate::protocol::{self, Operation, Request};
+
+/// Writes a status snapshot whenever `trigger` fires and, if configured, at
+/// every refresh interval. The snapshot is taken through the same channel the
+/// control socket uses.
+pub async fn run(cfg: config::StatusConfig, control: mpsc::Sender<ControlMessage>, mut trigger: mpsc::Receiver<()>,
+    cancel: CancellationToken) -> Result<(), WingmateInitError> {
+
+    let mut refresh: Option<Interval> = cfg.interval.map(interval);
+
+    'report: loop {
+        select! {
+            t = trigger.recv() => {
+                if t.is_none() {
+                    break 'report;
+                }
+            },
+            _ = tick(&mut refresh) => {},
+            _ = cancel.cancelled() => {
+                break 'report;
+            }
+        }
+
+        let status = match query(&control).await {
+            Some(s) => s,
+            None => continue 'report,
+        };
+
+        let content = match cfg.format {
+            config::LogFormat::Text => text(&status),
+            config::LogFormat::Json => {
+                let mut json = serde_json::to_string_pretty(&status).unwrap_or_default();
+                json.push('\n');
+                json
+            },
+        };
+
+        let result = match &cfg.file {
+            Some(path) => write_file(path, &content),
+            None => io::stdout().lock().write_all(content.as_bytes()),
+        };
+        match result {
+            Ok(_) => debug!("status report written"),
+            Err(e) => warn!("writing status report: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+async fn tick(refresh: &mut Option<Interval>) {
+    match refresh {
+        Some(i) => {
+            i.tick().await;
+        },
+        None => std::future::pending().await,
+    }
+}
+
+async fn query(control: &mpsc::Sender<ControlMessage>) -> Option<protocol::Status> {
+    let (tx, rx) = oneshot::channel();
+    let request = Request { version: protocol::VERSION, operation: Operation::Status };
+    control.send((request, tx)).await.ok()?;
+    rx.await.ok()?.response.status.map(|s| { *s })
+}
+
+/// Replaces the file in one step, so readers never see half a report.
+fn write_file(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}
+
+fn text(status: &protocol::Status) -> String {
+    let mut out = String::new();
+    let now = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+    let _ = writeln!(out, "wingmate status at {}", now);
+
+    let _ = writeln!(out, "services:");
+    for s in status.services.iter() {
+        let _ = write!(out, "  {} {}", s.name, s.state);
+        if let Some(pid) = s.pid {
+            let _ = write!(out, " pid={}", pid);
+        }
+        if let Some(up) = s.uptime_secs {
+            let _ = write!(out, " uptime={}s", up);
+        }
+        let _ = write!(out, " restarts={}", s.restarts);
+        if let Some(code) = s.last_exit_code {
+            let _ = write!(out, " last_exit_code={}", code);
+        }
+        if let Some(sig) = s.last_signal {
+            let _ = write!(out, " last_signal={}", sig);
+        }
+        if let Some(reason) = &s.last_failure {
+            let _ = write!(out, " last_failure=\"{}\"", reason);
+        }
+        out.push('\n');
+    }
+
+    if !status.cron.is_empty() {
+        let _ = writeln!(out, "cron:");
+    }
+    for c in status.cron.iter() {
+        let _ = write!(out, "  {} \"{}\" {}", c.name, c.schedule, if c.enabled { "enabled" } else { "disabled" });
+        if let Some(next) = &c.next_run {
+            let _ = write!(out, " next_run={}", next);
+        }
+        if !c.running.is_empty() {
+            let pids: Vec<String> = c.running.iter().map(|p| { p.to_string() }).collect();
+            let _ = write!(out, " running={}", pids.join(","));
+        }
+        out.push('\n');
+        for r in c.last_runs.iter().rev() {
+            let _ = write!(out, "    {} {}ms", r.started_at, r.duration_ms);
+            if let Some(code) = r.exit_code {
+                let _ = write!(out, " exit_code={}", code);
+            }
+            if let Some(sig) = r.signal {
+                let _ = write!(out, " signal={}", sig);
+            }
+            if let Some(e) = &r.error {
+                let _ = write!(out, " error=\"{}\"", e);
+            }
+            out.push('\n');
+        }
+    }
+
+    if !status.timers.is_empty() {
+        let _ = writeln!(out, "timers:");
+    }
+    for t in status.timers.iter() {
+        let _ = write!(out, "  {} service={} \"{}\"", t.name, t.service, t.schedule);
+        if let Some(next) = &t.next_elapse {
+            let _ = write!(out, " next_elapse={}", next);
+        }
+        if let Some(last) = &t.last_trigger {
+            let _ = write!(out, " last_trigger={}", last);
+        }
+        out.push('\n');
+    }
+
+    if let Some(f) = &status.forwarding {
+        let _ = writeln!(out, "syslog forwarding: sent={} dropped={} failed={}", f.sent, f.dropped, f.failed);
+    }
+
+    out
+}
@@ -1,27 +1,45 @@
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::select;
 use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
+use nix::sys::signal::Signal;
+use log::{debug, info};
 use crate::init::error::WingmateInitError;
 
-pub async fn sighandler(flag: Arc<Mutex<bool>>, cancel: CancellationToken, exit: CancellationToken) -> Result<(), WingmateInitError> {
+/// `report` receives a notification whenever `report_signal` arrives, and
+/// `reload` whenever SIGHUP does. With a main command, SIGINT, SIGTERM and
+/// SIGHUP are its business; wingmate stops once it exits.
+pub async fn sighandler(flag: Arc<Mutex<bool>>, cancel: CancellationToken, exit: CancellationToken,
+    report_signal: Signal, report: mpsc::Sender<()>, reload: mpsc::Sender<()>, forwarding: bool) -> Result<(), WingmateInitError> {
     let mut sigint = signal(SignalKind::interrupt()).map_err(|e| { WingmateInitError::Signal { source: e } })?; 
     let mut sigterm = signal(SignalKind::terminate()).map_err(|e| { WingmateInitError::Signal { source: e } })?;
     let mut sigchld = signal(SignalKind::child()).map_err(|e| { WingmateInitError::Signal { source: e } })?;
+    let mut sighup = signal(SignalKind::hangup()).map_err(|e| { WingmateInitError::Signal { source: e } })?;
+    let mut sigreport = signal(SignalKind::from_raw(report_signal as i32)).map_err(|e| { WingmateInitError::Signal { source: e } })?;
 
     'signal: loop {
         select! {
-            _ = sigint.recv() => {
-                println!("got SIGINT");
+            _ = sigint.recv(), if !forwarding => {
+                info!(event = "signal", signal = "SIGINT"; "stopping");
                 initiate_stop(flag.clone(), cancel.clone());
             },
-            _ = sigterm.recv() => {
-                println!("got SIGTERM");
+            _ = sigterm.recv(), if !forwarding => {
+                info!(event = "signal", signal = "SIGTERM"; "stopping");
                 initiate_stop(flag.clone(), cancel.clone());
             },
             _ = sigchld.recv() => {
                 // do nothing intentionally
             },
+            _ = sighup.recv(), if !forwarding => {
+                info!(event = "signal", signal = "SIGHUP"; "reloading configuration");
+                let _ = reload.try_send(());
+            },
+            _ = sigreport.recv() => {
+                debug!(event = "signal", signal = report_signal.as_str(); "status report requested");
+                // a report already pending covers this one too
+                let _ = report.try_send(());
+            },
             _ = exit.cancelled() => {
                 break 'signal;
             }
@@ -31,7 +49,7 @@ pub async fn sighandler(flag: Arc<Mutex<bool>>, cancel: CancellationToken, exit:
     Ok(())
 }
 
-fn initiate_stop(flag: Arc<Mutex<bool>>, cancel: CancellationToken) {
+pub(super) fn initiate_stop(flag: Arc<Mutex<bool>>, cancel: CancellationToken) {
     {
         let mut fl = flag.lock().unwrap();
         *fl = true;
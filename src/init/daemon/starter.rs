@@ -3,271 +3,1059 @@ use tokio::task::JoinSet;
 use tokio::process::{Command, Child};
 use tokio_util::sync::CancellationToken;
 use tokio::select;
+use tokio::sync::{mpsc, watch};
+use tokio::net::UnixDatagram;
 use tokio::io::Result as tokio_result;
 use tokio::time::{sleep, interval};
 use std::env;
+use std::fs;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram as StdUnixDatagram};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use std::process::ExitStatus;
 use nix::sys::signal::{kill, Signal};
 use nix::errno::Errno;
-use nix::unistd::Pid;
+use nix::unistd::{Gid, Group, Pid, Uid, User};
 use anyhow::{Context, anyhow};
-use time::{OffsetDateTime, Duration as TimeDur, Weekday, UtcOffset};
+use time::{OffsetDateTime, Duration as TimeDur, UtcOffset};
+use log::{debug, error, info, trace, warn};
 use crate::init::config;
+use crate::init::daemon::output;
+use crate::init::daemon::tty;
+use crate::init::daemon::status::{CronRun, CronRuntime, Exit, Reaped, ServiceRuntime, ServiceState};
 use crate::init::error::{WingmateInitError, CronConfigError};
 
 
 const CRON_TRIGGER_WAIT_SECS: u64 = 20;
-const ENV_UTC_OFFSET: &'static str = "WINGMATE_TIME_OFFSET";
-
-pub fn start_services(ts: &mut JoinSet<Result<(), WingmateInitError>>, cfg: &config::Config, cancel: CancellationToken)
-    -> Result<(), WingmateInitError> {
-
-    for svc_ in cfg.get_service_iter() {
-        let mut shell: String = String::new();
-        if let config::Command::ShellPrefixed(_) = svc_ {
-            shell = cfg.get_shell().ok_or::<WingmateInitError>(WingmateInitError::NoShellAvailable)?;
-        }
-        let svc = svc_.clone();
-        let cancel = cancel.clone();
-        ts.spawn(async move {
-            'autorestart: loop {
-                let mut child: Child;
-                let svc = svc.clone();
-                match svc {
-                    config::Command::Direct(c) => {
-                        let exp_str = c.clone();
-                        child = Command::new(c).spawn().map_err(|e| {
-                            WingmateInitError::SpawnError { source: e, message: exp_str }
-                        })?;
+const BACKOFF_INITIAL_SECS: u64 = 1;
+const BACKOFF_MAX_SECS: u64 = 60;
+const ENV_UTC_OFFSET: &str = "WINGMATE_TIME_OFFSET";
+/// systemd's default start timeout, for the commands run before a service
+/// and on reload.
+const HELPER_TIMEOUT_SECS: u64 = 90;
+const DAEMON_POLL_MILLIS: u64 = 500;
+const PID_FILE_ATTEMPTS: u32 = 10;
+const PID_FILE_WAIT_MILLIS: u64 = 100;
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+const NOTIFY_READY: &str = "READY=1";
+const NOTIFY_BUFFER_SIZE: usize = 4096;
+
+/// Counts the notify sockets bound, so that a service replaced on reload
+/// gets an address of its own while the old task still holds the previous one.
+static NOTIFY_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Requests handled by a running service task. `Reload` runs the reload
+/// command of a running service.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceCommand {
+    Start,
+    Stop,
+    Restart,
+    Reload,
+}
+
+/// Requests handled by a running cron task. `Run` triggers the command
+/// immediately, regardless of the schedule and of the enabled flag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CronCommand {
+    Enable,
+    Disable,
+    Run,
+}
+
+/// Cancelling `cancel` stops this service alone; `stopped` is cancelled once
+/// its task has finished.
+pub struct ServiceHandle {
+    pub name: String,
+    pub service: config::Service,
+    pub tx: mpsc::UnboundedSender<ServiceCommand>,
+    pub runtime: Arc<Mutex<ServiceRuntime>>,
+    pub history: Option<output::LogHistory>,
+    pub readiness: watch::Receiver<Readiness>,
+    pub cancel: CancellationToken,
+    pub stopped: CancellationToken,
+}
+
+pub struct CronHandle {
+    pub name: String,
+    pub entry: config::Crontab,
+    pub tx: mpsc::UnboundedSender<CronCommand>,
+    pub runtime: Arc<Mutex<CronRuntime>>,
+    pub history: Option<output::LogHistory>,
+    pub cancel: CancellationToken,
+}
+
+enum Stopped {
+    /// The process went away by itself.
+    Exited,
+    /// Stopped on request, start it again only when asked to.
+    Requested,
+    /// Stopped on request and started again right away.
+    Restart,
+    /// Wingmate is shutting down.
+    Shutdown,
+}
+
+/// Whether a service got started, for the services ordered after it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Readiness {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// A service to wait for before starting; `readiness` is `None` when there is
+/// no such service.
+pub struct Dependency {
+    pub name: String,
+    pub required: bool,
+    pub readiness: Option<watch::Receiver<Readiness>>,
+}
+
+/// What a service waits for before it starts the first time.
+#[derive(Default)]
+pub struct Wait {
+    /// The task of the service this one replaces, so both never run at the
+    /// same time.
+    pub stopped: Option<CancellationToken>,
+    pub dependencies: Vec<Dependency>,
+}
+
+/// The process a service is about: the child wingmate spawned or, for a
+/// forking service, the daemon that child left behind, when its pid is known.
+enum Main {
+    Child(Child),
+    Daemon(Option<u32>),
+}
+
+pub fn start_service(ts: &mut JoinSet<Result<(), WingmateInitError>>, svc_: &config::Service, shell: Option<String>, capture: bool,
+    reaped: Reaped, cancel: CancellationToken, wait: Wait) -> Result<ServiceHandle, WingmateInitError> {
+
+    let mut shell_path: String = String::new();
+    let needs_shell = svc_.commands().any(|c| {
+        matches!(c, config::Command::ShellPrefixed(_) | config::Command::Inline(_))
+    });
+    if needs_shell {
+        shell_path = shell.ok_or::<WingmateInitError>(WingmateInitError::NoShellAvailable)?;
+    }
+
+    let notify = match svc_.service_type {
+        config::ServiceType::Notify => Some(notify_socket(&svc_.name).map_err(|e| {
+            WingmateInitError::SpawnError { source: e, message: format!("notify socket of {}", svc_.name) }
+        })?),
+        _ => None,
+    };
+
+    let stopped = CancellationToken::new();
+    let log_pipe = match &svc_.log {
+        Some(log) => {
+            let (reader, writer) = io::pipe().map_err(|e| {
+                WingmateInitError::SpawnError { source: e, message: format!("log pipe of {}", svc_.name) }
+            })?;
+            let logger = Logger {
+                name: svc_.name.clone(),
+                command: log.clone(),
+                shell: shell_path.clone(),
+                directory: svc_.directory.as_ref().map(|d| { d.join("log") }),
+                sink: output::Sink::new(&format!("{}/log", svc_.name), &config::Output::default(), capture),
+                reaped: reaped.clone(),
+            };
+            let log_after = wait.stopped.clone();
+            let log_stopped = stopped.clone();
+            ts.spawn(async move {
+                if let Some(after) = log_after {
+                    after.cancelled().await;
+                }
+                logger.run(reader, log_stopped).await
+            });
+            Some(writer)
+        },
+        None => None,
+    };
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (readiness_tx, readiness) = watch::channel(Readiness::Pending);
+    let runtime = Arc::new(Mutex::new(ServiceRuntime::default()));
+    let sink = output::Sink::new(&svc_.name, &svc_.output, capture);
+    let history = sink.history();
+    let task = ServiceTask {
+        name: svc_.name.clone(),
+        svc: svc_.clone(),
+        shell: shell_path,
+        log_pipe,
+        notify,
+        sink,
+        runtime: runtime.clone(),
+        readiness: readiness_tx,
+        dependencies: wait.dependencies,
+        reaped,
+    };
+    let task_cancel = cancel.clone();
+    let task_stopped = stopped.clone();
+    ts.spawn(async move {
+        if let Some(after) = wait.stopped {
+            after.cancelled().await;
+        }
+        let result = task.supervise(rx, task_cancel).await;
+        task_stopped.cancel();
+        result
+    });
+
+    Ok(ServiceHandle { name: svc_.name.clone(), service: svc_.clone(), tx, runtime, history, readiness, cancel, stopped })
+}
+
+struct ServiceTask {
+    name: String,
+    svc: config::Service,
+    shell: String,
+    /// Dropped together with the task, which ends the logger's input.
+    log_pipe: Option<io::PipeWriter>,
+    /// The socket a notify service reports on, and its address.
+    notify: Option<(UnixDatagram, String)>,
+    sink: output::Sink,
+    runtime: Arc<Mutex<ServiceRuntime>>,
+    readiness: watch::Sender<Readiness>,
+    dependencies: Vec<Dependency>,
+    reaped: Reaped,
+}
+
+impl ServiceTask {
+    async fn supervise(mut self, mut rx: mpsc::UnboundedReceiver<ServiceCommand>, cancel: CancellationToken) -> Result<(), WingmateInitError> {
+        let name = self.name.clone();
+        let name = name.as_str();
+        let mut want_running = self.svc.enabled;
+        let mut backoff: Option<Duration> = None;
+        let mut delay: Option<Duration> = None;
+        let mut failed_starts: u32 = 0;
+
+        if !want_running {
+            info!(event = "service_down", service = name; "service is down, start it on request");
+        } else {
+            select! {
+                failed = self.wait_dependencies() => {
+                    if let Some(dep) = failed {
+                        error!(event = "dependency_failed", service = name, dependency = dep.as_str(); "required service {} did not start", dep);
+                        self.update(|rt| {
+                            rt.state = ServiceState::Failed;
+                            rt.last_failure = Some(format!("required service {} did not start", dep));
+                        });
+                        self.readiness.send_replace(Readiness::Failed);
+                        want_running = false;
+                    }
+                },
+                _ = cancel.cancelled() => {},
+            }
+        }
+
+        'supervise: loop {
+            if cancel.is_cancelled() {
+                break 'supervise;
+            }
+
+            if let Some(delay) = delay.take().filter(|_| { want_running }) {
+                info!(event = "service_backoff", service = name, delay_secs = delay.as_secs(); "restarting after {}s", delay.as_secs());
+                self.update(|rt| { rt.state = ServiceState::Backoff; });
+                select! {
+                    _ = sleep(delay) => {},
+                    cmd = rx.recv() => {
+                        match cmd {
+                            Some(ServiceCommand::Start) | Some(ServiceCommand::Restart) => {
+                                backoff = None;
+                                failed_starts = 0;
+                            },
+                            Some(ServiceCommand::Stop) => want_running = false,
+                            Some(ServiceCommand::Reload) => {},
+                            None => break 'supervise,
+                        }
                     },
-                    config::Command::ShellPrefixed(s) => {
-                        let shell = shell.clone();
-                        let exp_str = s.clone();
-                        let exp_shell = shell.clone();
-                        child = Command::new(shell).arg(s).spawn().map_err(|e| {
-                            WingmateInitError::SpawnError { source: e, message: format!("{} {}", exp_shell, exp_str) }
-                        })?;
-                    } 
+                    _ = cancel.cancelled() => break 'supervise,
                 }
+                continue 'supervise;
+            }
 
+            if !want_running {
+                self.update(|rt| {
+                    if rt.state != ServiceState::Failed {
+                        rt.state = ServiceState::Stopped;
+                    }
+                });
                 select! {
-                    _ = cancel.cancelled() => {
-                        if let Some(id) = child.id() {
-                            match kill(Pid::from_raw(id as i32), Some(Signal::SIGTERM)) {
-                                Ok(_) => {
-                                    select! {
-                                        _ = sleep(Duration::from_secs(config::MAX_TERM_WAIT_TIME_SECS)) => {
-                                            child.kill().await.expect("failed to kill process");
-                                        },
-                                        result = child.wait() => {
-                                            if let Err(e) = result_match(result) {
-                                                return Err(WingmateInitError::ChildExit { source: e });
-                                            }
-                                            break 'autorestart;
-                                        }
+                    cmd = rx.recv() => {
+                        match cmd {
+                            Some(ServiceCommand::Start) | Some(ServiceCommand::Restart) => {
+                                want_running = true;
+                                backoff = None;
+                                failed_starts = 0;
+                            },
+                            Some(ServiceCommand::Stop) | Some(ServiceCommand::Reload) => {},
+                            None => break 'supervise,
+                        }
+                    },
+                    _ = cancel.cancelled() => break 'supervise,
+                }
+                continue 'supervise;
+            }
+
+            self.update(|rt| { rt.state = ServiceState::Starting; });
+            let mut ready = false;
+            let pre_failed = self.run_start_pre().await.err();
+            let (stopped, exit) = if let Some(exit) = pre_failed {
+                warn!(event = "service_start_pre_failed", service = name; "command before the service failed");
+                (Stopped::Exited, exit)
+            } else {
+                let mut child = match self.spawn() {
+                    Ok(c) => c,
+                    Err(WingmateInitError::SpawnError { source, message }) => {
+                        error!(event = "spawn_failed", service = name, error:% = source; "failed to spawn: {}", message);
+                        self.update(|rt| {
+                            rt.state = ServiceState::Failed;
+                            rt.last_failure = Some(format!("spawning {}: {}", message, source));
+                        });
+                        self.readiness.send_replace(Readiness::Failed);
+                        want_running = false;
+                        continue 'supervise;
+                    },
+                    Err(e) => return Err(e),
+                };
+                self.sink.attach(&mut child);
+                let mut pid = child.id().unwrap_or_default();
+                self.reaped.register(pid);
+                if self.holds_terminal() {
+                    tty::acquired(pid);
+                }
+                self.update(|rt| {
+                    rt.state = ServiceState::Running;
+                    rt.pid = Some(pid);
+                    rt.started_at = Some(OffsetDateTime::now_utc());
+                });
+                info!(event = "service_started", service = name, pid = pid; "service started");
+                let mut main = Main::Child(child);
+
+                let ready_at = sleep(self.svc.start_secs);
+                tokio::pin!(ready_at);
+
+                let (stopped, exit) = 'running: loop {
+                    select! {
+                        _ = &mut ready_at, if !ready && self.svc.service_type == config::ServiceType::Simple => {
+                            ready = true;
+                            backoff = None;
+                            failed_starts = 0;
+                            self.mark_ready();
+                        },
+                        message = notified(&self.notify), if self.notify.is_some() => {
+                            if !ready && message.lines().any(|l| { l == NOTIFY_READY }) {
+                                ready = true;
+                                backoff = None;
+                                failed_starts = 0;
+                                self.mark_ready();
+                            }
+                        },
+                        result = self.wait_main(&mut main, pid) => {
+                            let exit = result?;
+                            let success = exit == Some(Exit::Code(0));
+                            if !ready && success && self.svc.service_type == config::ServiceType::Forking {
+                                self.reaped.release(pid);
+                                let daemon = self.read_pid_file().await;
+                                pid = daemon.unwrap_or_default();
+                                self.reaped.register(pid);
+                                self.update(|rt| { rt.pid = daemon; });
+                                info!(event = "service_forked", service = name, pid = pid; "service daemon started");
+                                main = Main::Daemon(daemon);
+                                ready = true;
+                                backoff = None;
+                                failed_starts = 0;
+                                self.mark_ready();
+                                continue 'running;
+                            }
+                            if !ready && success && self.svc.service_type == config::ServiceType::Oneshot {
+                                ready = true;
+                                self.mark_ready();
+                            }
+                            break 'running (Stopped::Exited, exit);
+                        },
+                        cmd = rx.recv() => {
+                            match cmd {
+                                Some(ServiceCommand::Start) => {},
+                                Some(ServiceCommand::Reload) => {
+                                    if let Some(reload) = &self.svc.reload {
+                                        info!(event = "service_reloading", service = name, pid = pid; "reloading service on request");
+                                        self.run_helper("reload", reload, &[], Some(pid), Duration::from_secs(HELPER_TIMEOUT_SECS)).await;
                                     }
                                 },
-                                Err(e) => {
-                                    if e != Errno::ESRCH {
-                                        return Err(WingmateInitError::ChildNotFound);
-                                    } else {
-                                        break 'autorestart;
-                                    }
-                                }
+                                Some(ServiceCommand::Stop) => {
+                                    info!(event = "service_stopping", service = name, pid = pid; "stopping service on request");
+                                    break 'running (Stopped::Requested, self.terminate(&mut main, pid).await?);
+                                },
+                                Some(ServiceCommand::Restart) => {
+                                    info!(event = "service_stopping", service = name, pid = pid; "restarting service on request");
+                                    break 'running (Stopped::Restart, self.terminate(&mut main, pid).await?);
+                                },
+                                None => break 'running (Stopped::Shutdown, self.terminate(&mut main, pid).await?),
                             }
-                        } else {
-                            break 'autorestart;
+                        },
+                        _ = cancel.cancelled() => {
+                            break 'running (Stopped::Shutdown, self.terminate(&mut main, pid).await?);
+                        },
+                    }
+                };
+
+                self.reaped.release(pid);
+                if self.holds_terminal() {
+                    tty::release();
+                }
+                log_service_exit(name, pid, exit);
+                (stopped, exit)
+            };
+
+            self.update(|rt| {
+                rt.state = ServiceState::Stopped;
+                rt.pid = None;
+                rt.last_exit = exit;
+            });
+            if pre_failed.is_none() {
+                self.run_finish(exit).await;
+            }
+
+            match stopped {
+                Stopped::Exited => {
+                    if cancel.is_cancelled() {
+                        break 'supervise;
+                    }
+
+                    let start_secs = self.svc.start_secs.as_secs();
+                    let success = exit == Some(Exit::Code(0));
+                    let reason = match exit {
+                        Some(e) if pre_failed.is_some() => format!("command before starting: {}", e),
+                        Some(e) if !ready => format!("{} within {}s of starting", e, start_secs),
+                        Some(e) => format!("unexpected {}", e),
+                        None if pre_failed.is_some() => String::from("command before starting failed"),
+                        None if !ready => format!("exited within {}s of starting", start_secs),
+                        None => String::from("unexpected exit"),
+                    };
+
+                    let restart = match self.svc.restart {
+                        config::Restart::Always => true,
+                        config::Restart::OnFailure => !success || pre_failed.is_some(),
+                        config::Restart::Never => false,
+                    };
+                    if !restart {
+                        info!(event = "service_done", service = name; "service exited, not restarting it");
+                        if !success || pre_failed.is_some() {
+                            self.update(|rt| { rt.last_failure = Some(reason); });
                         }
-                    },
-                    result = child.wait() => {
-                        if let Err(e) = result_match(result) {
-                            return Err(WingmateInitError::ChildExit { source: e });
+                        if !ready {
+                            self.readiness.send_replace(Readiness::Failed);
+                        }
+                        want_running = false;
+                        continue 'supervise;
+                    }
+
+                    self.update(|rt| {
+                        rt.restarts += 1;
+                        rt.last_failure = Some(reason.clone());
+                    });
+
+                    if !ready {
+                        failed_starts += 1;
+                        if self.svc.start_retries.is_some_and(|r| { failed_starts > r }) {
+                            error!(event = "service_failed", service = name, attempts = failed_starts; "giving up after {} failed starts", failed_starts);
+                            self.update(|rt| {
+                                rt.state = ServiceState::Failed;
+                                rt.last_failure = Some(format!("{}, gave up after {} failed starts", reason, failed_starts));
+                            });
+                            self.readiness.send_replace(Readiness::Failed);
+                            want_running = false;
+                            continue 'supervise;
                         }
+
+                        // crashing right away; do not spin
+                        backoff = Some(match backoff {
+                            Some(d) => (d * 2).min(Duration::from_secs(BACKOFF_MAX_SECS)),
+                            None => Duration::from_secs(BACKOFF_INITIAL_SECS),
+                        });
+                        delay = backoff;
+                    }
+                    if let Some(d) = self.svc.restart_delay {
+                        delay = Some(delay.map_or(d, |b| { b.max(d) }));
+                    }
+                },
+                Stopped::Requested => want_running = false,
+                Stopped::Restart => {},
+                Stopped::Shutdown => break 'supervise,
+            }
+        }
+
+        self.update(|rt| {
+            rt.state = ServiceState::Stopped;
+            rt.pid = None;
+        });
+        debug!(service = name; "service task completed");
+        Ok(())
+    }
+
+    /// Waits until every dependency has started or failed, and returns the
+    /// name of a required one that failed or does not exist.
+    async fn wait_dependencies(&mut self) -> Option<String> {
+        for dep in self.dependencies.iter_mut() {
+            let rx = match &mut dep.readiness {
+                Some(rx) => rx,
+                None if dep.required => return Some(dep.name.clone()),
+                None => continue,
+            };
+
+            debug!(service = self.name.as_str(); "waiting for {}", dep.name);
+            let state = match rx.wait_for(|r| { *r != Readiness::Pending }).await {
+                Ok(r) => *r,
+                // the task of the dependency is gone
+                Err(_) => Readiness::Failed,
+            };
+            if state == Readiness::Failed && dep.required {
+                return Some(dep.name.clone());
+            }
+        }
+        None
+    }
+
+    /// Whether this is the foreground service and there is a terminal for it.
+    fn holds_terminal(&self) -> bool {
+        self.svc.foreground && tty::active()
+    }
+
+    fn mark_ready(&self) {
+        self.update(|rt| { rt.state = ServiceState::Ready; });
+        self.readiness.send_replace(Readiness::Ready);
+    }
+
+    fn spawn(&self) -> Result<Child, WingmateInitError> {
+        let (mut command, exp_str) = build_command(&self.svc.command, &self.shell);
+        self.prepare(&mut command).map_err(|e| {
+            WingmateInitError::SpawnError { source: e, message: exp_str.clone() }
+        })?;
+        if let Some((_, address)) = &self.notify {
+            command.env(NOTIFY_SOCKET_ENV, address);
+        }
+        if self.holds_terminal() {
+            tty::isolate(&mut command, true);
+        } else {
+            self.sink.prepare(&mut command);
+        }
+        if let Some(pipe) = &self.log_pipe {
+            let stdout = pipe.try_clone().map_err(|e| {
+                WingmateInitError::SpawnError { source: e, message: exp_str.clone() }
+            })?;
+            command.stdout(stdout);
+        }
+        command.spawn().map_err(|e| {
+            WingmateInitError::SpawnError { source: e, message: exp_str }
+        })
+    }
+
+    fn prepare(&self, command: &mut Command) -> io::Result<()> {
+        if let Some(dir) = &self.svc.directory {
+            command.current_dir(dir);
+        }
+        for (key, value) in self.svc.environment.iter() {
+            match value {
+                Some(v) => command.env(key, v),
+                None => command.env_remove(key),
+            };
+        }
+        if let Some(user) = &self.svc.user {
+            let (uid, gid) = resolve_user(user)?;
+            command.uid(uid).gid(gid);
+        }
+        if let Some(group) = &self.svc.group {
+            command.gid(resolve_group(group)?);
+        }
+        Ok(())
+    }
+
+    /// Runs the commands meant to run before the service, in order, up to the
+    /// first failure not to be ignored, whose exit is returned.
+    async fn run_start_pre(&self) -> Result<(), Option<Exit>> {
+        for (pre, ignore_failure) in self.svc.start_pre.iter() {
+            let exit = self.run_helper("start-pre", pre, &[], None, Duration::from_secs(HELPER_TIMEOUT_SECS)).await;
+            if exit != Some(Exit::Code(0)) && !ignore_failure {
+                return Err(exit);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the finish script with the exit code, -1 after a signal, and the
+    /// signal number, 0 after a normal exit, like runit does.
+    async fn run_finish(&self, exit: Option<Exit>) {
+        if let Some(finish) = &self.svc.finish {
+            let (code, signal) = Exit::split(exit);
+            let args = [code.unwrap_or(-1).to_string(), signal.unwrap_or(0).to_string()];
+            self.run_helper("finish", finish, &args, None, Duration::from_secs(config::MAX_TERM_WAIT_TIME_SECS)).await;
+        }
+    }
+
+    /// Runs one of the commands accompanying the service in its environment,
+    /// killing it once `limit` is over. `main_pid` is passed as `MAINPID`.
+    async fn run_helper(&self, what: &str, helper: &config::Command, args: &[String], main_pid: Option<u32>, limit: Duration) -> Option<Exit> {
+        let (mut command, exp_str) = build_command(helper, &self.shell);
+        command.args(args);
+        if let Some(pid) = main_pid {
+            command.env("MAINPID", pid.to_string());
+        }
+        self.sink.prepare(&mut command);
+
+        let mut child = match self.prepare(&mut command).and_then(|_| { command.spawn() }) {
+            Ok(c) => c,
+            Err(e) => {
+                error!(event = "spawn_failed", service = self.name.as_str(), error:% = e; "failed to spawn: {}", exp_str);
+                return None;
+            }
+        };
+        let pumps = self.sink.attach(&mut child);
+        let pid = child.id().unwrap_or_default();
+        self.reaped.register(pid);
+        debug!(service = self.name.as_str(), pid = pid; "running {} command", what);
+
+        let exit = select! {
+            result = child.wait() => self.exit_of(pid, result).await.ok().flatten(),
+            _ = sleep(limit) => {
+                warn!(service = self.name.as_str(), pid = pid; "{} command took too long, killing it", what);
+                let _ = child.kill().await;
+                Some(Exit::Signal(Signal::SIGKILL as i32))
+            },
+        };
+        self.reaped.release(pid);
+        for p in pumps {
+            let _ = p.await;
+        }
+        exit
+    }
+
+    /// The pid a forking service wrote, given a moment to show up.
+    async fn read_pid_file(&self) -> Option<u32> {
+        let path = self.svc.pid_file.as_ref()?;
+        for _ in 0..PID_FILE_ATTEMPTS {
+            let pid = fs::read_to_string(path).ok().and_then(|c| { c.trim().parse::<u32>().ok() });
+            if pid.is_some() {
+                return pid;
+            }
+            sleep(Duration::from_millis(PID_FILE_WAIT_MILLIS)).await;
+        }
+        warn!(service = self.name.as_str(); "no pid found in {}", path.to_string_lossy());
+        None
+    }
+
+    async fn wait_main(&self, main: &mut Main, pid: u32) -> Result<Option<Exit>, WingmateInitError> {
+        match main {
+            Main::Child(child) => {
+                let result = child.wait().await;
+                self.exit_of(pid, result).await
+            },
+            Main::Daemon(Some(daemon)) => Ok(wait_daemon(*daemon, &self.reaped).await),
+            Main::Daemon(None) => std::future::pending().await,
+        }
+    }
+
+    /// Runs the stop command, if any, then sends the stop signal and falls
+    /// back to KILL once the stop timeout is over.
+    async fn terminate(&self, main: &mut Main, pid: u32) -> Result<Option<Exit>, WingmateInitError> {
+        self.update(|rt| { rt.state = ServiceState::Stopping; });
+        if let Some(stop) = &self.svc.stop {
+            self.run_helper("stop", stop, &[], Some(pid), self.svc.stop_timeout).await;
+        }
+
+        match main {
+            Main::Child(child) if child.id().is_none() => return Ok(self.reaped.take(pid).await),
+            Main::Daemon(None) => return Ok(None),
+            _ => {},
+        }
+
+        match kill(Pid::from_raw(pid as i32), Some(self.svc.stop_signal)) {
+            Ok(_) => {
+                select! {
+                    _ = sleep(self.svc.stop_timeout) => {
+                        let killed = match main {
+                            Main::Child(child) => child.kill().await,
+                            Main::Daemon(_) => kill(Pid::from_raw(pid as i32), Signal::SIGKILL).map_err(io::Error::from),
+                        };
+                        if let Err(e) = killed {
+                            warn!(service = self.name.as_str(); "killing {}: {}", pid, e);
+                        }
+                        Ok(Some(Exit::Signal(Signal::SIGKILL as i32)))
                     },
+                    result = self.wait_main(main, pid) => result,
                 }
+            },
+            Err(Errno::ESRCH) => Ok(self.reaped.take(pid).await),
+            Err(_) => Err(WingmateInitError::ChildNotFound),
+        }
+    }
+
+    async fn exit_of(&self, pid: u32, result: tokio_result<ExitStatus>) -> Result<Option<Exit>, WingmateInitError> {
+        match result_match(result) {
+            Ok(Some(status)) => Ok(Exit::from_status(status)),
+            Ok(None) => Ok(self.reaped.take(pid).await),
+            Err(e) => Err(WingmateInitError::ChildExit { source: e }),
+        }
+    }
+
+    fn update<F: FnOnce(&mut ServiceRuntime)>(&self, f: F) {
+        f(&mut self.runtime.lock().unwrap());
+    }
+}
+
+/// Binds the socket a notify service reports on, in the abstract namespace
+/// so nothing is left behind.
+fn notify_socket(name: &str) -> io::Result<(UnixDatagram, String)> {
+    let generation = NOTIFY_GENERATION.fetch_add(1, Ordering::Relaxed);
+    let address = format!("wingmate/{}/{}/{}", std::process::id(), generation, name);
+    let socket = StdUnixDatagram::bind_addr(&SocketAddr::from_abstract_name(address.as_bytes())?)?;
+    socket.set_nonblocking(true)?;
+    Ok((UnixDatagram::from_std(socket)?, format!("@{}", address)))
+}
+
+/// The next message on the notify socket. Messages keep being read after
+/// readiness so a chatty service never blocks on a full socket.
+async fn notified(notify: &Option<(UnixDatagram, String)>) -> String {
+    let socket = match notify {
+        Some((s, _)) => s,
+        None => return std::future::pending().await,
+    };
+
+    let mut buf = [0u8; NOTIFY_BUFFER_SIZE];
+    match socket.recv(&mut buf).await {
+        Ok(n) => String::from_utf8_lossy(&buf[..n]).into_owned(),
+        Err(e) => {
+            warn!("reading notify socket: {}", e);
+            std::future::pending().await
+        }
+    }
+}
+
+/// A daemon is no child of the task; it is gone once signalling it fails, and
+/// its exit is known when wingmate is the one reaping it.
+async fn wait_daemon(pid: u32, reaped: &Reaped) -> Option<Exit> {
+    loop {
+        sleep(Duration::from_millis(DAEMON_POLL_MILLIS)).await;
+        if kill(Pid::from_raw(pid as i32), None) == Err(Errno::ESRCH) {
+            return reaped.take(pid).await;
+        }
+    }
+}
+
+/// Runs `log/run` of a service directory on the read end of the log pipe,
+/// restarting it until the service task is gone.
+struct Logger {
+    name: String,
+    command: config::Command,
+    shell: String,
+    directory: Option<PathBuf>,
+    sink: output::Sink,
+    reaped: Reaped,
+}
+
+impl Logger {
+    async fn run(self, reader: io::PipeReader, stopped: CancellationToken) -> Result<(), WingmateInitError> {
+        let name = self.name.as_str();
+
+        'log: loop {
+            let (mut command, exp_str) = build_command(&self.command, &self.shell);
+            if let Some(dir) = &self.directory {
+                command.current_dir(dir);
             }
-            dbg!("starter: task completed");
-            Ok(())
-        });
+            self.sink.prepare(&mut command);
+
+            let spawned = reader.try_clone().and_then(|stdin| {
+                command.stdin(stdin);
+                command.spawn()
+            });
+            match spawned {
+                Ok(mut child) => {
+                    let pumps = self.sink.attach(&mut child);
+                    let pid = child.id().unwrap_or_default();
+                    self.reaped.register(pid);
+                    info!(event = "service_log_started", service = name, pid = pid; "log service started");
+                    // ends once every writer of the pipe is gone
+                    let _ = child.wait().await;
+                    self.reaped.release(pid);
+                    for p in pumps {
+                        let _ = p.await;
+                    }
+                    debug!(service = name, pid = pid; "log service exited");
+                },
+                Err(e) => {
+                    error!(event = "spawn_failed", service = name, error:% = e; "failed to spawn: {}", exp_str);
+                },
+            }
+
+            select! {
+                _ = sleep(Duration::from_secs(BACKOFF_INITIAL_SECS)) => {},
+                _ = stopped.cancelled() => break 'log,
+            }
+        }
 
+        Ok(())
     }
-    dbg!("starter: spawning completed");
+}
 
-    Ok(())
+/// Looks up a group name, or takes a numeric gid as is.
+pub(super) fn resolve_group(group: &str) -> io::Result<u32> {
+    let found = match group.parse::<u32>() {
+        Ok(gid) => Group::from_gid(Gid::from_raw(gid)),
+        Err(_) => Group::from_name(group),
+    };
+    match found {
+        Ok(Some(g)) => Ok(g.gid.as_raw()),
+        Ok(None) => Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown group {}", group))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Looks up a user name, or takes a numeric uid as is, returning the uid
+/// and the primary group.
+pub(super) fn resolve_user(user: &str) -> io::Result<(u32, u32)> {
+    let found = match user.parse::<u32>() {
+        Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+        Err(_) => User::from_name(user),
+    };
+    match found {
+        Ok(Some(u)) => Ok((u.uid.as_raw(), u.gid.as_raw())),
+        Ok(None) => Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown user {}", user))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn build_command(command: &config::Command, shell: &str) -> (Command, String) {
+    match command {
+        config::Command::Direct(c) => {
+            (Command::new(c), c.clone())
+        },
+        config::Command::ShellPrefixed(s) => {
+            let mut cmd = Command::new(shell);
+            cmd.arg(s);
+            (cmd, format!("{} {}", shell, s))
+        },
+        config::Command::Inline(i) => {
+            let mut cmd = Command::new(shell);
+            cmd.arg("-c").arg(i);
+            (cmd, format!("{} -c {}", shell, i))
+        },
+    }
 }
 
-fn result_match(result: tokio_result<ExitStatus>) -> Result<(), anyhow::Error> {
-    if let Err(e) = result {
-        if let Some(eos) = e.raw_os_error() {
-            if eos != nix::Error::ECHILD as i32 {
-                return Err(e).context("unexpected child exit status");
+/// The reaper may collect the child before tokio does, in which case the exit
+/// status is only known to the reaper and `None` is returned here.
+pub(super) fn result_match(result: tokio_result<ExitStatus>) -> Result<Option<ExitStatus>, anyhow::Error> {
+    match result {
+        Ok(status) => Ok(Some(status)),
+        Err(e) => {
+            if let Some(eos) = e.raw_os_error() {
+                if eos != nix::Error::ECHILD as i32 {
+                    return Err(e).context("unexpected child exit status");
+                }
+            } else {
+                return Err(e).context("unexpected child error");
             }
-        } else {
-            return Err(e).context("unexpected child error");
+            Ok(None)
+        }
+    }
+}
+
+fn log_service_exit(name: &str, pid: u32, exit: Option<Exit>) {
+    match exit {
+        Some(Exit::Code(code)) => {
+            info!(event = "service_exited", service = name, pid = pid, exit_code = code; "service exited");
+        },
+        Some(Exit::Signal(sig)) => {
+            info!(event = "service_exited", service = name, pid = pid, signal = sig; "service killed by signal");
+        },
+        None => {
+            info!(event = "service_exited", service = name, pid = pid; "service exited");
         }
     }
+}
+
+/// The wall clock cron entries are matched against. `WINGMATE_TIME_OFFSET`
+/// overrides the local offset with a whole number of hours.
+pub fn cron_time() -> Result<OffsetDateTime, IndeterminateOffset> {
+    if let Ok(offset) = env::var(ENV_UTC_OFFSET) {
+        if let Ok(i_off) = offset.parse::<i8>() {
+            if let Ok(utc_offset) = UtcOffset::from_hms(i_off, 0, 0) {
+                return Ok(OffsetDateTime::now_utc().to_offset(utc_offset));
+            }
+        }
+    }
+    OffsetDateTime::now_local()
+}
 
+pub fn check_cron(c_: &config::Crontab) -> Result<(), WingmateInitError> {
+    if c_.day_of_month != config::CronTimeFieldSpec::Any
+        && c_.day_of_week != config::CronTimeFieldSpec::Any {
+            return Err(WingmateInitError::CronConfig { source: CronConfigError::ClashingConfig });
+    }
     Ok(())
 }
 
-pub fn start_cron(ts: &mut JoinSet<Result<(), WingmateInitError>>, cfg: &config::Config, cancel: CancellationToken)
-    -> Result<(), WingmateInitError> {
+pub fn start_cron(ts: &mut JoinSet<Result<(), WingmateInitError>>, c_: &config::Crontab, capture: bool, reaped: Reaped, cancel: CancellationToken)
+    -> Result<CronHandle, WingmateInitError> {
 
-    dbg!("cron: starting");
-    for c_ in cfg.get_cron_iter() {
-        let cron = c_.clone();
-        let in_loop_cancel = cancel.clone();
-        dbg!("cron: item", c_);
+    check_cron(c_)?;
 
-        ts.spawn(async move {
-            if cron.day_of_month != config::CronTimeFieldSpec::Any
-                && cron.day_of_week != config::CronTimeFieldSpec::Any {
-                    return Err(WingmateInitError::CronConfig { source: CronConfigError::ClashingConfig });
-            }
+    let cron = c_.clone();
+    let sink = output::Sink::new(&c_.name, &c_.output, capture);
+    let history = sink.history();
+    let in_loop_cancel = cancel.clone();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let runtime = Arc::new(Mutex::new(CronRuntime::default()));
+    let task_runtime = runtime.clone();
+    debug!(cron = c_.name.as_str(); "cron entry: {:?}", c_);
 
-            dbg!("cron: async task spawned");
+    ts.spawn(async move {
+        let mut cron_interval = interval(Duration::from_secs(CRON_TRIGGER_WAIT_SECS));
+        let mut cron_procs: JoinSet<Result<(), WingmateInitError>> = JoinSet::new();
+        let mut last_running: Option<OffsetDateTime> = None;
+        let mut run_now = false;
+        'continuous: loop {
+            let mut flag = task_runtime.lock().unwrap().enabled;
 
-            let cron = cron.clone();
-            let mut cron_interval = interval(Duration::from_secs(CRON_TRIGGER_WAIT_SECS));
-            let mut cron_procs: JoinSet<Result<(), WingmateInitError>> = JoinSet::new();
-            let mut last_running: Option<OffsetDateTime> = None;
-            'continuous: loop {
-                let cron = cron.clone();
-                let cron_proc_cancel = in_loop_cancel.clone();
-                dbg!("cron: single: in loop", &cron.command);
-                
-                let mut flag = true;
-
-                let tr: Result<OffsetDateTime, IndeterminateOffset>;
-                if let Ok(offset) = env::var(ENV_UTC_OFFSET) {
-                    if let Ok(i_off) =  offset.parse::<i8>() {
-                        let utc_time = OffsetDateTime::now_utc().to_offset(UtcOffset::from_hms(i_off, 0, 0).unwrap());
-                        tr = Ok(utc_time);
+            let tr = cron_time();
+            if let Ok(local_time) = tr {
+                trace!(cron = cron.name.as_str(); "current local time {}", &local_time);
+                if let Some(last) = last_running {
+                    trace!(cron = cron.name.as_str(); "last running instance {}", &last);
+                    if local_time - last < TimeDur::minutes(1) {
+                        flag = false;
                     } else {
-                        tr = OffsetDateTime::now_local();
+                        flag = flag && cron.is_due(&local_time);
                     }
                 } else {
-                    tr = OffsetDateTime::now_local();
+                    flag = flag && cron.is_due(&local_time);
                 }
-                // let tr = OffsetDateTime::now_local();
-                if let Ok(local_time) = tr {
-                    dbg!("cron: current local time", &local_time);
-                    if let Some(last) = last_running {
-                        dbg!("cron: last runing instance", &last);
-                        if local_time - last < TimeDur::minutes(1) {
-                            flag = false;
-                        } else {
-                            flag = flag && cron.minute.is_match(local_time.minute()) &&
-                                cron.hour.is_match(local_time.hour()) &&
-                                cron.day_of_month.is_match(local_time.day()) &&
-                                cron.day_of_week.is_match(weekday_map(local_time.weekday()));
-                        }
-                    } else {
-                        flag = flag && cron.minute.is_match(local_time.minute()) &&
-                            cron.hour.is_match(local_time.hour()) &&
-                            cron.day_of_month.is_match(local_time.day()) &&
-                            cron.day_of_week.is_match(weekday_map(local_time.weekday()));
-                    }
 
-                    if flag {
-                        dbg!("cron: timing: hit: {}", &cron.command);
-                        last_running = Some(local_time);
-                        cron_procs.spawn(async move {
-                            run_cron_command(cron.command.clone(), cron_proc_cancel).await
-                        });
-                    }    
-                } else {
-                    dbg!("cron: unexpected error");
-                    if let Err(e) = tr {
-                        dbg!(e);
-                    }
+                if flag {
+                    last_running = Some(local_time);
                 }
+            } else if let Err(e) = tr {
+                warn!(cron = cron.name.as_str(); "cannot determine local time: {}", e);
+            }
 
-                if cron_procs.is_empty() {
-                    select! {
-                        _ = in_loop_cancel.cancelled() => {
-                            break 'continuous;
-                        },
-                        _ = cron_interval.tick() => {},
-                    }    
-                } else {
-                    'task: while !cron_procs.is_empty() {
-                        select! {
-                            opt_res = cron_procs.join_next() => {
-                                if let Some(res) = opt_res {
-                                    if let Err(e) = res {
-                                        eprintln!("running cron got problem {:?}", e);
-                                    }                                        
-                                }
-                            },
-                            _ = in_loop_cancel.cancelled() => {
-                                while let Some(res) = cron_procs.join_next().await {
-                                    if let Err(e) = res {
-                                        eprintln!("running cron got problem {:?}", e);
-                                    }                                        
-                                }
-                                break 'continuous;
+            if flag || run_now {
+                info!(event = "cron_triggered", cron = cron.name.as_str(); "running {}", &cron.command);
+                let cron = cron.clone();
+                let cron_sink = sink.clone();
+                let cron_proc_cancel = in_loop_cancel.clone();
+                let cron_runtime = task_runtime.clone();
+                let cron_reaped = reaped.clone();
+                cron_procs.spawn(async move {
+                    run_cron_command(cron.name.clone(), cron.command.clone(), cron_sink, cron_runtime, cron_reaped, cron_proc_cancel).await
+                });
+            }
+            run_now = false;
+
+            'wait: loop {
+                select! {
+                    Some(res) = cron_procs.join_next(), if !cron_procs.is_empty() => {
+                        match res {
+                            Ok(Err(WingmateInitError::SpawnError { source, message })) => {
+                                error!(event = "spawn_failed", cron = cron.name.as_str(), error:% = source; "failed to spawn: {}", message);
                             },
-                            _ = cron_interval.tick() => {
-                                break 'task;
+                            Ok(Err(e)) => error!(cron = cron.name.as_str(); "running cron got problem {}", e),
+                            Err(e) => error!("running cron got problem {}", e),
+                            Ok(Ok(())) => {},
+                        }
+                    },
+                    cmd = rx.recv() => {
+                        match cmd {
+                            Some(CronCommand::Enable) => task_runtime.lock().unwrap().enabled = true,
+                            Some(CronCommand::Disable) => task_runtime.lock().unwrap().enabled = false,
+                            Some(CronCommand::Run) => {
+                                run_now = true;
+                                break 'wait;
                             },
+                            // the handle is gone, so nothing tracks this entry any longer
+                            None => break 'continuous,
                         }
-                    }
+                    },
+                    _ = in_loop_cancel.cancelled() => break 'continuous,
+                    _ = cron_interval.tick() => {
+                        break 'wait;
+                    },
                 }
             }
-            Ok(())
-        });
-    }
-    Ok(())
-}
+        }
 
-fn weekday_map(wd: Weekday) -> u8 {
-    match wd {
-        Weekday::Sunday => 0,
-        Weekday::Monday => 1,
-        Weekday::Tuesday => 2,
-        Weekday::Wednesday => 3,
-        Weekday::Thursday => 4,
-        Weekday::Friday => 5,
-        Weekday::Saturday => 6
-    }
+        while let Some(res) = cron_procs.join_next().await {
+            if let Err(e) = res {
+                error!("running cron got problem {}", e);
+            }
+        }
+        Ok(())
+    });
+
+    Ok(CronHandle { name: c_.name.clone(), entry: c_.clone(), tx, runtime, history, cancel })
 }
 
-async fn run_cron_command(command: String, cancel: CancellationToken) -> Result<(), WingmateInitError> {
+async fn run_cron_command(name: String, command: String, sink: output::Sink, runtime: Arc<Mutex<CronRuntime>>, reaped: Reaped,
+    cancel: CancellationToken) -> Result<(), WingmateInitError> {
     let mut args: Vec<&str> = Vec::new();
     for part in command.split(' ') {
-        if part.len() > 0 {
+        if !part.is_empty() {
             args.push(part);
         }
     }
 
-    dbg!("cron: in running command");
     if args.is_empty() {
         return Err(WingmateInitError::Other { source: anyhow!("parsed as empty: {}", command) });
     }
 
-    let cmd = args.swap_remove(0);
-    let mut child: Child;
-    if args.is_empty() {
-        child = Command::new(cmd).spawn().map_err(|e| {
-            WingmateInitError::SpawnError { source: e, message: command }
-        })?;
-    } else {
-        child = Command::new(cmd).args(args.as_slice()).spawn().map_err(|e| {
-            WingmateInitError::SpawnError { source: e, message: command }
-        })?;
+    let cmd = args.remove(0);
+    let mut cron_command = Command::new(cmd);
+    if !args.is_empty() {
+        cron_command.args(args.as_slice());
+    }
+    sink.prepare(&mut cron_command);
+    let started_at = OffsetDateTime::now_utc();
+    let mut child = match cron_command.spawn() {
+        Ok(c) => c,
+        Err(e) => {
+            let error = format!("spawning {}: {}", command, e);
+            runtime.lock().unwrap().record(CronRun { started_at, finished_at: started_at, exit: None, error: Some(error) });
+            return Err(WingmateInitError::SpawnError { source: e, message: command });
+        }
+    };
+    let pumps = sink.attach(&mut child);
+    let pid = child.id().unwrap_or_default();
+    reaped.register(pid);
+    runtime.lock().unwrap().running.push(pid);
+    let result = wait_cron_command(&mut child, cancel).await;
+    runtime.lock().unwrap().running.retain(|p| { *p != pid });
+    let exit = match result {
+        Ok(Some(status)) => Exit::from_status(status),
+        Ok(None) => reaped.take(pid).await,
+        Err(e) => {
+            reaped.release(pid);
+            return Err(e);
+        }
+    };
+    reaped.release(pid);
+    log_cron_exit(&name, pid, exit);
+    runtime.lock().unwrap().record(CronRun { started_at, finished_at: OffsetDateTime::now_utc(), exit, error: None });
+
+    for p in pumps {
+        let _ = p.await;
     }
 
-    select! {
+    Ok(())
+}
+
+async fn wait_cron_command(child: &mut Child, cancel: CancellationToken) -> Result<Option<ExitStatus>, WingmateInitError> {
+    let result = select! {
         _ = cancel.cancelled() => {
             if let Some(id) = child.id() {
                 match kill(Pid::from_raw(id as i32), Some(Signal::SIGTERM)) {
-                    Ok(_) => {
-                        if let Err(e) = result_match(child.wait().await) {
-                            return Err(WingmateInitError::ChildExit { source: e });
-                        }            
-                    },
+                    Ok(_) => child.wait().await,
                     Err(e) => {
                         match e {
                             Errno::ESRCH => {
@@ -277,16 +1065,57 @@ async fn run_cron_command(command: String, cancel: CancellationToken) -> Result<
                                 return Err(WingmateInitError::FromNix { source: e });
                             }
                         }
-                    }        
+                    }
                 }
+            } else {
+                return Ok(None);
             }
         },
-        result = child.wait() => {
-            if let Err(e) = result_match(result) {
-                return Err(WingmateInitError::ChildExit { source: e });
-            }
+        result = child.wait() => result,
+    };
+
+    result_match(result).map_err(|e| { WingmateInitError::ChildExit { source: e } })
+}
+
+fn log_cron_exit(name: &str, pid: u32, exit: Option<Exit>) {
+    match exit {
+        Some(Exit::Code(code)) => {
+            info!(event = "cron_exited", cron = name, pid = pid, exit_code = code; "cron command exited");
+        },
+        Some(Exit::Signal(sig)) => {
+            info!(event = "cron_exited", cron = name, pid = pid, signal = sig; "cron command killed by signal");
+        },
+        None => {
+            info!(event = "cron_exited", cron = name, pid = pid; "cron command exited");
         }
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+    use crate::init::config::CronTimeFieldSpec;
+
+    #[tokio::test]
+    async fn cron_task_ends_with_its_handle() {
+        let entry = config::Crontab {
+            name: String::from("yearly"),
+            minute: CronTimeFieldSpec::Exact(0),
+            hour: CronTimeFieldSpec::Exact(0),
+            day_of_month: CronTimeFieldSpec::Exact(1),
+            month: CronTimeFieldSpec::Exact(1),
+            day_of_week: CronTimeFieldSpec::Any,
+            schedule: String::from("0 0 1 1 *"),
+            command: String::from("true"),
+            output: config::Output::default(),
+            critical: false,
+        };
+        let mut tasks: JoinSet<Result<(), WingmateInitError>> = JoinSet::new();
+        let handle = start_cron(&mut tasks, &entry, false, Reaped::default(), CancellationToken::new()).unwrap();
+
+        drop(handle);
+        let ended = timeout(Duration::from_secs(5), tasks.join_next()).await;
+        assert!(matches!(ended, Ok(Some(Ok(Ok(()))))));
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::{HashSet, VecDeque};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::time::sleep;
+
+const REAPED_HISTORY: usize = 256;
+const CRON_RUN_HISTORY: usize = 10;
+const REAPED_LOOKUP_ATTEMPTS: u32 = 10;
+const REAPED_LOOKUP_WAIT_MILLIS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceState {
+    Starting,
+    Running,
+    Ready,
+    Stopping,
+    Backoff,
+    Failed,
+    Stopped,
+}
+
+/// How a process ended, as reported by tokio or by the reaper.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Code(i32),
+    Signal(i32),
+}
+
+/// The status table entry of a service. `restarts` only counts the restarts
+/// after an unexpected exit, not the ones requested through the control socket.
+#[derive(Debug, Clone)]
+pub struct ServiceRuntime {
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    pub started_at: Option<OffsetDateTime>,
+    pub restarts: u32,
+    pub last_exit: Option<Exit>,
+    pub last_failure: Option<String>,
+}
+
+/// Besides the latest runs, the counters cover every run since wingmate
+/// started. A run fails when it cannot be started or exits unsuccessfully.
+#[derive(Debug, Clone)]
+pub struct CronRuntime {
+    pub enabled: bool,
+    pub running: Vec<u32>,
+    /// The latest completed runs, oldest first.
+    pub runs: VecDeque<CronRun>,
+    pub runs_total: u64,
+    pub failures_total: u64,
+    pub duration_total: Duration,
+    pub last_success: Option<OffsetDateTime>,
+}
+
+/// The status table entry of a timer.
+#[derive(Debug, Clone, Default)]
+pub struct TimerRuntime {
+    pub next_elapse: Option<OffsetDateTime>,
+    pub last_trigger: Option<OffsetDateTime>,
+}
+
+/// A finished run of a cron command. `error` is set when it could not be
+/// started at all.
+#[derive(Debug, Clone)]
+pub struct CronRun {
+    pub started_at: OffsetDateTime,
+    pub finished_at: OffsetDateTime,
+    pub exit: Option<Exit>,
+    pub error: Option<String>,
+}
+
+/// Exit statuses collected by the reaper. The reaper and tokio race for every
+/// child; when the reaper wins, this is the only place the status is known.
+/// Processes that were never registered by a service or cron task are
+/// counted as orphans.
+#[derive(Clone, Default)]
+pub struct Reaped {
+    inner: Arc<Mutex<ReapedInner>>,
+}
+
+#[derive(Default)]
+struct ReapedInner {
+    exits: VecDeque<(u32, Exit)>,
+    known: HashSet<u32>,
+    orphans: u64,
+}
+
+impl ServiceState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceState::Starting => "starting",
+            ServiceState::Running => "running",
+            ServiceState::Ready => "ready",
+            ServiceState::Stopping => "stopping",
+            ServiceState::Backoff => "backoff",
+            ServiceState::Failed => "failed",
+            ServiceState::Stopped => "stopped",
+        }
+    }
+}
+
+impl Exit {
+    pub fn from_status(status: ExitStatus) -> Option<Exit> {
+        if let Some(code) = status.code() {
+            Some(Exit::Code(code))
+        } else {
+            status.signal().map(Exit::Signal)
+        }
+    }
+
+    /// Splits into the exit code and signal fields of the status report.
+    pub fn split(exit: Option<Exit>) -> (Option<i32>, Option<i32>) {
+        match exit {
+            Some(Exit::Code(c)) => (Some(c), None),
+            Some(Exit::Signal(s)) => (None, Some(s)),
+            None => (None, None),
+        }
+    }
+}
+
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exit::Code(c) => write!(f, "exit code {}", c),
+            Exit::Signal(s) => write!(f, "signal {}", s),
+        }
+    }
+}
+
+impl Default for ServiceRuntime {
+    fn default() -> Self {
+        Self {
+            state: ServiceState::Starting,
+            pid: None,
+            started_at: None,
+            restarts: 0,
+            last_exit: None,
+            last_failure: None,
+        }
+    }
+}
+
+impl Default for CronRuntime {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            running: Vec::new(),
+            runs: VecDeque::with_capacity(CRON_RUN_HISTORY),
+            runs_total: 0,
+            failures_total: 0,
+            duration_total: Duration::ZERO,
+            last_success: None,
+        }
+    }
+}
+
+impl CronRuntime {
+    pub fn record(&mut self, run: CronRun) {
+        self.runs_total += 1;
+        if run.failed() {
+            self.failures_total += 1;
+        } else if run.exit == Some(Exit::Code(0)) {
+            self.last_success = Some(run.finished_at);
+        }
+        self.duration_total += (run.finished_at - run.started_at).try_into().unwrap_or(Duration::ZERO);
+
+        if self.runs.len() == CRON_RUN_HISTORY {
+            self.runs.pop_front();
+        }
+        self.runs.push_back(run);
+    }
+}
+
+impl CronRun {
+    pub fn failed(&self) -> bool {
+        self.error.is_some() || matches!(self.exit, Some(e) if e != Exit::Code(0))
+    }
+}
+
+impl Reaped {
+    pub fn record(&self, pid: u32, exit: Exit) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.known.contains(&pid) {
+            inner.orphans += 1;
+        }
+        if inner.exits.len() == REAPED_HISTORY {
+            inner.exits.pop_front();
+        }
+        inner.exits.push_back((pid, exit));
+    }
+
+    /// Marks `pid` as a child of wingmate's own tasks. The reaper may have
+    /// collected it already if it exited right away.
+    pub fn register(&self, pid: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.known.insert(pid);
+        if inner.exits.iter().any(|(p, _)| { *p == pid }) {
+            inner.orphans = inner.orphans.saturating_sub(1);
+        }
+    }
+
+    pub fn release(&self, pid: u32) {
+        self.inner.lock().unwrap().known.remove(&pid);
+    }
+
+    pub fn orphans(&self) -> u64 {
+        self.inner.lock().unwrap().orphans
+    }
+
+    /// Looks up the exit of `pid`, giving the reaper a moment to record it
+    /// in case tokio noticed the missing child first.
+    pub async fn take(&self, pid: u32) -> Option<Exit> {
+        for _ in 0..REAPED_LOOKUP_ATTEMPTS {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(i) = inner.exits.iter().rposition(|(p, _)| { *p == pid }) {
+                    return inner.exits.remove(i).map(|(_, e)| { e });
+                }
+            }
+            sleep(Duration::from_millis(REAPED_LOOKUP_WAIT_MILLIS)).await;
+        }
+        None
+    }
+}
@@ -0,0 +1,579 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU8, Ordering};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::select;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+use log::{debug, error, info, warn};
+use crate::init::config;
+use crate::init::daemon;
+use crate::init::daemon::output::LogHistory;
+use crate::init::daemon::sighandler;
+use crate::init::daemon::status::{Exit, Reaped, ServiceState};
+use crate::init::daemon::starter::{self, CronCommand, CronHandle, Dependency, ServiceCommand, ServiceHandle, Wait};
+use crate::init::daemon::timer::{self, TimerHandle};
+use crate::init::logger;
+use crate::init::error::WingmateInitError;
+use crate::protocol::{self, Operation, Request, Response, Target};
+
+const DEFAULT_LOG_LINES: usize = 50;
+
+pub type ControlMessage = (Request, oneshot::Sender<Reply>);
+
+/// Answer to a control request. `follow` carries the lines produced after the
+/// response for `logs --follow`.
+pub struct Reply {
+    pub response: Response,
+    pub follow: Option<broadcast::Receiver<String>>,
+}
+
+/// Owns the service, cron and timer tasks and keeps a handle to each of
+/// them, so they can be addressed by name from the control socket.
+pub struct Supervisor {
+    tasks: JoinSet<Result<(), WingmateInitError>>,
+    services: Vec<ServiceHandle>,
+    cron: Vec<CronHandle>,
+    timers: Vec<TimerHandle>,
+    boot: Instant,
+    trigger_tx: mpsc::UnboundedSender<String>,
+    trigger_rx: mpsc::UnboundedReceiver<String>,
+    reaped: Reaped,
+    health: config::HealthConfig,
+    search_path: Vec<String>,
+    search_mode: config::SearchMode,
+    inline: config::InlineConfig,
+    capture: bool,
+    stop_flag: Arc<Mutex<bool>>,
+    exit_code: Arc<AtomicU8>,
+    cancel: CancellationToken,
+}
+
+impl Supervisor {
+    pub fn new(cfg: &config::Config, stop_flag: Arc<Mutex<bool>>, exit_code: Arc<AtomicU8>, reaped: Reaped,
+        cancel: CancellationToken) -> Result<Self, WingmateInitError> {
+        let mut tasks: JoinSet<Result<(), WingmateInitError>> = JoinSet::new();
+
+        let capture = cfg.control.enabled && cfg.control.logs;
+
+        let mut services: Vec<ServiceHandle> = Vec::new();
+        for svc in cfg.start_order()? {
+            let wait = Wait { stopped: None, dependencies: dependencies(&services, svc) };
+            services.push(starter::start_service(&mut tasks, svc, cfg.get_shell(), capture, reaped.clone(), cancel.child_token(), wait)?);
+        }
+        debug!("spawning services completed");
+
+        let mut cron: Vec<CronHandle> = Vec::new();
+        for c in cfg.get_cron_iter() {
+            cron.push(starter::start_cron(&mut tasks, c, capture, reaped.clone(), cancel.child_token())?);
+        }
+
+        let boot = Instant::now();
+        let (trigger_tx, trigger_rx) = mpsc::unbounded_channel::<String>();
+        let timers = cfg.timers.iter().map(|t| {
+            timer::start_timer(&mut tasks, t, boot, trigger_tx.clone(), cancel.child_token())
+        }).collect();
+
+        Ok(Self {
+            tasks,
+            services,
+            cron,
+            timers,
+            boot,
+            trigger_tx,
+            trigger_rx,
+            reaped,
+            health: cfg.health.clone(),
+            search_path: cfg.get_search_path(),
+            search_mode: cfg.get_search_mode(),
+            inline: cfg.get_inline(),
+            capture,
+            stop_flag,
+            exit_code,
+            cancel,
+        })
+    }
+
+    /// Serves control requests until every task has finished after `cancel`.
+    /// Every notification on `reload` reads the configuration again.
+    pub async fn run(mut self, mut rx: mpsc::Receiver<ControlMessage>, mut reload: mpsc::Receiver<()>) -> Result<(), WingmateInitError> {
+        let mut control_open = true;
+        let mut reload_open = true;
+
+        'supervise: loop {
+            select! {
+                Some(res) = self.tasks.join_next(), if !self.tasks.is_empty() => {
+                    match res {
+                        Ok(Ok(())) => {},
+                        Ok(Err(e)) => return Err(e),
+                        Err(e) => return Err(WingmateInitError::Join { source: e }),
+                    }
+                },
+                msg = rx.recv(), if control_open => {
+                    match msg {
+                        Some((request, reply)) => {
+                            let _ = reply.send(self.handle(request));
+                        },
+                        None => control_open = false,
+                    }
+                },
+                Some(unit) = self.trigger_rx.recv() => self.trigger(&unit),
+                r = reload.recv(), if reload_open => {
+                    match r {
+                        Some(()) => {
+                            let _ = self.reload();
+                        },
+                        None => reload_open = false,
+                    }
+                },
+                _ = self.cancel.cancelled(), if self.tasks.is_empty() => break 'supervise,
+                else => break 'supervise,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, request: Request) -> Reply {
+        debug!("control request: {:?}", request.operation);
+        let response = match request.operation {
+            Operation::Status => {
+                let mut response = Response::ok();
+                response.status = Some(Box::new(self.status()));
+                response
+            },
+            Operation::Start { target } => self.command("start", &target, ServiceCommand::Start, CronCommand::Enable),
+            Operation::Stop { target } => self.command("stop", &target, ServiceCommand::Stop, CronCommand::Disable),
+            Operation::Restart { target } => match target {
+                Target::Service(_) => self.command("restart", &target, ServiceCommand::Restart, CronCommand::Run),
+                Target::Cron(name) => Response::error(format!("cron entry {} cannot be restarted, use run", name)),
+            },
+            Operation::Run { target } => match target {
+                Target::Cron(_) => self.command("run", &target, ServiceCommand::Start, CronCommand::Run),
+                Target::Service(name) => Response::error(format!("service {} cannot be run, use start", name)),
+            },
+            Operation::Signal { target, signal } => self.signal(&target, &signal),
+            Operation::Logs { target, follow, lines } => return self.logs(&target, follow, lines),
+            Operation::Health => {
+                let mut response = Response::ok();
+                response.health = Some(Box::new(self.health()));
+                response
+            },
+            Operation::Reload { target: None } => self.reload(),
+            Operation::Reload { target: Some(Target::Service(name)) } => self.reload_service(&name),
+            Operation::Reload { target: Some(Target::Cron(name)) } => Response::error(format!("cron entry {} cannot be reloaded", name)),
+            Operation::Shutdown { exit_code } => {
+                let code = exit_code.unwrap_or(0);
+                info!(event = "control", op = "shutdown", exit_code = code; "stopping");
+                self.exit_code.store(code, Ordering::Relaxed);
+                sighandler::initiate_stop(self.stop_flag.clone(), self.cancel.clone());
+                Response::ok()
+            },
+        };
+
+        Reply { response, follow: None }
+    }
+
+    /// Reads the configuration again and brings the running set in line with
+    /// it. Services and cron entries whose definition did not change keep
+    /// running untouched. Only services, cron entries and the health settings
+    /// are reloaded; the other sections take effect on the next start.
+    /// When the configuration cannot be read the current one stays in place.
+    fn reload(&mut self) -> Response {
+        if self.cancel.is_cancelled() {
+            return Response::error("shutting down");
+        }
+
+        let cfg = config::Config::find(self.search_path.clone(), self.search_mode, self.inline.clone()).and_then(|cfg| {
+            daemon::check(&cfg)?;
+            Ok(cfg)
+        });
+        let cfg = match cfg {
+            Ok(c) => c,
+            Err(e) => return reload_failed(e),
+        };
+        // nothing may be stopped before the new services are known to start
+        let order = match cfg.start_order() {
+            Ok(o) => o,
+            Err(e) => return reload_failed(e),
+        };
+
+        let mut changes: Vec<String> = Vec::new();
+        let mut failures: Vec<String> = Vec::new();
+
+        let mut old = std::mem::take(&mut self.services);
+        for svc in order {
+            let (previous, change) = match old.iter().position(|h| { h.name == svc.name }) {
+                Some(i) if same_process(&old[i].service, svc) => {
+                    let mut h = old.remove(i);
+                    h.service = svc.clone();
+                    self.services.push(h);
+                    continue;
+                },
+                Some(i) => {
+                    let h = old.remove(i);
+                    h.cancel.cancel();
+                    (Some(h.stopped), "restarted")
+                },
+                None => (None, "added"),
+            };
+
+            let wait = Wait { stopped: previous, dependencies: dependencies(&self.services, svc) };
+            match starter::start_service(&mut self.tasks, svc, cfg.get_shell(), self.capture, self.reaped.clone(), self.cancel.child_token(), wait) {
+                Ok(h) => {
+                    info!(event = "reload", service = svc.name.as_str(); "service {}", change);
+                    changes.push(format!("service {} {}", svc.name, change));
+                    self.services.push(h);
+                },
+                Err(e) => {
+                    error!(event = "reload", service = svc.name.as_str(), error:% = e; "cannot start service");
+                    failures.push(format!("starting service {}: {}", svc.name, e));
+                },
+            }
+        }
+        for h in old {
+            info!(event = "reload", service = h.name.as_str(); "service removed");
+            changes.push(format!("service {} removed", h.name));
+            h.cancel.cancel();
+        }
+
+        let mut old = std::mem::take(&mut self.cron);
+        for c in cfg.get_cron_iter() {
+            let change = match old.iter().position(|h| { h.name == c.name }) {
+                Some(i) if old[i].entry.same_job(c) => {
+                    let mut h = old.remove(i);
+                    h.entry = c.clone();
+                    self.cron.push(h);
+                    continue;
+                },
+                Some(i) => {
+                    old.remove(i).cancel.cancel();
+                    "replaced"
+                },
+                None => "added",
+            };
+
+            match starter::start_cron(&mut self.tasks, c, self.capture, self.reaped.clone(), self.cancel.child_token()) {
+                Ok(h) => {
+                    info!(event = "reload", cron = c.name.as_str(); "cron entry {}", change);
+                    changes.push(format!("cron entry {} {}", c.name, change));
+                    self.cron.push(h);
+                },
+                Err(e) => {
+                    error!(event = "reload", cron = c.name.as_str(), error:% = e; "cannot start cron entry");
+                    failures.push(format!("starting cron entry {}: {}", c.name, e));
+                },
+            }
+        }
+        for h in old {
+            info!(event = "reload", cron = h.name.as_str(); "cron entry removed");
+            changes.push(format!("cron entry {} removed", h.name));
+            h.cancel.cancel();
+        }
+
+        let mut old = std::mem::take(&mut self.timers);
+        for t in cfg.timers.iter() {
+            let change = match old.iter().position(|h| { h.name == t.name }) {
+                Some(i) if old[i].timer == *t => {
+                    self.timers.push(old.remove(i));
+                    continue;
+                },
+                Some(i) => {
+                    old.remove(i).cancel.cancel();
+                    "replaced"
+                },
+                None => "added",
+            };
+
+            info!(event = "reload", timer = t.name.as_str(); "timer {}", change);
+            changes.push(format!("timer {} {}", t.name, change));
+            self.timers.push(timer::start_timer(&mut self.tasks, t, self.boot, self.trigger_tx.clone(), self.cancel.child_token()));
+        }
+        for h in old {
+            info!(event = "reload", timer = h.name.as_str(); "timer removed");
+            changes.push(format!("timer {} removed", h.name));
+            h.cancel.cancel();
+        }
+
+        self.health = cfg.health.clone();
+        info!(event = "reload", changes = changes.len(); "configuration reloaded");
+
+        let mut response = if failures.is_empty() {
+            Response::ok()
+        } else {
+            Response::error(failures.join("; "))
+        };
+        response.lines = Some(changes);
+        response
+    }
+
+    fn status(&self) -> protocol::Status {
+        let now = OffsetDateTime::now_utc();
+        let services = self.services.iter().map(|h| {
+            let rt = h.runtime.lock().unwrap().clone();
+            let (last_exit_code, last_signal) = Exit::split(rt.last_exit);
+            // the start time of a process no longer running is of no interest
+            let started_at = rt.started_at.filter(|_| { rt.pid.is_some() });
+            protocol::ServiceStatus {
+                name: h.name.clone(),
+                state: String::from(rt.state.as_str()),
+                pid: rt.pid,
+                started_at: started_at.and_then(|t| { t.format(&Rfc3339).ok() }),
+                uptime_secs: started_at.map(|t| { (now - t).whole_seconds().max(0) as u64 }),
+                restarts: rt.restarts,
+                last_exit_code,
+                last_signal,
+                last_failure: rt.last_failure,
+            }
+        }).collect();
+
+        let cron_now = starter::cron_time().ok();
+        let cron = self.cron.iter().map(|h| {
+            let rt = h.runtime.lock().unwrap().clone();
+            let next_run = cron_now.filter(|_| { rt.enabled })
+                .and_then(|t| { h.entry.next_run(t) })
+                .and_then(|t| { t.format(&Rfc3339).ok() });
+            let last_runs = rt.runs.iter().map(|r| {
+                let (exit_code, signal) = Exit::split(r.exit);
+                protocol::CronRun {
+                    started_at: r.started_at.format(&Rfc3339).unwrap_or_default(),
+                    duration_ms: (r.finished_at - r.started_at).whole_milliseconds().max(0) as u64,
+                    exit_code,
+                    signal,
+                    error: r.error.clone(),
+                }
+            }).collect();
+            protocol::CronStatus {
+                name: h.name.clone(),
+                schedule: h.entry.schedule.clone(),
+                enabled: rt.enabled,
+                running: rt.running,
+                next_run,
+                last_runs,
+                runs_total: rt.runs_total,
+                failures_total: rt.failures_total,
+                duration_ms_total: rt.duration_total.as_millis() as u64,
+                last_success: rt.last_success.and_then(|t| { t.format(&Rfc3339).ok() }),
+            }
+        }).collect();
+
+        let timers = self.timers.iter().map(|h| {
+            let rt = h.runtime.lock().unwrap().clone();
+            protocol::TimerStatus {
+                name: h.name.clone(),
+                service: h.timer.unit.clone(),
+                schedule: h.timer.schedule(),
+                next_elapse: rt.next_elapse.and_then(|t| { t.format(&Rfc3339).ok() }),
+                last_trigger: rt.last_trigger.and_then(|t| { t.format(&Rfc3339).ok() }),
+            }
+        }).collect();
+
+        let forwarding = logger::forwarded().map(|c| {
+            protocol::ForwardingStatus { sent: c.sent, dropped: c.dropped, failed: c.failed }
+        });
+
+        protocol::Status { services, cron, timers, reaped_orphans: self.reaped.orphans(), forwarding }
+    }
+
+    /// Required services must be up unless they are down by configuration, and a critical cron entry must not have
+    /// failed all of its latest `failed_runs` runs.
+    fn health(&self) -> protocol::Health {
+        let mut problems: Vec<String> = Vec::new();
+
+        for h in self.services.iter().filter(|h| { h.service.required && h.service.enabled }) {
+            let rt = h.runtime.lock().unwrap();
+            // a service not meant to be restarted is done once it exited cleanly
+            let done = h.service.restart != config::Restart::Always && rt.state == ServiceState::Stopped
+                && rt.last_exit == Some(Exit::Code(0));
+            if !done && !matches!(rt.state, ServiceState::Running | ServiceState::Ready) {
+                problems.push(format!("service {} is {}", h.name, rt.state.as_str()));
+            }
+        }
+
+        let n = self.health.failed_runs;
+        for h in self.cron.iter().filter(|h| { h.entry.critical }) {
+            let rt = h.runtime.lock().unwrap();
+            if rt.runs.len() >= n && rt.runs.iter().rev().take(n).all(|r| { r.failed() }) {
+                problems.push(format!("cron entry {} failed its last {} run(s)", h.name, n));
+            }
+        }
+
+        protocol::Health { healthy: problems.is_empty(), problems }
+    }
+
+    fn command(&self, op: &str, target: &Target, svc_cmd: ServiceCommand, cron_cmd: CronCommand) -> Response {
+        let sent = match target {
+            Target::Service(name) => match self.service(name) {
+                Ok(h) => {
+                    info!(event = "control", op = op, service = name.as_str(); "{} requested", op);
+                    h.tx.send(svc_cmd).is_ok()
+                },
+                Err(r) => return r,
+            },
+            Target::Cron(name) => match self.cron_entry(name) {
+                Ok(h) => {
+                    info!(event = "control", op = op, cron = name.as_str(); "{} requested", op);
+                    h.tx.send(cron_cmd).is_ok()
+                },
+                Err(r) => return r,
+            },
+        };
+
+        if sent {
+            Response::ok()
+        } else {
+            Response::error("task is no longer running")
+        }
+    }
+
+    /// Starts the service of an elapsed timer. A service still running from
+    /// the previous elapse is left alone.
+    fn trigger(&self, unit: &str) {
+        if self.cancel.is_cancelled() {
+            return;
+        }
+        match self.service(unit) {
+            Ok(h) => {
+                let _ = h.tx.send(ServiceCommand::Start);
+            },
+            Err(_) => warn!(service = unit; "timer elapsed for unknown service {}", unit),
+        }
+    }
+
+    fn reload_service(&self, name: &str) -> Response {
+        let h = match self.service(name) {
+            Ok(h) => h,
+            Err(r) => return r,
+        };
+        if h.service.reload.is_none() {
+            return Response::error(format!("service {} has no reload command", name));
+        }
+        if h.runtime.lock().unwrap().pid.is_none() {
+            return Response::error(format!("service {} is not running", name));
+        }
+
+        info!(event = "control", op = "reload", service = name; "reload requested");
+        if h.tx.send(ServiceCommand::Reload).is_ok() {
+            Response::ok()
+        } else {
+            Response::error("task is no longer running")
+        }
+    }
+
+    fn signal(&self, target: &Target, signal: &str) -> Response {
+        let sig = match config::parse_signal(signal) {
+            Some(s) => s,
+            None => return Response::error(format!("unknown signal {}", signal)),
+        };
+
+        let pids: Vec<u32> = match target {
+            Target::Service(name) => match self.service(name) {
+                Ok(h) => h.runtime.lock().unwrap().pid.into_iter().collect(),
+                Err(r) => return r,
+            },
+            Target::Cron(name) => match self.cron_entry(name) {
+                Ok(h) => h.runtime.lock().unwrap().running.clone(),
+                Err(r) => return r,
+            },
+        };
+
+        if pids.is_empty() {
+            return Response::error("no running process");
+        }
+
+        for pid in pids {
+            if let Err(e) = kill(Pid::from_raw(pid as i32), sig) {
+                return Response::error(format!("sending {} to {}: {}", sig, pid, e));
+            }
+            info!(event = "control", op = "signal", pid = pid, signal = sig.as_str(); "signal sent");
+        }
+        Response::ok()
+    }
+
+    fn logs(&self, target: &Target, follow: bool, lines: Option<usize>) -> Reply {
+        let history: Result<Option<&LogHistory>, Response> = match target {
+            Target::Service(name) => self.service(name).map(|h| { h.history.as_ref() }),
+            Target::Cron(name) => self.cron_entry(name).map(|h| { h.history.as_ref() }),
+        };
+
+        let history = match history {
+            Ok(Some(h)) => h,
+            Ok(None) => return Reply { response: Response::error("output is not kept, set logs = true in [control] to keep it"), follow: None },
+            Err(r) => return Reply { response: r, follow: None },
+        };
+
+        // subscribe first so no line falls between the two
+        let follow = if follow { Some(history.subscribe()) } else { None };
+        let mut response = Response::ok();
+        response.lines = Some(history.tail(lines.unwrap_or(DEFAULT_LOG_LINES)));
+        Reply { response, follow }
+    }
+
+    fn service(&self, name: &str) -> Result<&ServiceHandle, Response> {
+        self.services.iter().find(|h| { h.name == name })
+            .ok_or_else(|| { Response::error(format!("unknown service {}", name)) })
+    }
+
+    fn cron_entry(&self, name: &str) -> Result<&CronHandle, Response> {
+        self.cron.iter().find(|h| { h.name == name })
+            .ok_or_else(|| { Response::error(format!("unknown cron entry {}", name)) })
+    }
+}
+
+fn reload_failed(e: WingmateInitError) -> Response {
+    let e = format!("{:#}", anyhow::Error::new(e));
+    error!(event = "reload_failed", error = e.as_str(); "keeping the current configuration");
+    Response::error(format!("reload failed, keeping the current configuration: {}", e))
+}
+
+/// Whether a running service can be kept as is; only being required by the
+/// health check does not concern the process.
+fn same_process(a: &config::Service, b: &config::Service) -> bool {
+    let mut a = a.clone();
+    a.required = b.required;
+    a == *b
+}
+
+/// What `svc` waits for among the services started so far.
+fn dependencies(started: &[ServiceHandle], svc: &config::Service) -> Vec<Dependency> {
+    svc.dependencies().into_iter().map(|(name, required)| {
+        Dependency {
+            name: String::from(name),
+            required,
+            readiness: started.iter().find(|h| { h.name == name }).map(|h| { h.readiness.clone() }),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use crate::init::config::{Command, Service};
+
+    #[test]
+    fn reload_keeps_same_process() {
+        let svc = Service::new(String::from("web"), Command::Inline(String::from("serve")));
+        assert!(same_process(&svc, &svc.clone()));
+
+        let mut required = svc.clone();
+        required.required = !svc.required;
+        assert!(same_process(&svc, &required));
+
+        let changes: [fn(&mut Service); 4] = [
+            |s| { s.command = Command::Inline(String::from("serve --port 80")) },
+            |s| { s.environment.push((String::from("PORT"), Some(String::from("80")))) },
+            |s| { s.modified = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1)) },
+            |s| { s.after.push(String::from("db")) },
+        ];
+        for change in changes {
+            let mut changed = svc.clone();
+            change(&mut changed);
+            assert!(!same_process(&svc, &changed), "{:?}", changed);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+mod message;
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::net::UnixDatagram;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use log::{Level, debug, log, warn};
+use crate::init::config;
+use crate::init::logger::SYSLOG_TARGET;
+use crate::init::error::WingmateInitError;
+
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// Receives messages written to the local syslog socket (usually `/dev/log`)
+/// and logs them through wingmate's own logger.
+pub async fn listen(cfg: config::SyslogConfig, cancel: CancellationToken) -> Result<(), WingmateInitError> {
+    let path = cfg.socket.as_path();
+    let socket = bind(path).map_err(|e| { WingmateInitError::Syslog { source: e } })?;
+    debug!("listening on {}", path.to_string_lossy());
+
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    'listen: loop {
+        select! {
+            result = socket.recv(&mut buf) => {
+                match result {
+                    Ok(n) => emit(&message::parse(&buf[..n])),
+                    Err(e) => warn!("receiving on {}: {}", path.to_string_lossy(), e),
+                }
+            },
+            _ = cancel.cancelled() => {
+                break 'listen;
+            }
+        }
+    }
+
+    let _ = fs::remove_file(path);
+    Ok(())
+}
+
+fn bind(path: &Path) -> std::io::Result<UnixDatagram> {
+    if let Ok(m) = fs::symlink_metadata(path) {
+        // only clean up a stale socket; never remove a regular file
+        if m.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let socket = UnixDatagram::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+    Ok(socket)
+}
+
+fn emit(msg: &message::Message) {
+    let level = match msg.severity {
+        0..=3 => Level::Error,
+        4 => Level::Warn,
+        5 | 6 => Level::Info,
+        _ => Level::Debug,
+    };
+
+    let ident = msg.app_name.as_deref().unwrap_or_default();
+    match msg.proc_id.as_deref() {
+        Some(pid) => {
+            log!(target: SYSLOG_TARGET, level, event = "syslog", ident = ident, pid = pid, facility = msg.facility, severity = msg.severity; "{}", msg.message);
+        },
+        None => {
+            log!(target: SYSLOG_TARGET, level, event = "syslog", ident = ident, facility = msg.facility, severity = msg.severity; "{}", msg.message);
+        }
+    }
+}
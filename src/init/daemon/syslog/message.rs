@@ -0,0 +1,306 @@
+/// A syslog message as received on the local socket. Fields that the sender
+/// left out (or set to the RFC 5424 nil value) are `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: Option<String>,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+    pub proc_id: Option<String>,
+    pub msg_id: Option<String>,
+    pub message: String,
+}
+
+const DEFAULT_PRI: u8 = 13; // user.notice
+const NIL: &str = "-";
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Parses either an RFC 5424 or an RFC 3164 (BSD) message. Anything that does
+/// not follow either format is kept as the message text.
+pub fn parse(buf: &[u8]) -> Message {
+    let text = String::from_utf8_lossy(buf);
+    let text = text.trim_end_matches(['\n', '\r', '\0']);
+
+    let (pri, rest) = parse_pri(text);
+    let mut msg = Message {
+        facility: pri >> 3,
+        severity: pri & 0x07,
+        timestamp: None,
+        hostname: None,
+        app_name: None,
+        proc_id: None,
+        msg_id: None,
+        message: String::new(),
+    };
+
+    if let Some(rest) = rest.strip_prefix("1 ") {
+        parse_rfc5424(rest, &mut msg);
+    } else {
+        parse_rfc3164(rest, &mut msg);
+    }
+
+    msg
+}
+
+fn parse_pri(text: &str) -> (u8, &str) {
+    if let Some(rest) = text.strip_prefix('<') {
+        if let Some(end) = rest.find('>') {
+            if end > 0 && end <= 3 {
+                if let Ok(pri) = rest[..end].parse::<u8>() {
+                    if pri <= 191 {
+                        return (pri, &rest[end + 1..]);
+                    }
+                }
+            }
+        }
+    }
+
+    (DEFAULT_PRI, text)
+}
+
+fn parse_rfc5424(text: &str, msg: &mut Message) {
+    let mut rest = text;
+    let mut header: Vec<Option<String>> = Vec::with_capacity(5);
+    for _ in 0..5 {
+        let (field, remaining) = next_token(rest);
+        header.push(nil_to_none(field));
+        rest = remaining;
+    }
+
+    let mut header = header.into_iter();
+    msg.timestamp = header.next().flatten();
+    msg.hostname = header.next().flatten();
+    msg.app_name = header.next().flatten();
+    msg.proc_id = header.next().flatten();
+    msg.msg_id = header.next().flatten();
+
+    let rest = skip_structured_data(rest);
+    let rest = rest.strip_prefix(' ').unwrap_or(rest);
+    let rest = rest.strip_prefix('\u{feff}').unwrap_or(rest);
+    msg.message = String::from(rest);
+}
+
+fn parse_rfc3164(text: &str, msg: &mut Message) {
+    let mut rest = text;
+
+    if let Some((timestamp, after)) = bsd_timestamp(rest) {
+        msg.timestamp = Some(String::from(timestamp));
+        rest = after.trim_start();
+    }
+
+    // The hostname is optional; glibc leaves it out on /dev/log. A first
+    // token that does not look like a tag followed by one that does is
+    // taken as the hostname.
+    let (first, after_first) = next_token(rest);
+    if !looks_like_tag(first) {
+        let (second, _) = next_token(after_first);
+        if looks_like_tag(second) {
+            msg.hostname = Some(String::from(first));
+            rest = after_first;
+        }
+    }
+
+    let (tag, after_tag) = next_token(rest);
+    if looks_like_tag(tag) {
+        let tag = tag.trim_end_matches(':');
+        match tag.find('[') {
+            Some(open) => {
+                msg.app_name = Some(String::from(&tag[..open]));
+                let pid = tag[open + 1..].trim_end_matches(']');
+                if !pid.is_empty() {
+                    msg.proc_id = Some(String::from(pid));
+                }
+            },
+            None => {
+                msg.app_name = Some(String::from(tag));
+            }
+        }
+        rest = after_tag;
+    }
+
+    msg.message = String::from(rest);
+}
+
+fn next_token(text: &str) -> (&str, &str) {
+    match text.find(' ') {
+        Some(i) => (&text[..i], &text[i + 1..]),
+        None => (text, ""),
+    }
+}
+
+fn nil_to_none(field: &str) -> Option<String> {
+    if field.is_empty() || field == NIL {
+        None
+    } else {
+        Some(String::from(field))
+    }
+}
+
+fn looks_like_tag(token: &str) -> bool {
+    token.len() > 1 && (token.ends_with(':') || token.ends_with(']'))
+}
+
+/// Splits off a leading "Mmm dd hh:mm:ss" timestamp, the day padded with a
+/// space.
+fn bsd_timestamp(text: &str) -> Option<(&str, &str)> {
+    let stamp = text.get(..15)?;
+    let b = stamp.as_bytes();
+    let digits = |at: &[usize]| { at.iter().all(|i| { b[*i].is_ascii_digit() }) };
+    let valid = MONTHS.iter().any(|m| { stamp.starts_with(m) })
+        && b[3] == b' '
+        && (b[4] == b' ' || b[4].is_ascii_digit())
+        && b[6] == b' '
+        && b[9] == b':'
+        && b[12] == b':'
+        && digits(&[5, 7, 8, 10, 11, 13, 14]);
+    if valid {
+        Some((stamp, &text[15..]))
+    } else {
+        None
+    }
+}
+
+fn skip_structured_data(text: &str) -> &str {
+    if let Some(rest) = text.strip_prefix(NIL) {
+        return rest;
+    }
+
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() && bytes[i] == b'[' {
+        let mut escaped = false;
+        i += 1;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' if !escaped => escaped = true,
+                b']' if !escaped => break,
+                _ => escaped = false,
+            }
+            i += 1;
+        }
+        i += 1;
+    }
+
+    if i >= bytes.len() {
+        ""
+    } else {
+        &text[i..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3164_with_hostname() {
+        let msg = parse(b"<34>Oct 11 22:14:15 mymachine su[230]: 'su root' failed\n");
+        assert_eq!(msg.facility, 4);
+        assert_eq!(msg.severity, 2);
+        assert_eq!(msg.timestamp.as_deref(), Some("Oct 11 22:14:15"));
+        assert_eq!(msg.hostname.as_deref(), Some("mymachine"));
+        assert_eq!(msg.app_name.as_deref(), Some("su"));
+        assert_eq!(msg.proc_id.as_deref(), Some("230"));
+        assert_eq!(msg.message, "'su root' failed");
+    }
+
+    #[test]
+    fn rfc3164_from_glibc() {
+        let msg = parse(b"<30>Feb  5 01:02:03 myapp: started\0");
+        assert_eq!(msg.timestamp.as_deref(), Some("Feb  5 01:02:03"));
+        assert_eq!(msg.hostname, None);
+        assert_eq!(msg.app_name.as_deref(), Some("myapp"));
+        assert_eq!(msg.proc_id, None);
+        assert_eq!(msg.message, "started");
+    }
+
+    #[test]
+    fn rfc3164_without_header() {
+        let msg = parse(b"just some text");
+        assert_eq!((msg.facility, msg.severity), (1, 5));
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.app_name, None);
+        assert_eq!(msg.message, "just some text");
+    }
+
+    #[test]
+    fn rfc3164_malformed_timestamp() {
+        let msg = parse(b"<13>Jan 01 aa:bb:cc app: hi");
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.message, "Jan 01 aa:bb:cc app: hi");
+    }
+
+    #[test]
+    fn rfc3164_non_ascii_within_timestamp() {
+        let msg = parse("Jan 01 00:00:0\u{e9}x: hi".as_bytes());
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.message, "Jan 01 00:00:0\u{e9}x: hi");
+
+        let msg = parse("<13>Jan 01 00:00:00 \u{e4}pp[1]: gr\u{fc}\u{df}e".as_bytes());
+        assert_eq!(msg.timestamp.as_deref(), Some("Jan 01 00:00:00"));
+        assert_eq!(msg.app_name.as_deref(), Some("\u{e4}pp"));
+        assert_eq!(msg.message, "gr\u{fc}\u{df}e");
+    }
+
+    #[test]
+    fn invalid_utf8_and_empty_input() {
+        let msg = parse(b"<13>\xffJan 01 00:00:0\xc3");
+        assert_eq!(msg.timestamp, None);
+        assert!(msg.message.starts_with('\u{fffd}'));
+
+        let msg = parse(b"");
+        assert_eq!(msg.message, "");
+    }
+
+    #[test]
+    fn invalid_pri_is_message_text() {
+        for text in ["<192>hello", "<>hello", "<1234>hello", "<abc>hello", "<13hello"] {
+            let msg = parse(text.as_bytes());
+            assert_eq!(msg.facility, DEFAULT_PRI >> 3, "{}", text);
+            assert_eq!(msg.message, text);
+        }
+    }
+
+    #[test]
+    fn rfc5424_full() {
+        let msg = parse("<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog 10 ID47 \
+            [exampleSDID@32473 iut=\"3\" eventSource=\"Application\"] \u{feff}An application event".as_bytes());
+        assert_eq!(msg.facility, 20);
+        assert_eq!(msg.severity, 5);
+        assert_eq!(msg.timestamp.as_deref(), Some("2003-10-11T22:14:15.003Z"));
+        assert_eq!(msg.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(msg.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(msg.proc_id.as_deref(), Some("10"));
+        assert_eq!(msg.msg_id.as_deref(), Some("ID47"));
+        assert_eq!(msg.message, "An application event");
+    }
+
+    #[test]
+    fn rfc5424_nil_fields() {
+        let msg = parse(b"<14>1 - - app - - - hello");
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.hostname, None);
+        assert_eq!(msg.app_name.as_deref(), Some("app"));
+        assert_eq!(msg.proc_id, None);
+        assert_eq!(msg.msg_id, None);
+        assert_eq!(msg.message, "hello");
+    }
+
+    #[test]
+    fn rfc5424_structured_data_with_escapes() {
+        let msg = parse("<14>1 - h app 1 m [a x=\"\\]\u{e9}\"][b] r\u{e9}sum\u{e9}".as_bytes());
+        assert_eq!(msg.message, "r\u{e9}sum\u{e9}");
+    }
+
+    #[test]
+    fn rfc5424_truncated() {
+        let msg = parse(b"<14>1 2003-10-11T22:14:15Z host");
+        assert_eq!(msg.hostname.as_deref(), Some("host"));
+        assert_eq!(msg.app_name, None);
+        assert_eq!(msg.message, "");
+
+        let msg = parse(b"<14>1 - h app 1 m [unterminated");
+        assert_eq!(msg.message, "");
+    }
+}
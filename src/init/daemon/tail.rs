@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+use nix::sys::inotify::AddWatchFlags;
+use tokio::select;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use log::{debug, info, warn};
+use crate::init::config;
+use crate::init::daemon::watch::Watcher;
+use crate::init::error::WingmateInitError;
+use crate::init::logger;
+
+const DIRECTORY_RETRY_SECS: u64 = 5;
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+/// A longer line is emitted in pieces rather than buffered whole.
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+struct Followed {
+    file: fs::File,
+    pos: u64,
+    partial: Vec<u8>,
+}
+
+/// Follows the files matching a tail entry and copies every new line to
+/// stdout, prefixed with the entry name and the file name.
+pub async fn follow(tail: config::Tail, cancel: CancellationToken) -> Result<(), WingmateInitError> {
+    let watcher = Watcher::new().map_err(|e| { WingmateInitError::Tail { source: e } })?;
+    let mask = AddWatchFlags::IN_MODIFY | AddWatchFlags::IN_CREATE | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_MOVED_FROM | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_DELETE_SELF
+        | AddWatchFlags::IN_MOVE_SELF;
+
+    'watch: loop {
+        // the directory may not exist yet when the application creates it lazily
+        if let Err(e) = watcher.add(&tail.directory, mask) {
+            debug!(tail = tail.name.as_str(); "cannot watch {}: {}", tail.directory.to_string_lossy(), e);
+            select! {
+                _ = sleep(Duration::from_secs(DIRECTORY_RETRY_SECS)) => continue 'watch,
+                _ = cancel.cancelled() => break 'watch,
+            }
+        }
+
+        let mut files = open_existing(&tail);
+
+        'events: loop {
+            let events = select! {
+                ev = watcher.next() => ev,
+                _ = cancel.cancelled() => break 'watch,
+            };
+
+            let events = match events {
+                Ok(ev) => ev,
+                Err(e) => {
+                    warn!(tail = tail.name.as_str(); "reading inotify events: {}", e);
+                    break 'watch;
+                }
+            };
+
+            for ev in events {
+                if ev.mask.intersects(AddWatchFlags::IN_DELETE_SELF | AddWatchFlags::IN_MOVE_SELF | AddWatchFlags::IN_IGNORED) {
+                    for (name, f) in files.iter_mut() {
+                        drain(&tail, name, f);
+                    }
+                    break 'events;
+                }
+
+                if ev.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                    for (name, f) in files.iter_mut() {
+                        drain(&tail, name, f);
+                    }
+                    continue;
+                }
+
+                let name = match ev.name {
+                    Some(n) if config::glob_match(tail.pattern.as_bytes(), n.as_encoded_bytes()) => n,
+                    _ => continue,
+                };
+
+                if ev.mask.intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO) {
+                    if let Some(mut old) = files.remove(&name) {
+                        drain(&tail, &name, &mut old);
+                    }
+                    if let Some(f) = open(&tail, &name, false) {
+                        info!(tail = tail.name.as_str(); "following {}", name.to_string_lossy());
+                        files.insert(name, f);
+                    }
+                } else if ev.mask.contains(AddWatchFlags::IN_MODIFY) {
+                    if !files.contains_key(&name) {
+                        if let Some(f) = open(&tail, &name, false) {
+                            files.insert(name.clone(), f);
+                        }
+                    }
+                    if let Some(f) = files.get_mut(&name) {
+                        drain(&tail, &name, f);
+                    }
+                } else if ev.mask.intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM) {
+                    // the handle still points at the rotated file; read what is left
+                    if let Some(mut old) = files.remove(&name) {
+                        drain(&tail, &name, &mut old);
+                        flush_partial(&tail, &name, &mut old);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn open_existing(tail: &config::Tail) -> HashMap<OsString, Followed> {
+    let mut files: HashMap<OsString, Followed> = HashMap::new();
+
+    if let Ok(entries) = fs::read_dir(&tail.directory) {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if config::glob_match(tail.pattern.as_bytes(), name.as_encoded_bytes()) {
+                if let Some(f) = open(tail, &name, !tail.from_start) {
+                    info!(tail = tail.name.as_str(); "following {}", name.to_string_lossy());
+                    files.insert(name, f);
+                }
+            }
+        }
+    }
+
+    files
+}
+
+fn open(tail: &config::Tail, name: &OsString, at_end: bool) -> Option<Followed> {
+    let path = tail.directory.join(name);
+    match fs::File::open(&path) {
+        Ok(file) => {
+            if !file.metadata().map(|m| { m.is_file() }).unwrap_or(false) {
+                return None;
+            }
+            let pos = if at_end { file.metadata().map(|m| { m.len() }).unwrap_or(0) } else { 0 };
+            Some(Followed { file, pos, partial: Vec::new() })
+        },
+        Err(e) => {
+            warn!(tail = tail.name.as_str(); "opening {}: {}", path.to_string_lossy(), e);
+            None
+        }
+    }
+}
+
+fn drain(tail: &config::Tail, name: &OsString, f: &mut Followed) {
+    read_lines(tail, name, f, |line| { emit(tail, name, line) });
+}
+
+/// Hands every complete line read since the last call to `line`, and a
+/// partial one once it reaches `MAX_LINE_LEN`.
+fn read_lines(tail: &config::Tail, name: &OsString, f: &mut Followed, mut line: impl FnMut(&[u8])) {
+    if let Ok(m) = f.file.metadata() {
+        if m.len() < f.pos {
+            info!(tail = tail.name.as_str(); "{} truncated", name.to_string_lossy());
+            f.pos = 0;
+            f.partial.clear();
+        }
+    }
+
+    if let Err(e) = f.file.seek(SeekFrom::Start(f.pos)) {
+        warn!(tail = tail.name.as_str(); "reading {}: {}", name.to_string_lossy(), e);
+        return;
+    }
+
+    let mut buf = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        let n = match f.file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!(tail = tail.name.as_str(); "reading {}: {}", name.to_string_lossy(), e);
+                return;
+            }
+        };
+        f.pos += n as u64;
+
+        f.partial.extend_from_slice(&buf[..n]);
+        let mut start = 0;
+        while let Some(i) = f.partial[start..].iter().position(|b| { *b == b'\n' }) {
+            line(&f.partial[start..start + i]);
+            start += i + 1;
+        }
+        f.partial.drain(..start);
+        if f.partial.len() >= MAX_LINE_LEN {
+            line(&std::mem::take(&mut f.partial));
+        }
+    }
+}
+
+fn flush_partial(tail: &config::Tail, name: &OsString, f: &mut Followed) {
+    if !f.partial.is_empty() {
+        let line = std::mem::take(&mut f.partial);
+        emit(tail, name, &line);
+    }
+}
+
+fn emit(tail: &config::Tail, name: &OsString, line: &[u8]) {
+    let mut out = io::stdout().lock();
+    let result = out.write_all(format!("{}/{}: ", tail.prefix, name.to_string_lossy()).as_bytes())
+        .and_then(|_| { out.write_all(line) })
+        .and_then(|_| { out.write_all(b"\n") });
+    if let Err(e) = result {
+        warn!(tail = tail.name.as_str(); "writing to stdout: {}", e);
+    }
+
+    logger::forward_output(&tail.prefix, None, false, line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn tail(name: &str) -> config::Tail {
+        let directory = std::env::temp_dir().join(format!("wingmate-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        config::Tail { name: String::from(name), directory, pattern: String::from("*.log"), prefix: String::from(name), from_start: false }
+    }
+
+    fn append(path: &PathBuf, content: &[u8]) {
+        fs::OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(content).unwrap();
+    }
+
+    fn lines(t: &config::Tail, name: &OsString, f: &mut Followed) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        read_lines(t, name, f, |l| { lines.push(String::from_utf8_lossy(l).into_owned()) });
+        lines
+    }
+
+    #[test]
+    fn reads_new_lines() {
+        let t = tail("lines");
+        let name = OsString::from("app.log");
+        let path = t.directory.join(&name);
+        append(&path, b"old\n");
+
+        let mut from_start = open(&t, &name, false).unwrap();
+        let mut f = open(&t, &name, true).unwrap();
+        assert_eq!(lines(&t, &name, &mut from_start), ["old"]);
+        assert!(lines(&t, &name, &mut f).is_empty());
+
+        append(&path, b"a\n\nb\npart");
+        assert_eq!(lines(&t, &name, &mut f), ["a", "", "b"]);
+        append(&path, b"ial\n");
+        assert_eq!(lines(&t, &name, &mut f), ["partial"]);
+
+        // a truncated file is read again from the start
+        fs::write(&path, b"new\n").unwrap();
+        assert_eq!(lines(&t, &name, &mut f), ["new"]);
+        fs::remove_dir_all(&t.directory).unwrap();
+    }
+
+    #[test]
+    fn splits_long_lines() {
+        let t = tail("long");
+        let name = OsString::from("app.log");
+        let path = t.directory.join(&name);
+        fs::write(&path, b"").unwrap();
+        let mut f = open(&t, &name, false).unwrap();
+
+        // longer than a read, so the line arrives over several chunks
+        let long = vec![b'x'; MAX_LINE_LEN + READ_CHUNK_SIZE + 10];
+        append(&path, &long);
+        append(&path, b"\nshort\n");
+        let mut read = lines(&t, &name, &mut f);
+        assert_eq!(read.pop().unwrap(), "short");
+        assert!(read.len() > 1);
+        assert!(read[..read.len() - 1].iter().all(|l| { l.len() >= MAX_LINE_LEN }));
+        assert_eq!(read.concat().into_bytes(), long);
+        fs::remove_dir_all(&t.directory).unwrap();
+    }
+}
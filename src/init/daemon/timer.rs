@@ -0,0 +1,153 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rand::Rng;
+use time::OffsetDateTime;
+use tokio::select;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
+use log::{debug, info, warn};
+use crate::init::config;
+use crate::init::daemon::starter;
+use crate::init::daemon::status::TimerRuntime;
+use crate::init::error::WingmateInitError;
+
+const STATE_DIR_ENV: &str = "WINGMATE_STATE_DIR";
+const DEFAULT_STATE_DIR: &str = "/var/lib/wingmate";
+const STAMP_DIRECTORY: &str = "timers";
+const STAMP_PREFIX: &str = "stamp-";
+
+pub struct TimerHandle {
+    pub name: String,
+    pub timer: config::Timer,
+    pub runtime: Arc<Mutex<TimerRuntime>>,
+    pub cancel: CancellationToken,
+}
+
+/// Sends the name of the service to start on `trigger` every time the timer
+/// elapses. `boot` is when wingmate started.
+pub fn start_timer(ts: &mut JoinSet<Result<(), WingmateInitError>>, t_: &config::Timer, boot: Instant,
+    trigger: mpsc::UnboundedSender<String>, cancel: CancellationToken) -> TimerHandle {
+
+    let timer = t_.clone();
+    let runtime = Arc::new(Mutex::new(TimerRuntime::default()));
+    let task_runtime = runtime.clone();
+    let task_cancel = cancel.clone();
+    debug!(timer = t_.name.as_str(); "timer: {:?}", t_);
+    ts.spawn(async move {
+        run(timer, boot, trigger, task_runtime, task_cancel).await
+    });
+
+    TimerHandle { name: t_.name.clone(), timer: t_.clone(), runtime, cancel }
+}
+
+async fn run(timer: config::Timer, boot: Instant, trigger: mpsc::UnboundedSender<String>, runtime: Arc<Mutex<TimerRuntime>>,
+    cancel: CancellationToken) -> Result<(), WingmateInitError> {
+
+    let name = timer.name.as_str();
+    // a timer created by a reload long after the start does not count it
+    let mut boot_elapsed = timer.on_boot.is_none_or(|d| { boot + d <= Instant::now() });
+    let mut last_trigger: Option<Instant> = None;
+    // the calendar elapse triggered last, so waking up early never runs it twice
+    let mut last_due: Option<OffsetDateTime> = None;
+
+    if timer.persistent && missed(&timer) {
+        info!(event = "timer_missed", timer = name; "catching up on a run missed while wingmate was not running");
+        fire(&timer, &trigger, &runtime);
+        last_trigger = Some(Instant::now());
+    }
+
+    'timer: loop {
+        let now = Instant::now();
+        let wall = match starter::cron_time() {
+            Ok(t) => Some(t),
+            Err(e) => {
+                warn!(timer = name; "cannot determine local time: {}", e);
+                None
+            }
+        };
+
+        let calendar_due = wall.and_then(|w| {
+            let from = last_due.filter(|d| { *d > w }).unwrap_or(w);
+            timer.calendar.iter().filter_map(|c| { c.next_elapse(from) }).min()
+        });
+        let calendar_at = calendar_due.zip(wall).map(|(due, w)| { now + Duration::try_from(due - w).unwrap_or_default() });
+        let boot_at = timer.on_boot.filter(|_| { !boot_elapsed }).map(|d| { boot + d });
+        let active_at = timer.on_unit_active.zip(last_trigger).map(|(d, last)| { last + d });
+
+        let at = match [calendar_at, boot_at, active_at].into_iter().flatten().min() {
+            Some(at) => at + random_delay(timer.randomized_delay),
+            None => {
+                debug!(timer = name; "timer does not elapse anymore");
+                runtime.lock().unwrap().next_elapse = None;
+                cancel.cancelled().await;
+                break 'timer;
+            }
+        };
+        runtime.lock().unwrap().next_elapse = wall.map(|w| { w + (at - now) });
+
+        select! {
+            _ = sleep_until(at) => {},
+            _ = cancel.cancelled() => break 'timer,
+        }
+
+        if calendar_at.is_some_and(|c| { c <= at }) {
+            last_due = calendar_due;
+        }
+        if boot_at.is_some_and(|b| { b <= at }) {
+            boot_elapsed = true;
+        }
+        fire(&timer, &trigger, &runtime);
+        last_trigger = Some(Instant::now());
+    }
+
+    Ok(())
+}
+
+fn fire(timer: &config::Timer, trigger: &mpsc::UnboundedSender<String>, runtime: &Arc<Mutex<TimerRuntime>>) {
+    info!(event = "timer_elapsed", timer = timer.name.as_str(), service = timer.unit.as_str(); "starting {}", timer.unit);
+    let _ = trigger.send(timer.unit.clone());
+
+    let now = OffsetDateTime::now_utc();
+    runtime.lock().unwrap().last_trigger = Some(now);
+    if timer.persistent {
+        let stamp = stamp_path(&timer.name);
+        let written = stamp.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| { fs::write(&stamp, now.unix_timestamp().to_string()) });
+        if let Err(e) = written {
+            warn!(timer = timer.name.as_str(); "cannot write {}: {}", stamp.to_string_lossy(), e);
+        }
+    }
+}
+
+/// Whether a calendar elapse fell between the last trigger recorded in the
+/// stamp file and now. Without a stamp the timer never ran, so nothing was
+/// missed.
+fn missed(timer: &config::Timer) -> bool {
+    let last = fs::read_to_string(stamp_path(&timer.name)).ok()
+        .and_then(|s| { s.trim().parse::<i64>().ok() })
+        .and_then(|ts| { OffsetDateTime::from_unix_timestamp(ts).ok() });
+    let (last, now) = match (last, starter::cron_time()) {
+        (Some(l), Ok(n)) => (l.to_offset(n.offset()), n),
+        _ => return false,
+    };
+
+    timer.calendar.iter().any(|c| { c.next_elapse(last).is_some_and(|t| { t <= now }) })
+}
+
+/// The stamp files of persistent timers live in `WINGMATE_STATE_DIR`.
+fn stamp_path(name: &str) -> PathBuf {
+    let dir = env::var_os(STATE_DIR_ENV).map(PathBuf::from).unwrap_or_else(|| { PathBuf::from(DEFAULT_STATE_DIR) });
+    dir.join(STAMP_DIRECTORY).join(format!("{}{}", STAMP_PREFIX, name))
+}
+
+fn random_delay(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max.as_millis() as u64))
+}
@@ -0,0 +1,133 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use tokio::process::Command;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use nix::sys::signal::{kill, pthread_sigmask, signal as nix_signal, SigHandler, SigmaskHow, SigSet, Signal};
+use nix::unistd::{getpgrp, getpid, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid};
+use log::{debug, warn};
+use crate::init::error::WingmateInitError;
+
+const STDIN: i32 = 0;
+
+/// Passed on to the process group holding the terminal. The terminal sends
+/// window size changes and ^Z to that group itself, so wingmate only gets
+/// these when they are sent to it directly.
+const TTY_SIGNALS: [Signal; 3] = [Signal::SIGWINCH, Signal::SIGTSTP, Signal::SIGCONT];
+/// What wingmate gets for touching the terminal from the background, such as
+/// writing its own logs with TOSTOP set. Wingmate ignores them; the process
+/// handed the terminal gets the default action back before it runs.
+const BACKGROUND_SIGNALS: [Signal; 2] = [Signal::SIGTTIN, Signal::SIGTTOU];
+
+/// Whether one process holds the terminal while everything else stays off it.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// The process group the terminal was handed to, 0 while wingmate holds it.
+static FOREGROUND: AtomicI32 = AtomicI32::new(0);
+
+/// Whether stdin is a terminal wingmate is in the foreground of, so it has
+/// something to hand over.
+pub fn available() -> bool {
+    isatty(STDIN).unwrap_or(false) && tcgetpgrp(STDIN).is_ok_and(|pg| { pg == getpgrp() })
+}
+
+/// From now on the foreground process gets the terminal, and the output of
+/// services and cron entries no longer goes to it.
+pub fn enable() {
+    ACTIVE.store(true, Ordering::Relaxed);
+    for sig in BACKGROUND_SIGNALS {
+        // no handler of wingmate's is replaced
+        if let Err(e) = unsafe { nix_signal(sig, SigHandler::SigIgn) } {
+            warn!("ignoring {}: {}", sig.as_str(), e);
+        }
+    }
+}
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Puts the child in a process group of its own, so it only gets the
+/// signals wingmate passes on. With `terminal` set, its group also becomes
+/// the foreground process group of the terminal before it runs.
+pub fn isolate(command: &mut Command, terminal: bool) {
+    let hand_over = move || -> io::Result<()> {
+        setpgid(Pid::from_raw(0), Pid::from_raw(0))?;
+        if terminal {
+            // a background group asking for the terminal gets SIGTTOU otherwise
+            without_ttou(|| { tcsetpgrp(STDIN, getpid()) })?;
+        }
+        // an ignored signal stays ignored across exec
+        for sig in BACKGROUND_SIGNALS {
+            unsafe { nix_signal(sig, SigHandler::SigDfl) }?;
+        }
+        Ok(())
+    };
+    // only async-signal-safe calls happen between fork and exec
+    unsafe {
+        command.pre_exec(hand_over);
+    }
+}
+
+/// Records that the group of `pid` holds the terminal.
+pub fn acquired(pid: u32) {
+    FOREGROUND.store(pid as i32, Ordering::Relaxed);
+}
+
+/// Takes the terminal back once the foreground process exited.
+pub fn release() {
+    FOREGROUND.store(0, Ordering::Relaxed);
+    if let Err(e) = without_ttou(|| { tcsetpgrp(STDIN, getpgrp()) }) {
+        warn!("taking back the terminal: {}", e);
+    }
+}
+
+/// Passes window size changes and job control signals sent to wingmate on to
+/// the process group holding the terminal. Handling them also keeps wingmate
+/// itself from being stopped.
+pub async fn forward(cancel: CancellationToken) -> Result<(), WingmateInitError> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Signal>();
+    let mut listeners: JoinSet<()> = JoinSet::new();
+    for sig in TTY_SIGNALS {
+        let mut stream = signal(SignalKind::from_raw(sig as i32)).map_err(|e| { WingmateInitError::Signal { source: e } })?;
+        let tx = tx.clone();
+        listeners.spawn(async move {
+            while stream.recv().await.is_some() {
+                if tx.send(sig).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    'forward: loop {
+        select! {
+            Some(sig) = rx.recv() => {
+                let pgid = FOREGROUND.load(Ordering::Relaxed);
+                if pgid == 0 {
+                    continue 'forward;
+                }
+                debug!(event = "signal", signal = sig.as_str(), pgid = pgid; "forwarding to the foreground process group");
+                if let Err(e) = kill(Pid::from_raw(-pgid), sig) {
+                    warn!("forwarding {} got {}", sig.as_str(), e);
+                }
+            },
+            _ = cancel.cancelled() => break 'forward,
+        }
+    }
+
+    listeners.shutdown().await;
+    Ok(())
+}
+
+fn without_ttou<T, F: FnOnce() -> nix::Result<T>>(f: F) -> nix::Result<T> {
+    let mut ttou = SigSet::empty();
+    ttou.add(Signal::SIGTTOU);
+    let mut previous = SigSet::empty();
+    pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&ttou), Some(&mut previous))?;
+    let result = f();
+    pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&previous), None)?;
+    result
+}
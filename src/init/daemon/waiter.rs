@@ -4,36 +4,37 @@ use nix::unistd::Pid;
 use std::sync::{Mutex, Arc};
 use std::{thread, time};
 use tokio_util::sync::CancellationToken;
+use log::{info, warn};
+use crate::init::daemon::status::{Exit, Reaped};
 
-pub fn wait_all(flag: Arc<Mutex<bool>>, stop_sighandler: CancellationToken) {
+pub fn wait_all(flag: Arc<Mutex<bool>>, stop_sighandler: CancellationToken, reaped: Reaped) {
     'wait: loop {
         match wait::waitpid(Pid::from_raw(-1), None) {
             Ok(x) => {
-                // dbg!(x);
                 match x {
                     WaitStatus::Exited(pid, v) => {
-                        println!("wait_all: pid {}: exited with status {}", pid, v);
+                        info!(event = "reaped", pid = pid.as_raw(), exit_code = v; "process exited");
+                        reaped.record(pid.as_raw() as u32, Exit::Code(v));
                     },
                     WaitStatus::Signaled(pid, sig, _dumped) => {
-                        println!("wait_all: pid {} killed with signal {}", pid, sig);
+                        info!(event = "reaped", pid = pid.as_raw(), signal = sig.as_str(); "process killed by signal");
+                        reaped.record(pid.as_raw() as u32, Exit::Signal(sig as i32));
                     },
                     _ => {}
                 }
             },
             Err(err) => {
-                dbg!(err);
-                match err {
-                    Errno::ECHILD => {
-                        let fl = flag.lock().unwrap();
-                        if *fl {
-                            stop_sighandler.cancel();
-                            break 'wait;
-                        } else {
-                            drop(fl);
-                            thread::sleep(time::Duration::from_millis(100));
-                        }
-                    },
-                    _ => {}
+                if err == Errno::ECHILD {
+                    let fl = flag.lock().unwrap();
+                    if *fl {
+                        stop_sighandler.cancel();
+                        break 'wait;
+                    } else {
+                        drop(fl);
+                        thread::sleep(time::Duration::from_millis(100));
+                    }
+                } else if err != Errno::EINTR {
+                    warn!("waitpid: {}", err);
                 }
             },
         }
@@ -0,0 +1,44 @@
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::path::Path;
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+use tokio::io::unix::AsyncFd;
+
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// Non-blocking inotify instance driven by the tokio reactor.
+pub struct Watcher {
+    fd: AsyncFd<InotifyFd>,
+}
+
+impl Watcher {
+    pub fn new() -> io::Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        Ok(Self {
+            fd: AsyncFd::new(InotifyFd(inotify))?,
+        })
+    }
+
+    pub fn add(&self, path: &Path, mask: AddWatchFlags) -> io::Result<WatchDescriptor> {
+        Ok(self.fd.get_ref().0.add_watch(path, mask)?)
+    }
+
+    /// Waits until at least one event is available and returns all of them.
+    pub async fn next(&self) -> io::Result<Vec<InotifyEvent>> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            match guard.get_inner().0.read_events() {
+                Ok(events) => return Ok(events),
+                Err(Errno::EAGAIN) => guard.clear_ready(),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
@@ -42,9 +42,30 @@ pub enum WingmateInitError {
         source: std::io::Error,
     },
 
+    #[error("setting up syslog socket")]
+    Syslog {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("following log files")]
+    Tail {
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("no shell available")]
     NoShellAvailable,
 
+    #[error("dependency cycle between services: {}", .0)]
+    DependencyCycle(String),
+
+    #[error("timer {} starts unknown service {}", timer, unit)]
+    TimerUnit {
+        timer: String,
+        unit: String,
+    },
+
     #[error("problem when join task")]
     Join {
         #[source]
@@ -57,6 +78,23 @@ pub enum WingmateInitError {
         source: CronConfigError,
     },
 
+    #[error("config file {}", path)]
+    ConfigFile {
+        path: String,
+        #[source]
+        source: ConfigFileError,
+    },
+
+    #[error("only one process can run in the foreground: {}", .0)]
+    Foreground(String),
+    #[error("invalid service definition \"{}\"", .0)]
+    InlineDefinition(String),
+    #[error("environment variable {}", name)]
+    Environment {
+        name: String,
+        #[source]
+        source: ConfigFileError,
+    },
     #[error("from nix")]
     FromNix {
         #[source]
@@ -114,3 +152,49 @@ pub enum FindShellError {
         source: anyhow::Error
     }
 }
+
+
+#[derive(Error,Debug)]
+pub enum ConfigFileError {
+    #[error("reading file")]
+    Read {
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("parsing toml")]
+    Parse {
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("service \"{}\" has no command and no matching file in services", .0)]
+    UnknownService(String),
+
+    #[error("cron \"{}\" has no schedule and no matching crontab entry", .0)]
+    UnknownCron(String),
+
+    #[error("cron \"{}\" needs both schedule and command", .0)]
+    IncompleteCron(String),
+
+    #[error("line {}: {}", line, message)]
+    Syntax {
+        line: usize,
+        message: String,
+    },
+
+    #[error("invalid size \"{}\"", .0)]
+    InvalidSize(String),
+
+    #[error("invalid value \"{}\" for {}", value, key)]
+    InvalidValue {
+        key: String,
+        value: String,
+    },
+
+    #[error("unknown option \"{}\"", .0)]
+    UnknownOption(String),
+
+    #[error("not valid UTF-8")]
+    NotUtf8,
+}
\ No newline at end of file
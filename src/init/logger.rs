@@ -0,0 +1,302 @@
+pub mod forward;
+
+use std::env;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use log::{Level, LevelFilter, Log, Metadata, Record, warn};
+use log::kv::{Key, Value, VisitSource};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use crate::init::config::{self, LogFormat};
+
+const WINGMATE_LOG_LEVEL: &str = "WINGMATE_LOG_LEVEL";
+const WINGMATE_LOG_FORMAT: &str = "WINGMATE_LOG_FORMAT";
+const TARGET_PREFIX: &str = "wingmate_rs::";
+const APP_NAME: &str = "wingmate";
+
+/// Target of records that relay messages received on the local syslog socket.
+pub const SYSLOG_TARGET: &str = "syslog";
+
+const FORMAT_TEXT: u8 = 0;
+const FORMAT_JSON: u8 = 1;
+
+struct Logger {
+    format: AtomicU8,
+    level_pinned: AtomicBool,
+    format_pinned: AtomicBool,
+}
+
+static LOGGER: Logger = Logger {
+    format: AtomicU8::new(FORMAT_TEXT),
+    level_pinned: AtomicBool::new(false),
+    format_pinned: AtomicBool::new(false),
+};
+
+struct Forwarding {
+    forwarder: forward::Forwarder,
+    events: bool,
+    services: bool,
+}
+
+static FORWARDING: OnceLock<Forwarding> = OnceLock::new();
+
+/// Installs wingmate's logger. Level and format come from the command line
+/// or else the environment at this point; `configure` applies the config file
+/// once it is loaded.
+pub fn init(level: Option<LevelFilter>, format: Option<LogFormat>) {
+    if log::set_logger(&LOGGER).is_err() {
+        return;
+    }
+    log::set_max_level(LevelFilter::Info);
+
+    if let Some(level) = level {
+        log::set_max_level(level);
+        LOGGER.level_pinned.store(true, Ordering::Relaxed);
+    } else if let Ok(l) = env::var(WINGMATE_LOG_LEVEL) {
+        match LevelFilter::from_str(&l) {
+            Ok(parsed) => {
+                log::set_max_level(parsed);
+                LOGGER.level_pinned.store(true, Ordering::Relaxed);
+            },
+            Err(_) => {
+                warn!("ignoring invalid {} \"{}\"", WINGMATE_LOG_LEVEL, l);
+            }
+        }
+    }
+
+    if let Some(format) = format {
+        set_format(format);
+        LOGGER.format_pinned.store(true, Ordering::Relaxed);
+    } else if let Ok(f) = env::var(WINGMATE_LOG_FORMAT) {
+        match LogFormat::from_str(&f) {
+            Ok(parsed) => {
+                set_format(parsed);
+                LOGGER.format_pinned.store(true, Ordering::Relaxed);
+            },
+            Err(_) => {
+                warn!("ignoring invalid {} \"{}\"", WINGMATE_LOG_FORMAT, f);
+            }
+        }
+    }
+}
+
+/// Applies the `[log]` section of the config. Settings coming from the
+/// command line or the environment take precedence over the config file.
+pub fn configure(cfg: &config::LogConfig) {
+    if let Some(level) = cfg.level {
+        if !LOGGER.level_pinned.load(Ordering::Relaxed) {
+            log::set_max_level(level);
+        }
+    }
+
+    if let Some(format) = cfg.format {
+        if !LOGGER.format_pinned.load(Ordering::Relaxed) {
+            set_format(format);
+        }
+    }
+}
+
+/// Starts sending wingmate's own records and/or service output to a syslog
+/// collector in addition to the local output.
+pub fn set_forwarder(forwarder: forward::Forwarder, events: bool, services: bool) {
+    let _ = FORWARDING.set(Forwarding { forwarder, events, services });
+}
+
+/// What happened to the messages queued for the collector so far, when
+/// forwarding is on.
+pub fn forwarded() -> Option<forward::Counts> {
+    FORWARDING.get().map(|f| { f.forwarder.counts() })
+}
+
+pub fn forwards_output() -> bool {
+    FORWARDING.get().is_some_and(|f| { f.services })
+}
+
+/// Queues one line of service or cron output for the syslog collector.
+pub fn forward_output(name: &str, pid: Option<u32>, stderr: bool, line: &[u8]) {
+    if let Some(f) = FORWARDING.get() {
+        if f.services {
+            let text = String::from_utf8_lossy(line);
+            let pid = pid.map(|p| { p.to_string() });
+            f.forwarder.send(&forward::Entry {
+                severity: if stderr { 3 } else { 6 },
+                facility: None,
+                app_name: name,
+                proc_id: pid.as_deref(),
+                msg_id: None,
+                message: &text,
+            });
+        }
+    }
+}
+
+fn forward_record(record: &Record) {
+    let f = match FORWARDING.get() {
+        Some(f) if f.events => f,
+        _ => return,
+    };
+
+    let kv = record.key_values();
+    let field = |k: &str| { kv.get(Key::from_str(k)).map(|v| { v.to_string() }) };
+
+    if record.target() == SYSLOG_TARGET {
+        // relay with the identity of the original sender
+        let message = record.args().to_string();
+        let ident = field("ident");
+        let pid = field("pid");
+        f.forwarder.send(&forward::Entry {
+            severity: field("severity").and_then(|s| { s.parse::<u8>().ok() }).unwrap_or(6),
+            facility: field("facility").and_then(|s| { s.parse::<u8>().ok() }),
+            app_name: ident.as_deref().unwrap_or_default(),
+            proc_id: pid.as_deref(),
+            msg_id: None,
+            message: &message,
+        });
+        return;
+    }
+
+    let mut message = format!("{}: {}", short_target(record), record.args());
+    let mut fields = TextFields(&mut message);
+    let _ = kv.visit(&mut fields);
+
+    let pid = std::process::id().to_string();
+    let event = field("event");
+    f.forwarder.send(&forward::Entry {
+        severity: match record.level() {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        },
+        facility: None,
+        app_name: APP_NAME,
+        proc_id: Some(&pid),
+        msg_id: event.as_deref(),
+        message: &message,
+    });
+}
+
+fn set_format(format: LogFormat) {
+    let f = match format {
+        LogFormat::Text => FORMAT_TEXT,
+        LogFormat::Json => FORMAT_JSON,
+    };
+    LOGGER.format.store(f, Ordering::Relaxed);
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = if self.format.load(Ordering::Relaxed) == FORMAT_JSON {
+            json_line(record)
+        } else {
+            text_line(record)
+        };
+
+        let _ = io::stderr().lock().write_all(line.as_bytes());
+        forward_record(record);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+fn timestamp() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+fn short_target<'a>(record: &'a Record) -> &'a str {
+    let target = record.target();
+    let target = target.strip_prefix(TARGET_PREFIX).unwrap_or(target);
+    target.rsplit("::").next().unwrap_or(target)
+}
+
+fn text_line(record: &Record) -> String {
+    let mut line = format!("{} {:<5} {}: {}", timestamp(), record.level(), short_target(record), record.args());
+
+    let mut fields = TextFields(&mut line);
+    let _ = record.key_values().visit(&mut fields);
+
+    line.push('\n');
+    line
+}
+
+fn json_line(record: &Record) -> String {
+    let mut line = String::from("{");
+    let _ = write!(line, "\"time\":\"{}\"", timestamp());
+    let _ = write!(line, ",\"level\":\"{}\"", level_name(record.level()));
+    let _ = write!(line, ",\"target\":\"{}\"", escape(short_target(record)));
+    let _ = write!(line, ",\"message\":\"{}\"", escape(&record.args().to_string()));
+
+    let mut fields = JsonFields(&mut line);
+    let _ = record.key_values().visit(&mut fields);
+
+    line.push_str("}\n");
+    line
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+struct TextFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for TextFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let _ = write!(self.0, " {}={}", key, value);
+        Ok(())
+    }
+}
+
+struct JsonFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let _ = write!(self.0, ",\"{}\":", escape(key.as_str()));
+        if let Some(n) = value.to_i64() {
+            let _ = write!(self.0, "{}", n);
+        } else if let Some(n) = value.to_u64() {
+            let _ = write!(self.0, "{}", n);
+        } else if let Some(b) = value.to_bool() {
+            let _ = write!(self.0, "{}", b);
+        } else {
+            let _ = write!(self.0, "\"{}\"", escape(&value.to_string()));
+        }
+        Ok(())
+    }
+}
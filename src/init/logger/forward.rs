@@ -0,0 +1,353 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket, UnixDatagram};
+use tokio::select;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use log::debug;
+use crate::init::config;
+
+const NIL: &str = "-";
+const MAX_APP_NAME_LEN: usize = 48;
+const MAX_PROC_ID_LEN: usize = 128;
+const MAX_MSG_ID_LEN: usize = 32;
+/// Bounds connecting and writing, so a collector that does not answer holds
+/// up neither the queue nor the shutdown for long.
+const IO_TIMEOUT_SECS: u64 = 5;
+const RECONNECT_INITIAL_SECS: u64 = 1;
+const RECONNECT_MAX_SECS: u64 = 30;
+
+/// Counters of the forwarding queue. Messages are dropped rather than
+/// blocking the caller when the queue is full or the collector is down.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub sent: AtomicU64,
+    pub dropped: AtomicU64,
+    pub failed: AtomicU64,
+}
+
+/// The counters at one point in time.
+#[derive(Debug, Clone, Copy)]
+pub struct Counts {
+    pub sent: u64,
+    pub dropped: u64,
+    pub failed: u64,
+}
+
+/// Handle used to queue messages for the collector. Cloning is cheap.
+#[derive(Clone)]
+pub struct Forwarder {
+    tx: mpsc::Sender<Vec<u8>>,
+    hostname: Arc<String>,
+    facility: u8,
+    stats: Arc<Stats>,
+}
+
+pub struct Entry<'a> {
+    pub severity: u8,
+    pub facility: Option<u8>,
+    pub app_name: &'a str,
+    pub proc_id: Option<&'a str>,
+    pub msg_id: Option<&'a str>,
+    pub message: &'a str,
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Unix(UnixDatagram),
+}
+
+impl Forwarder {
+    pub fn counts(&self) -> Counts {
+        Counts {
+            sent: self.stats.sent.load(Ordering::Relaxed),
+            dropped: self.stats.dropped.load(Ordering::Relaxed),
+            failed: self.stats.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn send(&self, entry: &Entry) {
+        let frame = self.format(entry);
+        if self.tx.try_send(frame).is_err() {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn format(&self, entry: &Entry) -> Vec<u8> {
+        let pri = (entry.facility.unwrap_or(self.facility) as u16) * 8 + (entry.severity & 0x07) as u16;
+        let timestamp = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_else(|_| { String::from(NIL) });
+
+        format!("<{}>1 {} {} {} {} {} {} {}",
+            pri,
+            timestamp,
+            self.hostname,
+            header_field(Some(entry.app_name), MAX_APP_NAME_LEN),
+            header_field(entry.proc_id, MAX_PROC_ID_LEN),
+            header_field(entry.msg_id, MAX_MSG_ID_LEN),
+            NIL,
+            entry.message.trim_end_matches(['\n', '\r'])
+        ).into_bytes()
+    }
+}
+
+/// Creates the forwarder handle together with the task that drains the queue
+/// towards the collector. The task returns once `cancel` fires and the queue
+/// has been flushed.
+pub fn start(cfg: &config::SyslogForward, cancel: CancellationToken) -> (Forwarder, impl std::future::Future<Output = ()>) {
+    let (tx, rx) = mpsc::channel(cfg.queue_size);
+    let hostname = match &cfg.hostname {
+        Some(h) => h.clone(),
+        None => nix::unistd::gethostname().ok()
+            .and_then(|h| { h.into_string().ok() })
+            .unwrap_or_else(|| { String::from(NIL) }),
+    };
+
+    let forwarder = Forwarder {
+        tx,
+        hostname: Arc::new(header_field(Some(&hostname), 255)),
+        facility: cfg.facility,
+        stats: Arc::new(Stats::default()),
+    };
+
+    let task = drain(cfg.address.clone(), rx, forwarder.stats.clone(), cancel);
+    (forwarder, task)
+}
+
+/// While the collector cannot be reached, frames fail right away until the
+/// next attempt to connect is due, each failed attempt doubling the wait.
+async fn drain(address: config::ForwardAddress, mut rx: mpsc::Receiver<Vec<u8>>, stats: Arc<Stats>, cancel: CancellationToken) {
+    let mut conn: Option<Connection> = None;
+    let mut retry: Option<(Instant, Duration)> = None;
+
+    'drain: loop {
+        let frame = select! {
+            f = rx.recv() => f,
+            _ = cancel.cancelled() => {
+                rx.close();
+                rx.recv().await
+            }
+        };
+
+        let frame = match frame {
+            Some(f) => f,
+            None => break 'drain,
+        };
+
+        if conn.is_none() {
+            if retry.is_some_and(|(at, _)| { Instant::now() < at }) {
+                stats.failed.fetch_add(1, Ordering::Relaxed);
+                continue 'drain;
+            }
+            match timeout(Duration::from_secs(IO_TIMEOUT_SECS), connect(&address)).await {
+                Ok(Ok(c)) => {
+                    conn = Some(c);
+                    retry = None;
+                },
+                Ok(Err(e)) => {
+                    debug!("connecting to the syslog collector: {}", e);
+                    retry = Some(backoff(retry));
+                    stats.failed.fetch_add(1, Ordering::Relaxed);
+                    continue 'drain;
+                },
+                Err(_) => {
+                    debug!("connecting to the syslog collector timed out");
+                    retry = Some(backoff(retry));
+                    stats.failed.fetch_add(1, Ordering::Relaxed);
+                    continue 'drain;
+                }
+            }
+        }
+
+        if let Some(c) = &mut conn {
+            match timeout(Duration::from_secs(IO_TIMEOUT_SECS), write(c, &frame)).await {
+                Ok(Ok(_)) => {
+                    stats.sent.fetch_add(1, Ordering::Relaxed);
+                },
+                _ => {
+                    stats.failed.fetch_add(1, Ordering::Relaxed);
+                    conn = None;
+                }
+            }
+        }
+    }
+
+    debug!(sent = stats.sent.load(Ordering::Relaxed),
+        dropped = stats.dropped.load(Ordering::Relaxed),
+        failed = stats.failed.load(Ordering::Relaxed); "syslog forwarding stopped");
+}
+
+/// When to try connecting next and the wait after that.
+fn backoff(previous: Option<(Instant, Duration)>) -> (Instant, Duration) {
+    let wait = match previous {
+        Some((_, w)) => (w * 2).min(Duration::from_secs(RECONNECT_MAX_SECS)),
+        None => Duration::from_secs(RECONNECT_INITIAL_SECS),
+    };
+    (Instant::now() + wait, wait)
+}
+
+async fn connect(address: &config::ForwardAddress) -> std::io::Result<Connection> {
+    match address {
+        config::ForwardAddress::Udp(addr) => {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(addr).await?;
+            Ok(Connection::Udp(socket))
+        },
+        config::ForwardAddress::Tcp(addr) => {
+            Ok(Connection::Tcp(TcpStream::connect(addr).await?))
+        },
+        config::ForwardAddress::Unix(path) => {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(path)?;
+            Ok(Connection::Unix(socket))
+        },
+    }
+}
+
+async fn write(conn: &mut Connection, frame: &[u8]) -> std::io::Result<()> {
+    match conn {
+        Connection::Udp(s) => {
+            s.send(frame).await?;
+        },
+        Connection::Tcp(s) => {
+            // octet counting framing, RFC 6587
+            s.write_all(format!("{} ", frame.len()).as_bytes()).await?;
+            s.write_all(frame).await?;
+        },
+        Connection::Unix(s) => {
+            s.send(frame).await?;
+        },
+    }
+    Ok(())
+}
+
+fn header_field(value: Option<&str>, max_len: usize) -> String {
+    let field: String = value.unwrap_or_default().chars()
+        .filter(|c| { c.is_ascii_graphic() })
+        .take(max_len)
+        .collect();
+
+    if field.is_empty() {
+        String::from(NIL)
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn forward(address: config::ForwardAddress, queue_size: usize) -> config::SyslogForward {
+        config::SyslogForward {
+            address,
+            queue_size,
+            facility: 16,
+            hostname: Some(String::from("box 1")),
+            services: true,
+            events: true,
+        }
+    }
+
+    fn entry(message: &str) -> Entry<'_> {
+        Entry { severity: 3, facility: None, app_name: "web", proc_id: Some("42"), msg_id: None, message }
+    }
+
+    /// The frame without its timestamp.
+    fn fields(frame: &[u8]) -> String {
+        let text = String::from_utf8(frame.to_vec()).unwrap();
+        let (pri, rest) = text.split_once(' ').unwrap();
+        let (_, rest) = rest.split_once(' ').unwrap();
+        format!("{} {}", pri, rest)
+    }
+
+    #[tokio::test]
+    async fn formats_rfc5424() {
+        let (f, _) = start(&forward(config::ForwardAddress::Udp(String::from("127.0.0.1:9")), 4), CancellationToken::new());
+        assert_eq!(fields(&f.format(&entry("failed\r\n"))), "<131>1 box1 web 42 - - failed");
+
+        let long = "a".repeat(MAX_APP_NAME_LEN + 10);
+        let e = Entry { severity: 14, facility: Some(3), app_name: &long, proc_id: None, msg_id: Some("ID 7"), message: "" };
+        assert_eq!(fields(&f.format(&e)), format!("<30>1 box1 {} - ID7 - ", "a".repeat(MAX_APP_NAME_LEN)));
+        assert_eq!(header_field(Some(" \t"), 10), NIL);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut retry = backoff(None);
+        assert_eq!(retry.1, Duration::from_secs(RECONNECT_INITIAL_SECS));
+        for _ in 0..10 {
+            let next = backoff(Some(retry));
+            assert_eq!(next.1, (retry.1 * 2).min(Duration::from_secs(RECONNECT_MAX_SECS)));
+            retry = next;
+        }
+        assert_eq!(retry.1, Duration::from_secs(RECONNECT_MAX_SECS));
+    }
+
+    #[tokio::test]
+    async fn sends_over_udp() {
+        let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let cancel = CancellationToken::new();
+        let (f, task) = start(&forward(config::ForwardAddress::Udp(address), 4), cancel.clone());
+        let task = tokio::spawn(task);
+
+        f.send(&entry("one"));
+        let mut buf = [0u8; 512];
+        let n = timeout(Duration::from_secs(5), collector.recv(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(fields(&buf[..n]), "<131>1 box1 web 42 - - one");
+
+        cancel.cancel();
+        task.await.unwrap();
+        assert_eq!((f.counts().sent, f.counts().dropped, f.counts().failed), (1, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn frames_tcp_by_octet_count() {
+        let collector = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let cancel = CancellationToken::new();
+        let (f, task) = start(&forward(config::ForwardAddress::Tcp(address), 4), cancel.clone());
+
+        f.send(&entry("one"));
+        f.send(&entry("two"));
+        // the task first runs here, already cancelled, and still flushes the queue
+        cancel.cancel();
+        task.await;
+        assert_eq!((f.counts().sent, f.counts().failed), (2, 0));
+
+        let (mut stream, _) = collector.accept().await.unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).await.unwrap();
+        let (len, rest) = received.split_once(' ').unwrap();
+        let (first, second) = rest.split_at(len.parse().unwrap());
+        assert!(first.ends_with(" one"));
+        let (len, rest) = second.split_once(' ').unwrap();
+        assert_eq!(rest.len(), len.parse::<usize>().unwrap());
+        assert!(rest.ends_with(" two"));
+    }
+
+    #[tokio::test]
+    async fn drops_when_full_and_fails_without_collector() {
+        let unused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = unused.local_addr().unwrap().to_string();
+        drop(unused);
+        let cancel = CancellationToken::new();
+        let (f, task) = start(&forward(config::ForwardAddress::Tcp(address), 2), cancel.clone());
+
+        for m in ["one", "two", "three"] {
+            f.send(&entry(m));
+        }
+        cancel.cancel();
+        task.await;
+        // the first frame fails to connect, the second waits for the backoff
+        assert_eq!((f.counts().sent, f.counts().dropped, f.counts().failed), (0, 1, 2));
+    }
+}
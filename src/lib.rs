@@ -1 +1,3 @@
-pub mod init;
\ No newline at end of file
+pub mod client;
+pub mod init;
+pub mod protocol;
\ No newline at end of file
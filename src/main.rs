@@ -1,13 +1,22 @@
+mod client;
 mod init;
+mod protocol;
 
+use std::env;
 use std::error;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn error::Error>> {
-    if let Err(e) = init::start().await {
-        eprintln!("{}", e);
-        return Err(e.into());
+async fn main() -> Result<ExitCode, Box<dyn error::Error>> {
+    if env::args().nth(1).as_deref() == Some("health") {
+        return Ok(client::check_health(&client::default_socket()));
     }
 
-    Ok(())
+    match init::start(env::args().skip(1).collect()).await {
+        Ok(code) => Ok(ExitCode::from(code)),
+        Err(e) => {
+            log::error!("{}", e);
+            Err(e.into())
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,208 @@
+//! Wire format of the control socket. Every request and response is a single
+//! line of JSON. Requests carry the protocol version they were written for;
+//! the daemon refuses versions it does not know.
+
+use serde::{Deserialize, Serialize};
+
+pub const VERSION: u32 = 1;
+pub const DEFAULT_SOCKET: &str = "/run/wingmate.sock";
+pub const SOCKET_ENV: &str = "WINGMATE_CONTROL_SOCKET";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+
+    #[serde(flatten)]
+    pub operation: Operation,
+}
+
+/// For cron entries `start` and `stop` enable and disable the schedule, and
+/// `run` triggers the command right away. `signal` reaches every running
+/// instance of a cron command. A `logs` request with `follow` set keeps the
+/// connection open and sends one response per new line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Status,
+    Start { target: Target },
+    Stop { target: Target },
+    Restart { target: Target },
+    Signal { target: Target, signal: String },
+    Run { target: Target },
+    Logs {
+        target: Target,
+        #[serde(default)]
+        follow: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        lines: Option<usize>,
+    },
+    Health,
+    Reload {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<Target>,
+    },
+    Shutdown {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_code: Option<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Service(String),
+    Cron(String),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    pub ok: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Box<Status>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<Box<Health>>,
+}
+
+/// Healthy means every required service is running and no critical cron
+/// entry failed its latest runs. `problems` says what is wrong otherwise.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Health {
+    pub healthy: bool,
+
+    #[serde(default)]
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Status {
+    pub services: Vec<ServiceStatus>,
+    pub cron: Vec<CronStatus>,
+
+    #[serde(default)]
+    pub timers: Vec<TimerStatus>,
+
+    /// Processes reaped by wingmate that were not started by it.
+    #[serde(default)]
+    pub reaped_orphans: u64,
+
+    /// Only present while forwarding to a syslog collector.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forwarding: Option<ForwardingStatus>,
+}
+
+/// Messages queued for the syslog collector: sent, dropped because the queue
+/// was full, or failed because the collector could not be reached.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ForwardingStatus {
+    pub sent: u64,
+    pub dropped: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub state: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+
+    /// RFC 3339, only while the process is running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+
+    /// Restarts after an unexpected exit.
+    #[serde(default)]
+    pub restarts: u32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_exit_code: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_signal: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CronStatus {
+    pub name: String,
+    pub schedule: String,
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub running: Vec<u32>,
+
+    /// RFC 3339, in the time zone cron entries are matched in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<String>,
+
+    /// The latest completed runs, oldest first.
+    #[serde(default)]
+    pub last_runs: Vec<CronRun>,
+
+    #[serde(default)]
+    pub runs_total: u64,
+
+    #[serde(default)]
+    pub failures_total: u64,
+
+    #[serde(default)]
+    pub duration_ms_total: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<String>,
+}
+
+/// A timer and the service it starts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimerStatus {
+    pub name: String,
+    pub service: String,
+    pub schedule: String,
+
+    /// RFC 3339, in the time zone cron entries are matched in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_elapse: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_trigger: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CronRun {
+    pub started_at: String,
+    pub duration_ms: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Self { version: VERSION, ok: true, ..Default::default() }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self { version: VERSION, ok: false, error: Some(message.into()), ..Default::default() }
+    }
+}